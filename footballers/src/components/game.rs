@@ -1,19 +1,41 @@
-use crate::components::utils;
-use crate::game::{FootballersGame, Game, HostGame, GAME_CANVAS_HEIGHT, GAME_CANVAS_WIDTH};
+use crate::components::{utils, Route};
+use crate::game::{
+    connection_config, ClientGame, CollisionRules, FootballersGame, GameLoop, GameSettings,
+    HostGame, PitchProfile, Score, GAME_CANVAS_HEIGHT, GAME_CANVAS_WIDTH, MAX_CHAT_LENGTH,
+    MAX_QUEUED_INPUTS,
+};
 use crate::js_interface;
-use crate::utils::global_window;
+use crate::utils::{global_window, session_link};
 use log::{error, info};
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::spawn_local;
+use wasm_peers::one_to_one::NetworkManager;
 use wasm_peers::{ConnectionType, SessionId};
-use web_sys::HtmlCanvasElement;
-use yew::{html, Component, Context, Html, NodeRef};
+use web_sys::{FocusEvent, HtmlDocument, HtmlInputElement, KeyboardEvent};
+use yew::{html, Component, Context, Html};
+use yew_router::prelude::Link;
+
+/// Time in milliseconds the last messages of an ended game get to reach the other player,
+/// before the connection closes.
+const LEAVE_DELAY_MS: i32 = 1000;
 
 #[derive(Serialize, Deserialize)]
 pub struct GameQuery {
-    pub session_id: SessionId,
+    /// Session to join, the signaling server creating one for the host when `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<SessionId>,
     pub is_host: bool,
+    /// See [GameSettings::input_batch_size], one tick when `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_batch: Option<usize>,
+    /// Team of the host, `"blue"` or red when `None`, see [GameSettings::host_red].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_team: Option<String>,
+    /// See [GameSettings::red_on_left], read as present or not.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub switch_ends: bool,
 }
 
 #[derive(Debug)]
@@ -22,27 +44,62 @@ pub struct GameInit {
     pub signaling_server: String,
     pub username: String,
     pub credential: String,
+    pub is_host: bool,
+    pub settings: GameSettings,
 }
 impl GameQuery {
-    pub(crate) fn new(session_id: SessionId, is_host: bool) -> Self {
+    pub(crate) fn new(session_id: Option<SessionId>, is_host: bool) -> Self {
         GameQuery {
             session_id,
             is_host,
+            input_batch: None,
+            host_team: None,
+            switch_ends: false,
         }
     }
+
+    /// The sides the host chose, red on the left by default.
+    pub(crate) fn with_sides(mut self, host_blue: bool, switch_ends: bool) -> Self {
+        self.host_team = host_blue.then(|| "blue".to_string());
+        self.switch_ends = switch_ends;
+        self
+    }
+
+    pub(crate) fn with_input_batch(mut self, input_batch: usize) -> Self {
+        self.input_batch = Some(input_batch);
+        self
+    }
 }
 
 pub enum GameMsg {
     CopyLink,
+    /// Ask the signaling server for the session of the host, see [NetworkManager::create_session].
+    CreateSession,
+    /// The network manager of the host, in the session the signaling server created.
+    SessionCreated(Result<NetworkManager, JsValue>),
     Init,
-    Tick,
+    SendChat,
+    /// The page is closing, leave the session rather than let the signaling server time it out.
+    Leave,
+    /// The game is over, show the final score and leave the session.
+    Ended,
 }
 
 pub(crate) struct GameComponent {
-    session_id: SessionId,
-    canvas: NodeRef,
-    game: Option<FootballersGame>,
-    tick_callback: Closure<dyn FnMut()>,
+    /// `None` until the signaling server created the session of the host
+    session_id: Option<SessionId>,
+    is_host: bool,
+    settings: GameSettings,
+    /// Stopped when the component is destroyed, by dropping it.
+    game: Option<GameLoop>,
+    /// Listener of the page closing, removed when the component is destroyed
+    unload_listener: Closure<dyn FnMut()>,
+    /// Link of the session shown for the player to copy, when the browser can't copy it
+    manual_link: Option<String>,
+    /// Set once the game ended, shown in place of the chat
+    final_score: Option<Score>,
+    /// Set when the session could not be created, shown in place of its id
+    session_error: Option<String>,
 }
 
 impl Component for GameComponent {
@@ -51,118 +108,338 @@ impl Component for GameComponent {
 
     fn create(ctx: &Context<Self>) -> Self {
         let query_params = utils::get_query_params();
-        let session_id = if let Some(session_string) = query_params.get("session_id") {
-            SessionId::new(session_string.parse().unwrap())
-        } else {
+        let session_id = query_params
+            .get("session_id")
+            .map(|session_string| session_string.parse().unwrap());
+        let is_host = query_params.get("is_host").as_deref() == Some("true");
+        if session_id.is_none() && !is_host {
             todo!("Handle no session strings")
+        }
+        let mut settings = if query_params.has("side_view") {
+            GameSettings::side_view()
+        } else {
+            GameSettings::default()
         };
-        let canvas = NodeRef::default();
-        let tick_callback = {
+        settings.host_red = query_params.get("host_team").as_deref() != Some("blue");
+        settings.red_on_left = !query_params.has("switch_ends");
+        if let Some(pitch) = query_params.get("pitch") {
+            settings.pitch = pitch.parse().unwrap_or_else(|error| {
+                error!("{error}");
+                PitchProfile::default()
+            });
+        }
+        if let Some(batch) = query_params.get("input_batch") {
+            match batch.parse() {
+                Ok(size) if (1..=MAX_QUEUED_INPUTS).contains(&size) => {
+                    settings.input_batch_size = size;
+                }
+                _ => error!(
+                    "invalid input_batch {batch:?}, it must be between 1 and {MAX_QUEUED_INPUTS}"
+                ),
+            }
+        }
+        if query_params.has("pass_through") {
+            settings.collisions = CollisionRules::players_pass_through();
+        } else if query_params.has("ghost_ball") {
+            settings.collisions = CollisionRules::ghost_ball();
+        }
+        let mut movement = settings.movement;
+        let mut arena = settings.arena;
+        let tunings = [
+            ("acceleration", &mut movement.acceleration),
+            ("top_speed", &mut movement.top_speed),
+            ("damping", &mut movement.damping),
+            ("pitch_width", &mut arena.pitch_width),
+            ("pitch_height", &mut arena.pitch_height),
+            ("goal_breadth", &mut arena.goal_breadth),
+            ("goal_depth", &mut arena.goal_depth),
+            ("player_radius", &mut arena.player_radius),
+            ("ball_radius", &mut arena.ball_radius),
+        ];
+        for (name, tuning) in tunings {
+            if let Some(value) = query_params.get(name) {
+                match value.parse() {
+                    Ok(value) => *tuning = value,
+                    Err(error) => error!("invalid {name} {value:?}: {error}"),
+                }
+            }
+        }
+        if movement.is_valid() {
+            settings.movement = movement;
+        } else {
+            error!("the players can't move with {movement:?}, playing with the default movement");
+        }
+        if arena.is_valid() {
+            settings.arena = arena;
+        } else {
+            error!("the bodies don't fit in the arena {arena:?}, playing in the default one");
+        }
+        let unload_listener = {
             let link = ctx.link().clone();
-            Closure::wrap(Box::new(move || link.send_message(GameMsg::Tick)) as Box<dyn FnMut()>)
+            Closure::wrap(Box::new(move || link.send_message(GameMsg::Leave)) as Box<dyn FnMut()>)
         };
-        ctx.link().send_message(GameMsg::Init);
+        if let Err(error) = global_window().add_event_listener_with_callback(
+            "beforeunload",
+            unload_listener.as_ref().unchecked_ref(),
+        ) {
+            error!("failed listening to the page closing: {error:?}");
+        }
+        if session_id.is_some() {
+            ctx.link().send_message(GameMsg::Init);
+        } else {
+            ctx.link().send_message(GameMsg::CreateSession);
+        }
         Self {
             session_id,
-            canvas,
+            is_host,
+            settings,
             game: None,
-            tick_callback,
+            unload_listener,
+            manual_link: None,
+            final_score: None,
+            session_error: None,
         }
     }
 
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
-            GameMsg::CopyLink => {
-                if let Err(e) = copy_link(&self.session_id) {
-                    error!("{e:?}");
+            GameMsg::CopyLink => match self.session_id.as_ref().map(session_link) {
+                Some(Ok(link)) if copy_to_clipboard(&link) => false,
+                Some(Ok(link)) => {
+                    self.manual_link = Some(link);
+                    true
+                }
+                Some(Err(error)) => {
+                    error!("{error:?}");
+                    false
                 }
+                None => false,
+            },
+            GameMsg::CreateSession => {
+                let created = NetworkManager::create_session(
+                    &js_interface::server(),
+                    connection_type(
+                        js_interface::turn_username(),
+                        js_interface::turn_credential(),
+                    ),
+                    connection_config(),
+                );
+                let link = ctx.link().clone();
+                spawn_local(
+                    async move { link.send_message(GameMsg::SessionCreated(created.await)) },
+                );
                 false
             }
+            GameMsg::SessionCreated(Ok(mini_server)) => {
+                info!(
+                    "the signaling server created the session {:?}",
+                    mini_server.session_id()
+                );
+                self.session_id = Some(mini_server.session_id());
+                self.start(ctx, Some(mini_server));
+                true
+            }
+            GameMsg::SessionCreated(Err(error)) => {
+                error!("failed to create a session: {error:?}");
+                self.session_error =
+                    Some("the signaling server could not create a session".to_string());
+                true
+            }
             GameMsg::Init => {
-                let init = GameInit {
-                    session_id: self.session_id,
-                    signaling_server: js_interface::server(),
-                    username: js_interface::turn_username(),
-                    credential: js_interface::turn_credential(),
-                };
-                info!("{init:?}");
-                self.game = Some(init_game(self.canvas.clone(), init));
-                ctx.link().send_message(GameMsg::Tick);
+                self.start(ctx, None);
                 false
             }
-            GameMsg::Tick => {
-                match self.game.as_mut() {
-                    Some(game) => {
-                        game.tick();
-                        if !game.ended() {
-                            if let Err(error) = global_window().request_animation_frame(
-                                self.tick_callback.as_ref().unchecked_ref(),
-                            ) {
-                                error!("Failed requesting next animation frame: {error:?}");
-                            }
-                        }
-                    }
-                    None => {
-                        error!("No initialized game object yet.");
-                    }
+            GameMsg::SendChat => {
+                let input = utils::get_input("chat-input");
+                if let Some(game) = &self.game {
+                    game.game().send_chat(&input.value());
                 }
+                input.set_value("");
+                false
+            }
+            GameMsg::Leave => {
+                self.leave();
                 false
             }
+            GameMsg::Ended => {
+                self.final_score = self.game.as_ref().map(|game| game.game().score());
+                // the message ending the game may still be on its way to the other player
+                let link = ctx.link().clone();
+                let leave = Closure::once_into_js(move || link.send_message(GameMsg::Leave));
+                if let Err(error) = global_window()
+                    .set_timeout_with_callback_and_timeout_and_arguments_0(
+                        leave.unchecked_ref(),
+                        LEAVE_DELAY_MS,
+                    )
+                {
+                    error!("failed delaying leaving the ended game: {error:?}");
+                    self.leave();
+                }
+                true
+            }
         }
     }
 
+    fn destroy(&mut self, _ctx: &Context<Self>) {
+        let _ = global_window().remove_event_listener_with_callback(
+            "beforeunload",
+            self.unload_listener.as_ref().unchecked_ref(),
+        );
+        self.leave();
+    }
+
     fn view(&self, ctx: &Context<Self>) -> Html {
         let width = GAME_CANVAS_WIDTH.to_string();
         let height = GAME_CANVAS_HEIGHT.to_string();
         let onclick = ctx.link().callback(|_| GameMsg::CopyLink);
+        let send_chat = ctx.link().callback(|_| GameMsg::SendChat);
+        let chat_keydown = ctx.link().batch_callback(|event: KeyboardEvent| {
+            (event.key() == "Enter").then(|| GameMsg::SendChat)
+        });
+        let session = match (&self.session_id, &self.session_error) {
+            (Some(session_id), _) => session_id.to_string(),
+            (None, Some(error)) => error.clone(),
+            (None, None) => "creating the session...".to_string(),
+        };
         html! {
             <div class="px-3">
-                <canvas id="canvas" { width } { height } ref={ self.canvas.clone() }></canvas>
+                // resized to the page, and to the pixel density of the screen, when the game draws
+                <canvas id="canvas" { width } { height }></canvas>
+                if let Some(score) = &self.final_score {
+                    <p class="lead">
+                        { format!("Game over, red {} - {} blue. ", score.red_score, score.blue_score) }
+                        <Link<Route> to={ Route::Home }>{ "Play again" }</Link<Route>>
+                    </p>
+                } else {
+                    <p class="lead">
+                        <input id="chat-input"
+                            placeholder={ "Say something to the other player" }
+                            maxlength={ MAX_CHAT_LENGTH.to_string() }
+                            onkeydown={ chat_keydown }
+                        />
+                        <button onclick={ send_chat }>{ "Send" }</button>
+                    </p>
+                }
                 <p class="lead">{ "Use WASD to move, SPACE to shoot the ball." }</p>
-                <p class="lead">{ "Session id:" } { &self.session_id }</p>
+                <p class="lead">{ "Session id: " } { session }</p>
                 <button id="game_link_button" { onclick }>{ "Copy shareable link" }</button>
+                if let Some(link) = &self.manual_link {
+                    <p class="lead">
+                        { "Copy this link to share the game: " }
+                        <input id="game_link" readonly=true value={ link.clone() } onfocus={ select_input } />
+                    </p>
+                }
             </div>
         }
     }
 }
 
-fn init_game(canvas_node: NodeRef, settings: GameInit) -> FootballersGame {
-    let context = {
-        let canvas = canvas_node
-            .cast::<HtmlCanvasElement>()
-            .expect("no canvas element on page yet");
-        canvas
-            .get_context("2d")
-            .unwrap()
-            .unwrap()
-            .dyn_into::<web_sys::CanvasRenderingContext2d>()
-            .unwrap()
-    };
-    context.set_text_align("center");
-    context.set_text_baseline("middle");
+impl GameComponent {
+    /// Run the game, the host in `mini_server` if the signaling server created its session.
+    fn start(&mut self, ctx: &Context<Self>, mini_server: Option<NetworkManager>) {
+        let session_id = match self.session_id {
+            Some(session_id) => session_id,
+            None => return,
+        };
+        let init = GameInit {
+            session_id,
+            signaling_server: js_interface::server(),
+            username: js_interface::turn_username(),
+            credential: js_interface::turn_credential(),
+            is_host: self.is_host,
+            settings: self.settings.clone(),
+        };
+        info!("{init:?}");
+        let link = ctx.link().clone();
+        let on_end = move || link.send_message(GameMsg::Ended);
+        self.game = Some(GameLoop::run(init_game(init, mini_server), on_end));
+    }
+
+    fn leave(&mut self) {
+        if let Some(game) = self.game.take() {
+            game.stop();
+            game.game().leave();
+        }
+    }
+}
+
+/// Connection through the STUN and TURN servers, which run on the host of the signaling server.
+fn connection_type(username: String, credential: String) -> ConnectionType {
+    ConnectionType::StunAndTurn {
+        stun_urls: Vec::new(),
+        turn_urls: Vec::new(),
+        username,
+        credential,
+    }
+}
 
-    let connection_type = ConnectionType::StunAndTurn {
-        username: settings.username.clone(),
-        credential: settings.credential.clone(),
+/// The game of the host uses `mini_server` when given, or joins the session of `settings`.
+fn init_game(settings: GameInit, mini_server: Option<NetworkManager>) -> FootballersGame {
+    let connection_type = connection_type(settings.username.clone(), settings.credential.clone());
+    let mut game: FootballersGame = if settings.is_host {
+        let mini_server = mini_server.unwrap_or_else(|| {
+            NetworkManager::with_config(
+                &settings.signaling_server,
+                settings.session_id,
+                connection_type,
+                connection_config(),
+            )
+            .expect("failed to create network manager")
+        });
+        Box::new(HostGame::new(mini_server, settings.settings))
+    } else {
+        Box::new(ClientGame::new(
+            settings.session_id,
+            connection_type,
+            &settings.signaling_server,
+            settings.settings,
+        ))
     };
-    let mut game = HostGame::new(
-        settings.session_id,
-        connection_type,
-        &settings.signaling_server,
-    );
     game.init();
     game
 }
 
-fn copy_link(session_id: &SessionId) -> Result<(), JsValue> {
-    let window = global_window();
-    let clipboard = window
-        .navigator()
-        .clipboard()
-        .ok_or_else(|| JsValue::from("acquiring clipboard failed"))?;
-    let location = window.location();
-    let origin = location.origin()?;
-    let pathname = location.pathname()?;
-    let _promise = clipboard.write_text(&format!("{origin}{pathname}?session_id={session_id}"));
-    Ok(())
+/// Whether `text` was copied, with the clipboard API or else by selecting it,
+/// as the clipboard API is missing on origins without HTTPS and in older browsers.
+fn copy_to_clipboard(text: &str) -> bool {
+    let clipboard = global_window().navigator().clipboard();
+    // undefined where the API is missing, whatever the binding says
+    if !clipboard.is_undefined() {
+        let _promise = clipboard.write_text(text);
+        return true;
+    }
+    copy_by_selection(text).unwrap_or_else(|error| {
+        error!("failed to copy {text:?}: {error:?}");
+        false
+    })
+}
+
+fn copy_by_selection(text: &str) -> Result<bool, JsValue> {
+    let document = global_window()
+        .document()
+        .ok_or_else(|| JsValue::from("document node is missing"))?;
+    let body = document
+        .body()
+        .ok_or_else(|| JsValue::from("document body is missing"))?;
+    let input = document
+        .create_element("input")?
+        .dyn_into::<HtmlInputElement>()?;
+    input.set_value(text);
+    // out of sight, but still selectable
+    input.style().set_property("position", "fixed")?;
+    input.style().set_property("opacity", "0")?;
+    body.append_child(&input)?;
+    input.select();
+    let copied = document.dyn_into::<HtmlDocument>()?.exec_command("copy");
+    body.remove_child(&input)?;
+    copied
+}
+
+fn select_input(event: FocusEvent) {
+    if let Some(input) = event
+        .target()
+        .and_then(|target| target.dyn_into::<HtmlInputElement>().ok())
+    {
+        input.select();
+    }
 }
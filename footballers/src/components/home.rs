@@ -1,6 +1,6 @@
 use crate::components::game::GameQuery;
 use crate::components::{utils, Route};
-use wasm_peers::get_random_session_id;
+use crate::game::MAX_QUEUED_INPUTS;
 use yew::prelude::*;
 use yew_router::prelude::*;
 
@@ -36,9 +36,11 @@ impl Component for Home {
         let start_as_host = {
             let history = history.clone();
             Callback::once(move |_| {
-                history
-                    .push_with_query(Route::Game, GameQuery::new(get_random_session_id(), true))
-                    .unwrap();
+                let query = GameQuery::new(None, true).with_sides(
+                    utils::get_input("host-blue").checked(),
+                    utils::get_input("switch-ends").checked(),
+                );
+                history.push_with_query(Route::Game, query).unwrap();
             })
         };
         let update_input = ctx.link().callback(|_| HomeMsg::UpdateInput);
@@ -46,9 +48,12 @@ impl Component for Home {
             let session_id = self.input.clone();
             Callback::once(move |_| {
                 if let Ok(session_id) = session_id.parse() {
-                    history
-                        .push_with_query(Route::Game, GameQuery::new(session_id, false))
-                        .unwrap();
+                    let mut query = GameQuery::new(Some(session_id), false);
+                    match utils::get_input("input-batch").value().parse() {
+                        Ok(1) | Err(_) => {}
+                        Ok(input_batch) => query = query.with_input_batch(input_batch),
+                    }
+                    history.push_with_query(Route::Game, query).unwrap();
                 }
             })
         };
@@ -68,6 +73,13 @@ impl Component for Home {
                         <p class="lead">{ "Goal of the game is for a team to score 3 points before the other team." }</p>
                         <p class="lead">{ "Use WASD to move, SPACE to shoot the ball." }</p>
                         <hr />
+                        <p class="lead">
+                            <input id="host-blue" type="checkbox" />
+                            <label for="host-blue">{ " Play in the blue team" }</label>
+                            <br />
+                            <input id="switch-ends" type="checkbox" />
+                            <label for="switch-ends">{ " Red team defends the right goal" }</label>
+                        </p>
                         <p class="lead">
                             <button onclick={ start_as_host } class="btn btn-lg btn-secondary fw-bold border-white bg-white">{ "Start game as host" }</button>
                         </p>
@@ -78,6 +90,10 @@ impl Component for Home {
                             oninput={ update_input }
                         />
                         </p>
+                        <p class="lead">
+                            <label for="input-batch">{ "Ticks of input sent together " }</label>
+                            <input id="input-batch" type="number" min="1" max={ MAX_QUEUED_INPUTS.to_string() } value="1" />
+                        </p>
                         <p class="lead">
                             <button onclick={ join_existing } class="btn btn-lg btn-secondary fw-bold border-white bg-white">{ "Join existing" }</button>
                         </p>
@@ -0,0 +1,369 @@
+use crate::game::constants::{LAYOUT_VERSION, PITCH_LINE_WIDTH, RESET_TIME};
+use crate::game::input::{local_player_input, InputBatcher, PlayerInput};
+use crate::game::latency::LatencyMeter;
+use crate::game::utils::{
+    celebration_opacity, sanitize_chat, ChatLog, Circle, DeltaDecoder, Edge, GameMessage, Message,
+    Score, StateOrder, CLIENT_ID,
+};
+use crate::game::{
+    connection_config, rendering, ArenaConfig, Game, GameSettings, GameState, Movement,
+    PitchProfile,
+};
+use crate::utils::now;
+use log::{debug, error, info, warn};
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_peers::one_to_one::NetworkManager;
+use wasm_peers::{ChannelConfig, ConnectionType, SessionId};
+use web_sys::CanvasRenderingContext2d;
+
+/// Peer joining a game hosted by the other one.
+/// It only sends its inputs and draws the state simulated by the host.
+pub struct ClientGame {
+    inner: Rc<RefCell<ClientGameInner>>,
+}
+
+impl ClientGame {
+    pub fn new(
+        session_id: SessionId,
+        connection_type: ConnectionType,
+        signaling_server_url: &str,
+        settings: GameSettings,
+    ) -> ClientGame {
+        ClientGame {
+            inner: Rc::new(RefCell::new(ClientGameInner::new(
+                session_id,
+                connection_type,
+                signaling_server_url,
+                settings,
+            ))),
+        }
+    }
+}
+
+impl Game for ClientGame {
+    fn init(&mut self) {
+        // the game starts once the host sent the pitch
+        let on_open_callback = || debug!("connected to the host");
+
+        let client_game = self.inner.clone();
+        let on_message_callback = move |message: GameMessage| match message {
+            GameMessage::State(message) => client_game.borrow_mut().receive_state(message),
+            GameMessage::Input(_) | GameMessage::InputBatch(_) => {
+                error!("client received inputs from the host");
+            }
+        };
+
+        let client_game = self.inner.clone();
+        self.inner
+            .borrow()
+            .mini_client
+            .on_disconnect(move || client_game.borrow_mut().host_left());
+
+        self.inner.borrow().draw();
+
+        self.inner
+            .borrow_mut()
+            .mini_client
+            .start(
+                ChannelConfig::default(),
+                on_open_callback,
+                on_message_callback,
+            )
+            .expect("network manager failed to start");
+    }
+
+    fn tick(&mut self) {
+        self.inner.borrow_mut().tick();
+    }
+
+    fn state(&self) -> GameState {
+        self.inner.borrow().state
+    }
+
+    fn score(&self) -> Score {
+        let score = &self.inner.borrow().score;
+        Score::new(score.red_score, score.blue_score)
+    }
+
+    fn send_chat(&mut self, text: &str) {
+        self.inner.borrow_mut().send_chat(text);
+    }
+
+    fn leave(&mut self) {
+        self.inner.borrow().mini_client.close();
+    }
+}
+
+pub struct ClientGameInner {
+    edges: Vec<Edge>,
+    goal_posts: Vec<Circle>,
+    players: Vec<Circle>,
+    ball: Option<Circle>,
+    pitch: PitchProfile,
+    /// Dimensions of the pitch, the ones of the host once the game started
+    arena: ArenaConfig,
+    /// Movement of the players, the one of the host once the game started
+    movement: Movement,
+    score: Score,
+    last_scorer: Option<bool>,
+    scored_timer: u32,
+    chat: ChatLog,
+
+    // required by networking crate
+    mini_client: NetworkManager,
+    state: GameState,
+    state_order: StateOrder,
+    deltas: DeltaDecoder,
+    input_batcher: InputBatcher,
+
+    /// Round trip time to the host
+    latency: LatencyMeter,
+
+    // drawing stuff
+    context: CanvasRenderingContext2d,
+    player_input: Rc<RefCell<PlayerInput>>,
+}
+
+impl ClientGameInner {
+    fn new(
+        session_id: SessionId,
+        connection_type: ConnectionType,
+        signaling_server_url: &str,
+        settings: GameSettings,
+    ) -> ClientGameInner {
+        let mini_client = NetworkManager::with_config(
+            signaling_server_url,
+            session_id,
+            connection_type,
+            connection_config(),
+        )
+        .expect("failed to create network manager");
+
+        ClientGameInner {
+            edges: Vec::new(),
+            goal_posts: Vec::new(),
+            players: Vec::new(),
+            ball: None,
+            pitch: PitchProfile::default(),
+            arena: settings.arena,
+            movement: settings.movement,
+            score: Score::new(0, 0),
+            last_scorer: None,
+            scored_timer: 0,
+            chat: ChatLog::default(),
+            mini_client,
+            state: GameState::WaitingForPeer,
+            state_order: StateOrder::default(),
+            deltas: DeltaDecoder::default(),
+            input_batcher: InputBatcher::new(settings.input_batch_size),
+            latency: LatencyMeter::default(),
+            context: rendering::canvas_context(),
+            player_input: local_player_input(),
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.state == GameState::Playing {
+            self.send_input();
+        }
+        self.scored_timer = self.scored_timer.saturating_sub(1);
+        self.latency.poll(&self.mini_client);
+        self.draw();
+    }
+
+    fn send_input(&mut self) {
+        let input = *self.player_input.borrow();
+        if let Some(batch) = self.input_batcher.push(input) {
+            self.send(GameMessage::InputBatch(batch));
+        }
+    }
+
+    fn send(&self, message: GameMessage) {
+        if let Err(error) = self.mini_client.send_message(&message) {
+            error!("failed to send a message to the host: {error}");
+        }
+    }
+
+    /// The data channel closed, hold the game as it is unless it is over.
+    fn host_left(&mut self) {
+        if self.state != GameState::Ended {
+            self.state = GameState::Paused;
+        }
+    }
+
+    fn send_chat(&mut self, text: &str) {
+        if let Some(text) = sanitize_chat(text) {
+            let message = Message::Chat {
+                from: CLIENT_ID,
+                text: text.clone(),
+            };
+            self.send(GameMessage::from(message));
+            self.chat.push(CLIENT_ID, text);
+        }
+    }
+
+    fn receive_state(&mut self, message: Message) {
+        if let Some(layout) = message.layout_mismatch() {
+            warn!(
+                "the host built the pitch with layout {layout} but this build has {LAYOUT_VERSION}, \
+                 the game will misbehave until both players run the same version"
+            );
+        }
+        match message {
+            Message::GameInit {
+                layout: _,
+                arena,
+                seed,
+                edges,
+                goal_posts,
+                players,
+                ball,
+                pitch,
+                movement,
+                score,
+            } => {
+                if arena != self.arena {
+                    info!("playing in the arena of the host: {arena:?}");
+                    self.arena = arena;
+                }
+                // only the host draws random numbers for now, the client would seed its own with it
+                debug!("the host draws random numbers from seed {seed:#x}");
+                self.edges = edges;
+                self.goal_posts = goal_posts;
+                self.players = players;
+                self.ball = Some(ball);
+                self.pitch = pitch;
+                if movement != self.movement {
+                    info!("playing with the movement of the host: {movement:?}");
+                    self.movement = movement;
+                }
+                self.score = score;
+                self.state = GameState::Playing;
+                self.send(GameMessage::from(Message::Ready));
+            }
+            Message::GameState {
+                sequence,
+                players,
+                ball,
+            } => {
+                self.deltas.keyframe(sequence, &players, &ball);
+                if !self.state_order.accept(sequence) {
+                    return;
+                }
+                self.players = players;
+                self.ball = Some(ball);
+            }
+            Message::GameDelta {
+                sequence,
+                keyframe,
+                players,
+                ball,
+            } => match self.deltas.apply(keyframe, &players, ball) {
+                Some((players, ball)) => {
+                    if self.state_order.accept(sequence) {
+                        self.players = players;
+                        self.ball = Some(ball);
+                    }
+                }
+                None => {
+                    if self.deltas.request(keyframe) {
+                        debug!("missed keyframe {keyframe}, asking the host for a new one");
+                        self.send(GameMessage::from(Message::KeyframeRequest));
+                    }
+                }
+            },
+            Message::GoalScored { red_scored, score } => {
+                self.last_scorer = Some(red_scored);
+                self.score = score;
+                self.scored_timer = RESET_TIME;
+            }
+            Message::GameEnded => {
+                self.state = GameState::Ended;
+            }
+            Message::Chat { from, text } => {
+                if let Some(text) = sanitize_chat(&text) {
+                    self.chat.push(from, text);
+                }
+            }
+            Message::Ready | Message::KeyframeRequest => {
+                error!("client received a message meant for the host");
+            }
+        }
+    }
+
+    fn draw(&self) {
+        let arena = &self.arena;
+        let stadium_width = arena.stadium_width() as f64;
+        let stadium_height = arena.stadium_height() as f64;
+        rendering::fit_canvas(&self.context, stadium_width, stadium_height);
+        rendering::draw_stadium(&self.context, stadium_width, stadium_height);
+        rendering::draw_pitch(
+            &self.context,
+            &self.edges,
+            self.pitch,
+            arena.left_line() as f64,
+            arena.right_line() as f64,
+            arena.top_line() as f64,
+            arena.bottom_line() as f64,
+            PITCH_LINE_WIDTH as f64,
+            stadium_width,
+            stadium_height,
+            arena.goal_breadth as f64,
+        );
+        rendering::draw_goals(&self.context, &self.goal_posts);
+        rendering::draw_score(
+            &self.context,
+            &self.score,
+            stadium_width,
+            arena.top_line() as f64,
+        );
+        rendering::draw_players(&self.context, &self.players);
+        if let Some(ball) = &self.ball {
+            rendering::draw_ball(&self.context, ball);
+        }
+        rendering::draw_latency(&self.context, self.latency.rtt_ms(), stadium_width);
+        rendering::draw_chat(
+            &self.context,
+            &self.chat,
+            arena.left_line() as f64,
+            arena.bottom_line() as f64,
+        );
+        if let Some(red_scored) = self.last_scorer {
+            rendering::draw_last_scorer(
+                &self.context,
+                red_scored,
+                stadium_width,
+                arena.top_line() as f64,
+            );
+            if self.scored_timer > 0 {
+                rendering::draw_goal_banner(
+                    &self.context,
+                    red_scored,
+                    celebration_opacity(self.scored_timer),
+                    stadium_width,
+                    stadium_height,
+                );
+            }
+        }
+        match self.state {
+            GameState::WaitingForPeer => rendering::draw_waiting_for_peer(
+                &self.context,
+                None,
+                now().unwrap_or_default(),
+                stadium_width,
+                stadium_height,
+            ),
+            GameState::Playing => {}
+            GameState::Paused => {
+                rendering::draw_paused(&self.context, stadium_width, stadium_height)
+            }
+            GameState::Ended => rendering::draw_game_ended(
+                &self.context,
+                &self.score,
+                stadium_width,
+                stadium_height,
+            ),
+        }
+    }
+}
@@ -2,28 +2,50 @@ pub const PLAYER_ACCELERATION: f32 = 2_000.0;
 pub const PLAYER_TOP_SPEED: f32 = 90.0;
 pub const BALL_TOP_SPEED: f32 = 200.0;
 
+pub const SIDE_VIEW_GRAVITY: f32 = 400.0;
+pub const PLAYER_DAMPING: f32 = 1.0;
+pub const BALL_DAMPING: f32 = 0.3;
+pub const PLAYER_AIR_DAMPING: f32 = 0.5;
+pub const BALL_AIR_DAMPING: f32 = 0.05;
+
+pub const ICE_CANDIDATE_POOL_SIZE: u8 = 4;
+/// ICE restarts attempted in a row, recovering players whose network changed mid-match.
+pub const ICE_RESTARTS: u8 = 3;
+/// Inputs of the client waiting to be applied by the host, beyond which the oldest are skipped.
+pub const MAX_QUEUED_INPUTS: usize = 8;
+/// Default time in milliseconds the host holds the inputs of the client, to smooth out network jitter.
+pub const INPUT_JITTER_DELAY_MS: f64 = 50.0;
+/// Default time in milliseconds without input after which the host drops the client.
+pub const IDLE_TIMEOUT_MS: f64 = 5000.0;
+/// Characters kept of a chat message, the rest is cut before sending it.
+pub const MAX_CHAT_LENGTH: usize = 120;
+/// Number of the latest chat messages displayed over the pitch.
+pub const CHAT_HISTORY: usize = 5;
+/// State updates between two keyframes, the ones in between only carrying the entities which moved.
+pub const KEYFRAME_INTERVAL: u32 = 30;
+/// Distance from its position in the keyframe beyond which an entity is sent in a delta.
+pub const DELTA_THRESHOLD: f32 = 0.5;
+/// Ticks after which the host sends the game layout again, until the client answers it is ready.
+pub const GAME_INIT_RESEND_TIME: u32 = 30;
+
 pub const RESET_TIME: u32 = 60 * 3;
 pub const MAX_GOALS: u32 = 3;
 
+// dimensions of the default arena, see ArenaConfig
 pub const PLAYER_DIAMETER: f32 = 30.0;
-pub const PLAYER_RADIUS: f32 = PLAYER_DIAMETER / 2.0;
 pub const BALL_RADIUS: f32 = 10.0;
-pub const SHOOTING_DISTANCE: f32 = PLAYER_RADIUS + BALL_RADIUS + BALL_RADIUS / 2.0;
 
 pub const GOAL_BREADTH: f32 = 120.0;
 pub const GOAL_DEPTH: f32 = 3.0 * BALL_RADIUS;
-pub const PITCH_VERTICAL_LINE_HEIGHT: f32 = (PITCH_HEIGHT - GOAL_BREADTH) / 2.0;
 
 pub const PITCH_WIDTH: f32 = 500.0;
 pub const PITCH_HEIGHT: f32 = 300.0;
 pub const PITCH_LINE_WIDTH: f32 = 3.0;
 pub const PITCH_LINE_HEIGHT: f32 = 3.0;
-pub const PITCH_LEFT_LINE: f32 = 0.0 + 2.0 * PLAYER_DIAMETER;
-pub const PITCH_RIGHT_LINE: f32 = PITCH_LEFT_LINE + PITCH_WIDTH;
-pub const PITCH_TOP_LINE: f32 = 0.0 + PLAYER_DIAMETER;
-pub const PITCH_BOTTOM_LINE: f32 = PITCH_TOP_LINE + PITCH_HEIGHT;
-pub const STADIUM_WIDTH: f32 = 2.0 * PLAYER_DIAMETER + PITCH_WIDTH + 2.0 * PLAYER_DIAMETER;
-pub const STADIUM_HEIGHT: f32 = 2.0 * PLAYER_DIAMETER + PITCH_HEIGHT;
+
+/// Version of the pitch layout, to bump when it changes in a way the ArenaConfig doesn't show,
+/// like the placement of the players at kick-off.
+pub const LAYOUT_VERSION: u32 = 2;
 
 pub const PITCH_LINES_GROUP: u32 = 0b_0000_0001;
 pub const GOAL_POSTS_GROUP: u32 = 0b_0000_0010;
@@ -0,0 +1,93 @@
+use crate::game::FootballersGame;
+use crate::utils::global_window;
+use log::error;
+use std::cell::{Cell, RefCell, RefMut};
+use std::rc::{Rc, Weak};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+
+/// Ticks a game on every animation frame, until it ends or the loop is stopped.
+///
+/// Dropping the loop stops it, so that no frame is left calling into a game that is gone.
+/// The owner is told when the game ends, to tear it down.
+pub struct GameLoop {
+    state: Rc<LoopState>,
+}
+
+struct LoopState {
+    game: RefCell<FootballersGame>,
+    /// Handle of the requested animation frame, to cancel it when stopping.
+    frame: Cell<Option<i32>>,
+    stopped: Cell<bool>,
+    /// Holds a weak reference to the state, which owns it.
+    on_frame: RefCell<Option<Closure<dyn FnMut()>>>,
+    /// Taken when the game ends, it only runs once.
+    on_end: Cell<Option<Box<dyn FnOnce()>>>,
+}
+
+impl GameLoop {
+    /// Tick the initialized `game` now, and then on every animation frame,
+    /// running `on_end` after the tick in which it ends.
+    pub fn run(game: FootballersGame, on_end: impl FnOnce() + 'static) -> Self {
+        let state = Rc::new(LoopState {
+            game: RefCell::new(game),
+            frame: Cell::new(None),
+            stopped: Cell::new(false),
+            on_frame: RefCell::new(None),
+            on_end: Cell::new(Some(Box::new(on_end))),
+        });
+        let weak_state: Weak<LoopState> = Rc::downgrade(&state);
+        let on_frame = Closure::wrap(Box::new(move || {
+            if let Some(state) = weak_state.upgrade() {
+                state.frame.set(None);
+                state.tick();
+            }
+        }) as Box<dyn FnMut()>);
+        *state.on_frame.borrow_mut() = Some(on_frame);
+        state.tick();
+        GameLoop { state }
+    }
+
+    pub fn game(&self) -> RefMut<'_, FootballersGame> {
+        self.state.game.borrow_mut()
+    }
+
+    /// Cancel the next tick, the game staying as it is.
+    pub fn stop(&self) {
+        self.state.stopped.set(true);
+        if let Some(frame) = self.state.frame.take() {
+            if let Err(error) = global_window().cancel_animation_frame(frame) {
+                error!("failed cancelling the animation frame: {error:?}");
+            }
+        }
+    }
+}
+
+impl Drop for GameLoop {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl LoopState {
+    fn tick(&self) {
+        let mut game = self.game.borrow_mut();
+        game.tick();
+        if game.ended() {
+            drop(game);
+            if let Some(on_end) = self.on_end.take() {
+                on_end();
+            }
+            return;
+        }
+        if self.stopped.get() {
+            return;
+        }
+        if let Some(on_frame) = self.on_frame.borrow().as_ref() {
+            match global_window().request_animation_frame(on_frame.as_ref().unchecked_ref()) {
+                Ok(frame) => self.frame.set(Some(frame)),
+                Err(error) => error!("failed requesting the next animation frame: {error:?}"),
+            }
+        }
+    }
+}
@@ -1,28 +1,29 @@
 use crate::game::constants::{
-    BALL_GROUP, BALL_RADIUS, BALL_TOP_SPEED, GOAL_BREADTH, GOAL_DEPTH, GOAL_POSTS_GROUP, MAX_GOALS,
-    PITCH_BOTTOM_LINE, PITCH_HEIGHT, PITCH_LEFT_LINE, PITCH_LINES_GROUP, PITCH_LINE_HEIGHT,
-    PITCH_LINE_WIDTH, PITCH_RIGHT_LINE, PITCH_TOP_LINE, PITCH_VERTICAL_LINE_HEIGHT, PITCH_WIDTH,
-    PLAYERS_GROUP, PLAYER_ACCELERATION, PLAYER_DIAMETER, PLAYER_RADIUS, PLAYER_TOP_SPEED,
-    RESET_TIME, SHOOTING_DISTANCE, STADIUM_HEIGHT, STADIUM_WALLS_GROUP, STADIUM_WIDTH,
+    BALL_TOP_SPEED, GAME_INIT_RESEND_TIME, LAYOUT_VERSION, MAX_GOALS, MAX_QUEUED_INPUTS,
+    PITCH_LINE_HEIGHT, PITCH_LINE_WIDTH, RESET_TIME,
 };
-use crate::game::input::{local_player_input, PlayerInput};
-use crate::game::utils::{Arbiter, Circle, Edge, Message, Player, Score};
-use crate::game::{rendering, Game};
-use crate::utils::global_window;
-use rapier2d::dynamics::{
-    CCDSolver, IntegrationParameters, IslandManager, JointSet, RigidBody, RigidBodyBuilder,
-    RigidBodyHandle, RigidBodySet,
+use crate::game::input::{
+    debug_overlay_toggle, local_player_input, IdleTimer, InputQueue, PlayerInput,
+};
+use crate::game::latency::LatencyMeter;
+use crate::game::utils::{
+    celebration_opacity, limit_speed, sanitize_chat, spawn_position, Arbiter, ChatLog, Circle,
+    DeltaEncoder, Edge, GameMessage, GameRng, Message, Player, Score, CLIENT_ID, HOST_ID,
 };
-use rapier2d::geometry::{
-    BroadPhase, ColliderBuilder, ColliderSet, InteractionGroups, NarrowPhase,
+use crate::game::{rendering, ArenaConfig, CollisionRules, Game, GameSettings, GameState};
+use crate::utils::{now, session_link};
+use log::{debug, error};
+use rapier2d::dynamics::{
+    CCDSolver, IntegrationParameters, IslandManager, JointSet, RigidBodyBuilder, RigidBodyHandle,
+    RigidBodySet,
 };
+use rapier2d::geometry::{BroadPhase, ColliderBuilder, ColliderSet, NarrowPhase};
 use rapier2d::pipeline::PhysicsPipeline;
 use rapier2d::prelude::*;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
-use wasm_bindgen::JsCast;
 use wasm_peers::one_to_one::NetworkManager;
-use wasm_peers::{ConnectionType, SessionId};
+use wasm_peers::{ChannelConfig, Priority, UserId};
 use web_sys::CanvasRenderingContext2d;
 
 pub struct HostGame {
@@ -30,63 +31,41 @@ pub struct HostGame {
 }
 
 impl HostGame {
-    pub fn new(
-        session_id: SessionId,
-        connection_type: ConnectionType,
-        signaling_server_url: &str,
-    ) -> HostGame {
+    /// The game of the host, playing with the other peer of the session of `mini_server`.
+    pub fn new(mini_server: NetworkManager, settings: GameSettings) -> HostGame {
         HostGame {
-            inner: Rc::new(RefCell::new(HostGameInner::new(
-                session_id,
-                connection_type,
-                signaling_server_url,
-            ))),
+            inner: Rc::new(RefCell::new(HostGameInner::new(mini_server, settings))),
         }
     }
 }
 
 impl Game for HostGame {
     fn init(&mut self) {
-        let host_player = self.inner.borrow_mut().create_player(
-            PITCH_LEFT_LINE + 2.0 * PLAYER_DIAMETER,
-            STADIUM_HEIGHT / 2.0,
-            true,
-            1,
-        );
+        let host_red = self.inner.borrow().settings.host_red;
+        let (x, y) = {
+            let inner = self.inner.borrow();
+            spawn_position(&inner.settings.arena, inner.arbiter.defends_left(host_red))
+        };
+        let host_player = self.inner.borrow_mut().create_player(x, y, host_red, 1);
         self.inner.borrow_mut().host_player = Some(host_player);
 
         let host_game = self.inner.clone();
         let on_open_callback = move || {
-            let game_state = Message::GameInit {
-                edges: host_game.borrow().get_edge_entities(),
-                goal_posts: host_game.borrow().get_goal_posts_entities(),
-                players: host_game.borrow().get_player_entities(),
-                ball: host_game.borrow().get_ball_entity(),
-            };
-            let _ = host_game.borrow().mini_server.send_message(&game_state);
-            host_game.borrow_mut().game_started = true;
-
-            host_game.borrow_mut().oppo = Some(host_game.borrow_mut().create_player(
-                PITCH_RIGHT_LINE - 2.0 * PLAYER_DIAMETER,
-                STADIUM_HEIGHT / 2.0,
-                false,
-                1,
-            ));
+            host_game.borrow_mut().send_game_init();
         };
 
         let host_game = self.inner.clone();
-        let on_message_callback = move |input: PlayerInput| {
-            if let Some(oppo) = &mut host_game.borrow_mut().oppo {
-                oppo.set_input(input);
-            }
-        };
+        self.inner
+            .borrow()
+            .mini_server
+            .on_disconnect(move || host_game.borrow_mut().oppo_left());
 
         self.inner.borrow().draw();
 
         self.inner
             .borrow_mut()
             .mini_server
-            .start(10, on_open_callback, on_message_callback)
+            .start_polling::<GameMessage>(ChannelConfig::default(), on_open_callback)
             .expect("network manager failed to start");
     }
 
@@ -94,22 +73,42 @@ impl Game for HostGame {
         self.inner.borrow_mut().tick();
     }
 
-    fn ended(&self) -> bool {
-        self.inner.borrow().get_game_ended()
+    fn state(&self) -> GameState {
+        self.inner.borrow().state
+    }
+
+    fn score(&self) -> Score {
+        self.inner.borrow().get_score()
+    }
+
+    fn send_chat(&mut self, text: &str) {
+        self.inner.borrow_mut().chat(HOST_ID, text);
+    }
+
+    fn leave(&mut self) {
+        self.inner.borrow().mini_server.close();
     }
 }
 
 pub struct HostGameInner {
     host_player: Option<Player>,
     oppo: Option<Player>,
+    oppo_inputs: InputQueue,
+    oppo_idle: IdleTimer,
     edges: Vec<Edge>,
     goal_posts: Vec<Circle>,
     ball_body_handle: RigidBodyHandle,
     arbiter: Arbiter,
+    settings: GameSettings,
+    chat: ChatLog,
 
     // required by networking crate
     mini_server: NetworkManager,
-    game_started: bool,
+    state: GameState,
+    /// Ticks before sending [Message::GameInit] again, while waiting for [Message::Ready]
+    game_init_resend: Option<u32>,
+    state_sequence: u32,
+    deltas: DeltaEncoder,
 
     // stuff required by physics engine
     rigid_body_set: RigidBodySet,
@@ -126,53 +125,63 @@ pub struct HostGameInner {
     // drawing stuff
     context: CanvasRenderingContext2d,
     player_input: Rc<RefCell<PlayerInput>>,
+    session_link: Option<String>,
+    latency: LatencyMeter,
+    // debug overlay stuff
+    debug_overlay: Rc<Cell<bool>>,
+    last_tick_time: Option<f64>,
+    fps: f64,
+    last_message: Option<&'static str>,
 }
 
 impl HostGameInner {
-    pub(self) fn new(
-        session_id: SessionId,
-        connection_type: ConnectionType,
-        signaling_server_url: &str,
-    ) -> HostGameInner {
-        let mini_server = NetworkManager::new(signaling_server_url, session_id, connection_type)
-            .expect("failed to create network manager");
+    pub(self) fn new(mini_server: NetworkManager, settings: GameSettings) -> HostGameInner {
+        let session_id = mini_server.session_id();
 
         let mut rigid_body_set = RigidBodySet::new();
         let mut collider_set = ColliderSet::new();
 
-        let edges = HostGameInner::create_pitch_lines(&mut collider_set);
-        let goal_posts = HostGameInner::create_goals_posts(&mut collider_set);
-        HostGameInner::create_stadium_walls(&mut collider_set);
-
-        let ball_body_handle = HostGameInner::create_ball(&mut rigid_body_set, &mut collider_set);
-
-        let document = global_window().document().unwrap();
-        let context = {
-            let canvas = document.get_element_by_id("canvas").unwrap();
-            let canvas: web_sys::HtmlCanvasElement = canvas
-                .dyn_into::<web_sys::HtmlCanvasElement>()
-                .map_err(|_| ())
-                .unwrap();
-
-            canvas
-                .get_context("2d")
-                .unwrap()
-                .unwrap()
-                .dyn_into::<web_sys::CanvasRenderingContext2d>()
-                .unwrap()
+        let edges = HostGameInner::create_pitch_lines(
+            &mut collider_set,
+            &settings.arena,
+            &settings.collisions,
+        );
+        let arbiter = Arbiter {
+            red_on_left: settings.red_on_left,
+            arena: settings.arena,
+            rng: GameRng::for_session(session_id),
+            ..Arbiter::new()
         };
-        context.set_text_align("center");
-        context.set_text_baseline("middle");
+        let goal_posts =
+            HostGameInner::create_goals_posts(&mut collider_set, &arbiter, &settings.collisions);
+        HostGameInner::create_stadium_walls(
+            &mut collider_set,
+            &settings.arena,
+            &settings.collisions,
+        );
+
+        let ball_body_handle =
+            HostGameInner::create_ball(&mut rigid_body_set, &mut collider_set, &settings);
+
+        let context = rendering::canvas_context();
+        let session_link = session_link(&session_id).ok();
 
         HostGameInner {
             mini_server,
-            game_started: false,
+            state: GameState::WaitingForPeer,
+            game_init_resend: None,
+            state_sequence: 0,
+            deltas: DeltaEncoder::default(),
             host_player: None,
             oppo: None,
+            oppo_inputs: InputQueue::new(MAX_QUEUED_INPUTS, settings.input_jitter_delay_ms),
+            oppo_idle: IdleTimer::new(settings.idle_timeout_ms),
             edges,
             goal_posts,
             ball_body_handle,
-            arbiter: Arbiter::new(),
+            arbiter,
+            settings,
+            chat: ChatLog::default(),
             rigid_body_set,
             collider_set,
             integration_parameters: IntegrationParameters::default(),
@@ -186,24 +195,72 @@ impl HostGameInner {
             event_handler: (),
             context,
             player_input: local_player_input(),
+            session_link,
+            latency: LatencyMeter::default(),
+            debug_overlay: debug_overlay_toggle(),
+            last_tick_time: None,
+            fps: 0.0,
+            last_message: None,
         }
     }
 
     pub(self) fn tick(&mut self) {
+        self.measure_fps();
+        self.latency.poll(&self.mini_server);
+        // handled here rather than as they arrive, so that the whole tick sees the same inputs
+        for message in self.mini_server.drain_received() {
+            self.receive(message);
+        }
+        self.check_game_init_resend();
+        self.check_idle_oppo();
+        if self.state == GameState::Playing {
+            self.simulate();
+        }
+        self.draw();
+    }
+
+    fn receive(&mut self, message: GameMessage) {
+        match message {
+            GameMessage::Input(input) => {
+                self.last_message = Some("PlayerInput");
+                self.input_received();
+                if let Some(oppo) = &mut self.oppo {
+                    oppo.set_input(input);
+                }
+            }
+            GameMessage::InputBatch(batch) => {
+                self.last_message = Some("InputBatch");
+                self.input_received();
+                self.oppo_inputs.receive(batch, now().unwrap_or_default());
+            }
+            GameMessage::State(Message::Chat { text, .. }) => self.chat(CLIENT_ID, &text),
+            GameMessage::State(Message::Ready) => self.oppo_ready(),
+            GameMessage::State(Message::KeyframeRequest) => self.deltas.request_keyframe(),
+            GameMessage::State(_) => {
+                error!("host received a game state from the other player");
+            }
+        }
+    }
+
+    fn simulate(&mut self) {
         self.check_timer();
         self.host_player
             .as_mut()
             .unwrap()
             .set_input(*self.player_input.borrow());
+        let input = self.oppo_inputs.next(now().unwrap_or_default());
+        if let (Some(oppo), Some(input)) = (&mut self.oppo, input) {
+            oppo.set_input(input);
+        }
         self.advance_physic_tick();
 
-        HostGameInner::limit_speed(
+        limit_speed(
             &mut self.rigid_body_set[self.ball_body_handle],
             BALL_TOP_SPEED,
         );
 
         self.physics_pipeline.step(
-            &vector![0.0, 0.0],
+            &self.settings.gravity,
             &self.integration_parameters,
             &mut self.island_manager,
             &mut self.broad_phase,
@@ -217,14 +274,126 @@ impl HostGameInner {
         );
 
         self.host_send_state();
-        self.draw();
     }
 
-    fn create_pitch_lines(collider_set: &mut ColliderSet) -> Vec<Edge> {
+    /// Send the layout of the pitch, and keep sending it until the client is ready.
+    /// Also sent each time the data channel opens again, for a client joining back to catch up.
+    fn send_game_init(&mut self) {
+        let game_init = Message::GameInit {
+            layout: LAYOUT_VERSION,
+            arena: self.settings.arena,
+            seed: self.arbiter.rng.seed(),
+            edges: self.get_edge_entities(),
+            goal_posts: self.get_goal_posts_entities(),
+            players: self.get_player_entities(),
+            ball: self.get_ball_entity(),
+            pitch: self.settings.pitch,
+            movement: self.settings.movement,
+            score: self.get_score(),
+        };
+        self.send(GameMessage::from(game_init), Priority::Normal);
+        self.game_init_resend = Some(GAME_INIT_RESEND_TIME);
+    }
+
+    fn check_game_init_resend(&mut self) {
+        match self.game_init_resend {
+            Some(0) => self.send_game_init(),
+            Some(ticks) => self.game_init_resend = Some(ticks - 1),
+            None => {}
+        }
+    }
+
+    /// The client applied the layout, start the match with its player,
+    /// or resume it with the full state when the client joined back.
+    fn oppo_ready(&mut self) {
+        // repeated layouts are all answered, only the first answer counts
+        if self.game_init_resend.take().is_none() {
+            return;
+        }
+        self.last_message = Some("Ready");
+        if let Some(now) = now() {
+            self.oppo_idle.reset(now);
+        }
+        if self.oppo.is_some() {
+            debug!("the other player joined back, resuming the game");
+            self.deltas.request_keyframe();
+            // a fresh page numbers its inputs from 0 again, they would all look stale
+            self.oppo_inputs =
+                InputQueue::new(MAX_QUEUED_INPUTS, self.settings.input_jitter_delay_ms);
+            match self.state {
+                GameState::Ended => {
+                    self.send(GameMessage::from(Message::GameEnded), Priority::High)
+                }
+                _ => self.state = GameState::Playing,
+            }
+            return;
+        }
+        self.state = GameState::Playing;
+
+        let oppo_red = !self.settings.host_red;
+        let (x, y) = spawn_position(&self.settings.arena, self.arbiter.defends_left(oppo_red));
+        self.oppo = Some(self.create_player(x, y, oppo_red, 2));
+    }
+
+    /// The data channel closed, hold the game as it is unless it is over.
+    fn oppo_left(&mut self) {
+        if self.state != GameState::Ended {
+            self.state = GameState::Paused;
+        }
+    }
+
+    fn input_received(&mut self) {
+        if let Some(now) = now() {
+            self.oppo_idle.reset(now);
+        }
+    }
+
+    /// Drop the opponent once it stopped sending inputs, closing the connection with it.
+    fn check_idle_oppo(&mut self) {
+        let timed_out = match now() {
+            Some(now) => self.oppo_idle.timed_out(now),
+            None => false,
+        };
+        if !timed_out {
+            return;
+        }
+        if let Some(oppo) = self.oppo.take() {
+            error!("no input from the other player for too long, dropping it");
+            self.rigid_body_set.remove(
+                oppo.rigid_body_handle,
+                &mut self.island_manager,
+                &mut self.collider_set,
+                &mut self.joint_set,
+            );
+            self.state = GameState::Paused;
+            self.mini_server.close();
+        }
+    }
+
+    fn measure_fps(&mut self) {
+        const SMOOTHING: f64 = 0.9;
+        let now = match now() {
+            Some(now) => now,
+            None => return,
+        };
+        if let Some(last_tick_time) = self.last_tick_time {
+            let elapsed = now - last_tick_time;
+            if elapsed > 0.0 {
+                self.fps = SMOOTHING * self.fps + (1.0 - SMOOTHING) * 1000.0 / elapsed;
+            }
+        }
+        self.last_tick_time = Some(now);
+    }
+
+    fn create_pitch_lines(
+        collider_set: &mut ColliderSet,
+        arena: &ArenaConfig,
+        collisions: &CollisionRules,
+    ) -> Vec<Edge> {
         let mut edges = Vec::new();
-        let mut create_line_closure = |width, height, x, y, white, membership, filter| {
+        let mut create_line_closure = |width, height, x, y, white| {
             let cuboid_collider = ColliderBuilder::cuboid(width / 2.0, height / 2.0)
-                .collision_groups(InteractionGroups::new(membership, filter))
+                .collision_groups(collisions.pitch_lines())
                 .translation(vector![x, y])
                 .build();
             edges.push(Edge::new(
@@ -236,209 +405,198 @@ impl HostGameInner {
             ));
             collider_set.insert(cuboid_collider);
         };
+        let stadium_width = arena.stadium_width();
+        let stadium_height = arena.stadium_height();
+        let vertical_line_height = arena.vertical_line_height();
+        let goal_breadth = arena.goal_breadth;
+        let goal_depth = arena.goal_depth;
 
         // left higher pitch line
         create_line_closure(
             PITCH_LINE_WIDTH,
-            PITCH_VERTICAL_LINE_HEIGHT,
-            PITCH_LEFT_LINE,
-            (STADIUM_HEIGHT - GOAL_BREADTH - PITCH_VERTICAL_LINE_HEIGHT) / 2.0,
+            vertical_line_height,
+            arena.left_line(),
+            (stadium_height - goal_breadth - vertical_line_height) / 2.0,
             true,
-            PITCH_LINES_GROUP,
-            PITCH_LINES_GROUP,
         );
         // left lower pitch line
         create_line_closure(
             PITCH_LINE_WIDTH,
-            PITCH_VERTICAL_LINE_HEIGHT,
-            PITCH_LEFT_LINE,
-            (STADIUM_HEIGHT + GOAL_BREADTH + PITCH_VERTICAL_LINE_HEIGHT) / 2.0,
+            vertical_line_height,
+            arena.left_line(),
+            (stadium_height + goal_breadth + vertical_line_height) / 2.0,
             true,
-            PITCH_LINES_GROUP,
-            PITCH_LINES_GROUP,
         );
         // left goal
         create_line_closure(
             PITCH_LINE_WIDTH,
-            GOAL_BREADTH,
-            PITCH_LEFT_LINE - GOAL_DEPTH,
-            STADIUM_HEIGHT / 2.0,
+            goal_breadth,
+            arena.left_line() - goal_depth,
+            stadium_height / 2.0,
             false,
-            PITCH_LINES_GROUP,
-            PITCH_LINES_GROUP,
         );
         create_line_closure(
-            GOAL_DEPTH,
+            goal_depth,
             PITCH_LINE_HEIGHT,
-            PITCH_LEFT_LINE - GOAL_DEPTH / 2.0,
-            (STADIUM_HEIGHT - GOAL_BREADTH) / 2.0,
+            arena.left_line() - goal_depth / 2.0,
+            (stadium_height - goal_breadth) / 2.0,
             false,
-            PITCH_LINES_GROUP,
-            PITCH_LINES_GROUP,
         );
         create_line_closure(
-            GOAL_DEPTH,
+            goal_depth,
             PITCH_LINE_HEIGHT,
-            PITCH_LEFT_LINE - GOAL_DEPTH / 2.0,
-            (STADIUM_HEIGHT + GOAL_BREADTH) / 2.0,
+            arena.left_line() - goal_depth / 2.0,
+            (stadium_height + goal_breadth) / 2.0,
             false,
-            PITCH_LINES_GROUP,
-            PITCH_LINES_GROUP,
         );
 
         // right higher pitch line
         create_line_closure(
             PITCH_LINE_WIDTH,
-            PITCH_VERTICAL_LINE_HEIGHT,
-            PITCH_RIGHT_LINE,
-            (STADIUM_HEIGHT - GOAL_BREADTH - PITCH_VERTICAL_LINE_HEIGHT) / 2.0,
+            vertical_line_height,
+            arena.right_line(),
+            (stadium_height - goal_breadth - vertical_line_height) / 2.0,
             true,
-            PITCH_LINES_GROUP,
-            PITCH_LINES_GROUP,
         );
         // right lower pitch line
         create_line_closure(
             PITCH_LINE_WIDTH,
-            PITCH_VERTICAL_LINE_HEIGHT,
-            PITCH_RIGHT_LINE,
-            (STADIUM_HEIGHT + GOAL_BREADTH + PITCH_VERTICAL_LINE_HEIGHT) / 2.0,
+            vertical_line_height,
+            arena.right_line(),
+            (stadium_height + goal_breadth + vertical_line_height) / 2.0,
             true,
-            PITCH_LINES_GROUP,
-            PITCH_LINES_GROUP,
         );
         // right goal
         create_line_closure(
             PITCH_LINE_WIDTH,
-            GOAL_BREADTH,
-            PITCH_RIGHT_LINE + GOAL_DEPTH,
-            STADIUM_HEIGHT / 2.0,
+            goal_breadth,
+            arena.right_line() + goal_depth,
+            stadium_height / 2.0,
             false,
-            PITCH_LINES_GROUP,
-            PITCH_LINES_GROUP,
         );
         create_line_closure(
-            GOAL_DEPTH,
+            goal_depth,
             PITCH_LINE_HEIGHT,
-            PITCH_RIGHT_LINE + GOAL_DEPTH / 2.0,
-            (STADIUM_HEIGHT - GOAL_BREADTH) / 2.0,
+            arena.right_line() + goal_depth / 2.0,
+            (stadium_height - goal_breadth) / 2.0,
             false,
-            PITCH_LINES_GROUP,
-            PITCH_LINES_GROUP,
         );
         create_line_closure(
-            GOAL_DEPTH,
+            goal_depth,
             PITCH_LINE_HEIGHT,
-            PITCH_RIGHT_LINE + GOAL_DEPTH / 2.0,
-            (STADIUM_HEIGHT + GOAL_BREADTH) / 2.0,
+            arena.right_line() + goal_depth / 2.0,
+            (stadium_height + goal_breadth) / 2.0,
             false,
-            PITCH_LINES_GROUP,
-            PITCH_LINES_GROUP,
         );
 
         // top pitch line`
         create_line_closure(
-            PITCH_WIDTH,
+            arena.pitch_width,
             PITCH_LINE_HEIGHT,
-            STADIUM_WIDTH / 2.0,
-            PITCH_TOP_LINE,
+            stadium_width / 2.0,
+            arena.top_line(),
             true,
-            PITCH_LINES_GROUP,
-            PITCH_LINES_GROUP,
         );
 
         // bottom pitch line
         create_line_closure(
-            PITCH_WIDTH,
+            arena.pitch_width,
             PITCH_LINE_HEIGHT,
-            STADIUM_WIDTH / 2.0,
-            PITCH_BOTTOM_LINE,
+            stadium_width / 2.0,
+            arena.bottom_line(),
             true,
-            PITCH_LINES_GROUP,
-            PITCH_LINES_GROUP,
         );
 
         edges
     }
 
-    fn create_goals_posts(collider_set: &mut ColliderSet) -> Vec<Circle> {
+    fn create_goals_posts(
+        collider_set: &mut ColliderSet,
+        arbiter: &Arbiter,
+        collisions: &CollisionRules,
+    ) -> Vec<Circle> {
+        let arena = &arbiter.arena;
+        let left_red = arbiter.defends_left(true);
         let mut goal_posts = Vec::new();
 
         let mut create_post_closure = |x, y, red| {
-            let ball_collider = ColliderBuilder::ball(BALL_RADIUS)
-                .collision_groups(InteractionGroups::new(GOAL_POSTS_GROUP, GOAL_POSTS_GROUP))
+            let ball_collider = ColliderBuilder::ball(arena.ball_radius)
+                .collision_groups(collisions.goal_posts())
                 .translation(vector![x, y])
                 .build();
             goal_posts.push(Circle::new(
                 ball_collider.translation().x,
                 ball_collider.translation().y,
-                BALL_RADIUS,
+                arena.ball_radius,
                 red,
                 -1,
             ));
             collider_set.insert(ball_collider);
         };
-        // left red goal
+        let middle = arena.top_line() + arena.pitch_height / 2.0;
+        // left goal, red unless the teams switched ends
         create_post_closure(
-            PITCH_LEFT_LINE,
-            PITCH_TOP_LINE + PITCH_HEIGHT / 2.0 - GOAL_BREADTH / 2.0,
-            true,
+            arena.left_line(),
+            middle - arena.goal_breadth / 2.0,
+            left_red,
         );
         create_post_closure(
-            PITCH_LEFT_LINE,
-            PITCH_TOP_LINE + PITCH_HEIGHT / 2.0 + GOAL_BREADTH / 2.0,
-            true,
+            arena.left_line(),
+            middle + arena.goal_breadth / 2.0,
+            left_red,
         );
 
-        // right blue goal
+        // right goal
         create_post_closure(
-            PITCH_RIGHT_LINE,
-            PITCH_TOP_LINE + PITCH_HEIGHT / 2.0 - GOAL_BREADTH / 2.0,
-            false,
+            arena.right_line(),
+            middle - arena.goal_breadth / 2.0,
+            !left_red,
         );
         create_post_closure(
-            PITCH_RIGHT_LINE,
-            PITCH_TOP_LINE + PITCH_HEIGHT / 2.0 + GOAL_BREADTH / 2.0,
-            false,
+            arena.right_line(),
+            middle + arena.goal_breadth / 2.0,
+            !left_red,
         );
 
         goal_posts
     }
 
-    fn create_stadium_walls(collider_set: &mut ColliderSet) {
+    fn create_stadium_walls(
+        collider_set: &mut ColliderSet,
+        arena: &ArenaConfig,
+        collisions: &CollisionRules,
+    ) {
         let mut create_wall_closure = |width, height, x, y| {
             let cuboid_collider = ColliderBuilder::cuboid(width / 2.0, height / 2.0)
-                .collision_groups(InteractionGroups::new(
-                    STADIUM_WALLS_GROUP,
-                    STADIUM_WALLS_GROUP,
-                ))
+                .collision_groups(collisions.stadium_walls())
                 .translation(vector![x, y])
                 .build();
             collider_set.insert(cuboid_collider);
         };
+        let (width, height) = (arena.stadium_width(), arena.stadium_height());
         // left stadium wall
-        create_wall_closure(0.0, STADIUM_HEIGHT, 0.0, STADIUM_HEIGHT / 2.0);
+        create_wall_closure(0.0, height, 0.0, height / 2.0);
 
         // right stadium wall
-        create_wall_closure(0.0, STADIUM_HEIGHT, STADIUM_WIDTH, STADIUM_HEIGHT / 2.0);
+        create_wall_closure(0.0, height, width, height / 2.0);
 
         // top stadium wall
-        create_wall_closure(STADIUM_WIDTH, 0.0, STADIUM_WIDTH / 2.0, 0.0);
+        create_wall_closure(width, 0.0, width / 2.0, 0.0);
 
         // bottom stadium wall
-        create_wall_closure(STADIUM_WIDTH, 0.0, STADIUM_WIDTH / 2.0, STADIUM_HEIGHT);
+        create_wall_closure(width, 0.0, width / 2.0, height);
     }
 
     fn create_player(&mut self, x: f32, y: f32, is_red: bool, number: usize) -> Player {
-        const COLLISION_GROUP: u32 =
-            PLAYERS_GROUP | STADIUM_WALLS_GROUP | BALL_GROUP | GOAL_POSTS_GROUP;
         let player_rigid_body = RigidBodyBuilder::new_dynamic()
-            .linear_damping(1.0)
+            .linear_damping(self.settings.player_damping())
             .translation(vector![x, y])
             .build();
         let player_rigid_body = Rc::new(RefCell::new(player_rigid_body));
-        let player_collider = ColliderBuilder::ball(PLAYER_RADIUS)
-            .collision_groups(InteractionGroups::new(COLLISION_GROUP, COLLISION_GROUP))
-            .restitution(0.7)
+        let radius = self.settings.arena.player_radius;
+        let player_collider = ColliderBuilder::ball(radius)
+            .collision_groups(self.settings.collisions.players())
+            .restitution(self.settings.pitch.restitution())
             .build();
         let player_body_handle: RigidBodyHandle = self
             .rigid_body_set
@@ -448,25 +606,27 @@ impl HostGameInner {
             player_body_handle,
             &mut self.rigid_body_set,
         );
-        Player::new(player_body_handle, PLAYER_RADIUS, is_red, number)
+        Player::new(player_body_handle, radius, is_red, number)
     }
 
     fn create_ball(
         rigid_body_set: &mut RigidBodySet,
         collider_set: &mut ColliderSet,
+        settings: &GameSettings,
     ) -> RigidBodyHandle {
-        const COLLISION_GROUP: u32 =
-            BALL_GROUP | PLAYERS_GROUP | PITCH_LINES_GROUP | GOAL_POSTS_GROUP;
-
+        let arena = &settings.arena;
         let ball_rigid_body = RigidBodyBuilder::new_dynamic()
-            .linear_damping(0.3)
-            .translation(vector![STADIUM_WIDTH / 2.0, STADIUM_HEIGHT / 2.0])
+            .linear_damping(settings.ball_damping())
+            .translation(vector![
+                arena.stadium_width() / 2.0,
+                arena.stadium_height() / 2.0
+            ])
             .build();
         let ball_rigid_body = Rc::new(RefCell::new(ball_rigid_body));
-        let ball_collider = ColliderBuilder::ball(BALL_RADIUS)
+        let ball_collider = ColliderBuilder::ball(arena.ball_radius)
             .density(0.5)
-            .collision_groups(InteractionGroups::new(COLLISION_GROUP, COLLISION_GROUP))
-            .restitution(0.7)
+            .collision_groups(settings.collisions.ball())
+            .restitution(settings.pitch.restitution())
             .build();
         let ball_body_handle: RigidBodyHandle =
             rigid_body_set.insert(ball_rigid_body.borrow().to_owned());
@@ -476,22 +636,37 @@ impl HostGameInner {
     }
 
     fn host_send_state(&mut self) {
-        let game_state = if self.arbiter.send_score_message {
+        let (game_state, priority) = if self.arbiter.send_score_message {
             self.arbiter.send_score_message = false;
-            Message::GoalScored {
+            let goal = Message::GoalScored {
                 red_scored: self.get_red_scored(),
                 score: self.get_score(),
-            }
+            };
+            (goal, Priority::High)
         } else {
-            Message::GameState {
-                players: self.get_player_entities(),
-                ball: self.get_ball_entity(),
-            }
+            self.state_sequence = self.state_sequence.wrapping_add(1);
+            let state = self.deltas.encode(
+                self.state_sequence,
+                self.get_player_entities(),
+                self.get_ball_entity(),
+            );
+            (state, Priority::Normal)
         };
-        self.mini_server.send_message(&game_state);
+        self.send(GameMessage::from(game_state), priority);
+    }
+
+    fn send(&self, message: GameMessage, priority: Priority) {
+        if let Err(error) = self
+            .mini_server
+            .send_message_with_priority(&message, priority)
+        {
+            error!("failed to send a message to the other player: {error}");
+        }
     }
 
     fn advance_physic_tick(&mut self) {
+        let movement = self.settings.movement;
+        let shooting_distance = self.settings.arena.shooting_distance();
         let players = self.host_player.iter_mut().chain(self.oppo.iter_mut());
         for player in players {
             let player_last_tick_shot = player.last_tick_shot;
@@ -515,7 +690,9 @@ impl HostGameInner {
                     let dx = bx - px;
                     let dy = by - py;
                     let dist_sqr = dx * dx + dy * dy;
-                    if dist_sqr <= SHOOTING_DISTANCE * SHOOTING_DISTANCE {
+                    // the angle of a shot from the exact centre of the ball is undefined
+                    if dist_sqr > f32::EPSILON && dist_sqr <= shooting_distance * shooting_distance
+                    {
                         let angle = crate::game::utils::angle(px, py, bx, by);
                         let x_speed =
                             BALL_TOP_SPEED * (std::f32::consts::PI * (angle / 180.0)).cos();
@@ -531,42 +708,24 @@ impl HostGameInner {
 
             let player_body = &mut self.rigid_body_set[body_handle];
 
+            let acceleration = movement.acceleration;
             if input.up {
-                player_body.apply_impulse(vector![0.0, -PLAYER_ACCELERATION], true);
+                player_body.apply_impulse(vector![0.0, -acceleration], true);
             } else if input.down {
-                player_body.apply_impulse(vector![0.0, PLAYER_ACCELERATION], true);
+                player_body.apply_impulse(vector![0.0, acceleration], true);
             }
 
             if input.left {
-                player_body.apply_impulse(vector![-PLAYER_ACCELERATION, 0.0], true);
+                player_body.apply_impulse(vector![-acceleration, 0.0], true);
             } else if input.right {
-                player_body.apply_impulse(vector![PLAYER_ACCELERATION, 0.0], true);
+                player_body.apply_impulse(vector![acceleration, 0.0], true);
             }
 
-            HostGameInner::limit_speed(player_body, PLAYER_TOP_SPEED);
-        }
-    }
-
-    fn limit_speed(rigid_body: &mut RigidBody, top_speed: f32) {
-        let x_speed = rigid_body.linvel().x;
-        let y_speed = rigid_body.linvel().y;
-        let speed = f32::sqrt(x_speed * x_speed + y_speed * y_speed);
-        if speed > top_speed {
-            let speed_normalized = rigid_body.linvel().normalize();
-            rigid_body.set_linvel(
-                vector![
-                    speed_normalized.x * top_speed,
-                    speed_normalized.y * top_speed
-                ],
-                true,
-            );
+            limit_speed(player_body, movement.top_speed);
         }
     }
 
     fn check_timer(&mut self) {
-        if self.arbiter.game_ended {
-            return;
-        }
         if self.arbiter.reset_timer > 0 {
             self.timer_tick();
         } else if self.goal_scored() {
@@ -575,17 +734,8 @@ impl HostGameInner {
     }
 
     fn goal_scored(&mut self) -> bool {
-        let ball_body = &mut self.rigid_body_set[self.ball_body_handle];
-        let x = ball_body.translation().x;
-        if x < PITCH_LEFT_LINE {
-            self.arbiter.set_blue_scored();
-            true
-        } else if x > PITCH_RIGHT_LINE {
-            self.arbiter.set_red_scored();
-            true
-        } else {
-            false
-        }
+        let x = self.rigid_body_set[self.ball_body_handle].translation().x;
+        self.arbiter.check_goal(x)
     }
 
     fn timer_tick(&mut self) {
@@ -599,29 +749,46 @@ impl HostGameInner {
 
     fn check_ending(&mut self) {
         if self.arbiter.red_score == MAX_GOALS || self.arbiter.blue_score == MAX_GOALS {
-            self.arbiter.game_ended = true;
-            self.mini_server.send_message(&Message::GameEnded);
+            self.state = GameState::Ended;
+            self.send(GameMessage::from(Message::GameEnded), Priority::High);
         }
     }
 
     fn reset_game(&mut self) {
         {
+            let arena = &self.settings.arena;
+            let centre = vector![arena.stadium_width() / 2.0, arena.stadium_height() / 2.0];
             let ball_body = &mut self.rigid_body_set[self.ball_body_handle];
-            ball_body.set_position(
-                Isometry::new(vector![STADIUM_WIDTH / 2.0, STADIUM_HEIGHT / 2.0], 0.0),
-                false,
-            );
+            ball_body.set_position(Isometry::new(centre, 0.0), false);
             ball_body.set_linvel(vector![0.0, 0.0], false);
         }
 
         if let Some(oppo) = &mut self.oppo {
-            oppo.reset_position(&mut self.rigid_body_set, 0.0, 0.0);
+            oppo.reset_position(&mut self.rigid_body_set, &self.arbiter, 0.0, 0.0);
         }
         if let Some(player) = &mut self.host_player {
-            player.reset_position(&mut self.rigid_body_set, 0.0, 0.0);
+            player.reset_position(&mut self.rigid_body_set, &self.arbiter, 0.0, 0.0);
         }
     }
 
+    /// Display a chat message, and relay it to the peers other than its author.
+    /// The client can't pretend to be someone else, it is always the author of what it sends.
+    fn chat(&mut self, from: UserId, text: &str) {
+        let text = match sanitize_chat(text) {
+            Some(text) => text,
+            None => return,
+        };
+        if from != CLIENT_ID {
+            let message = Message::Chat {
+                from,
+                text: text.clone(),
+            };
+            self.send(GameMessage::from(message), Priority::Normal);
+        }
+        self.last_message = Some("Chat");
+        self.chat.push(from, text);
+    }
+
     fn players(&self) -> impl Iterator<Item = &Player> {
         self.host_player.iter().chain(self.oppo.iter())
     }
@@ -636,7 +803,7 @@ impl HostGameInner {
         Circle::new(
             brb.translation().x,
             brb.translation().y,
-            BALL_RADIUS,
+            self.settings.arena.ball_radius,
             false,
             -1,
         )
@@ -654,53 +821,92 @@ impl HostGameInner {
         self.arbiter.red_scored
     }
 
-    fn get_blue_scored(&self) -> bool {
-        self.arbiter.blue_scored
-    }
-
     fn get_score(&self) -> Score {
         Score::new(self.arbiter.red_score, self.arbiter.blue_score)
     }
 
-    fn get_game_ended(&self) -> bool {
-        self.arbiter.game_ended
-    }
-
     fn draw(&self) {
-        rendering::draw_stadium(&self.context, STADIUM_WIDTH as f64, STADIUM_HEIGHT as f64);
+        let arena = &self.settings.arena;
+        let stadium_width = arena.stadium_width() as f64;
+        let stadium_height = arena.stadium_height() as f64;
+        rendering::fit_canvas(&self.context, stadium_width, stadium_height);
+        rendering::draw_stadium(&self.context, stadium_width, stadium_height);
         rendering::draw_pitch(
             &self.context,
             &self.edges,
-            PITCH_LEFT_LINE as f64,
-            PITCH_RIGHT_LINE as f64,
-            PITCH_TOP_LINE as f64,
-            PITCH_BOTTOM_LINE as f64,
+            self.settings.pitch,
+            arena.left_line() as f64,
+            arena.right_line() as f64,
+            arena.top_line() as f64,
+            arena.bottom_line() as f64,
             PITCH_LINE_WIDTH as f64,
-            STADIUM_WIDTH as f64,
-            STADIUM_HEIGHT as f64,
-            GOAL_BREADTH as f64,
+            stadium_width,
+            stadium_height,
+            arena.goal_breadth as f64,
         );
         rendering::draw_goals(&self.context, &self.goal_posts);
         rendering::draw_score(
             &self.context,
             &self.get_score(),
-            STADIUM_WIDTH as f64,
-            PITCH_TOP_LINE as f64,
+            stadium_width,
+            arena.top_line() as f64,
         );
         rendering::draw_players(&self.context, &self.get_player_entities());
         rendering::draw_ball(&self.context, &self.get_ball_entity());
-        if self.get_red_scored() {
-            rendering::draw_red_scored(&self.context, STADIUM_WIDTH as f64, STADIUM_HEIGHT as f64);
-        }
-        if self.get_blue_scored() {
-            rendering::draw_blue_scored(&self.context, STADIUM_WIDTH as f64, STADIUM_HEIGHT as f64);
+        rendering::draw_latency(&self.context, self.latency.rtt_ms(), stadium_width);
+        rendering::draw_chat(
+            &self.context,
+            &self.chat,
+            arena.left_line() as f64,
+            arena.bottom_line() as f64,
+        );
+        if let Some(red_scored) = self.arbiter.last_scorer {
+            rendering::draw_last_scorer(
+                &self.context,
+                red_scored,
+                stadium_width,
+                arena.top_line() as f64,
+            );
+            if self.arbiter.reset_timer > 0 {
+                rendering::draw_goal_banner(
+                    &self.context,
+                    red_scored,
+                    celebration_opacity(self.arbiter.reset_timer),
+                    stadium_width,
+                    stadium_height,
+                );
+            }
         }
-        if self.get_game_ended() {
-            rendering::draw_game_ended(
+        match self.state {
+            GameState::WaitingForPeer => rendering::draw_waiting_for_peer(
+                &self.context,
+                self.session_link.as_deref(),
+                now().unwrap_or_default(),
+                stadium_width,
+                stadium_height,
+            ),
+            GameState::Playing => {}
+            GameState::Paused => {
+                rendering::draw_paused(&self.context, stadium_width, stadium_height)
+            }
+            GameState::Ended => rendering::draw_game_ended(
                 &self.context,
                 &self.get_score(),
-                STADIUM_WIDTH as f64,
-                STADIUM_HEIGHT as f64,
+                stadium_width,
+                stadium_height,
+            ),
+        }
+        if self.debug_overlay.get() {
+            let connection_state = match self.state {
+                GameState::WaitingForPeer => "waiting for peer",
+                GameState::Playing | GameState::Ended => "open",
+                GameState::Paused => "closed",
+            };
+            rendering::draw_debug_overlay(
+                &self.context,
+                self.fps,
+                connection_state,
+                self.last_message,
             );
         }
     }
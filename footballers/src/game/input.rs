@@ -1,12 +1,17 @@
 use crate::utils::global_window;
 use serde::{Deserialize, Serialize};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use std::rc::Rc;
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::JsCast;
-use web_sys::KeyboardEvent;
+use web_sys::{HtmlInputElement, KeyboardEvent};
 
-#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+/// Keys held by a player during a tick.
+///
+/// Sent every tick, so it is serialized as a single byte with one bit per key.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "u8", into = "u8")]
 pub struct PlayerInput {
     pub(crate) up: bool,
     pub(crate) down: bool,
@@ -15,12 +20,52 @@ pub struct PlayerInput {
     pub(crate) shoot: bool,
 }
 
+const UP_BIT: u8 = 1;
+const DOWN_BIT: u8 = 1 << 1;
+const LEFT_BIT: u8 = 1 << 2;
+const RIGHT_BIT: u8 = 1 << 3;
+const SHOOT_BIT: u8 = 1 << 4;
+
+impl From<PlayerInput> for u8 {
+    fn from(input: PlayerInput) -> u8 {
+        [
+            (input.up, UP_BIT),
+            (input.down, DOWN_BIT),
+            (input.left, LEFT_BIT),
+            (input.right, RIGHT_BIT),
+            (input.shoot, SHOOT_BIT),
+        ]
+        .iter()
+        .filter(|(pressed, _)| *pressed)
+        .fold(0, |bits, (_, bit)| bits | bit)
+    }
+}
+
+/// Bits of unknown keys are ignored.
+impl From<u8> for PlayerInput {
+    fn from(bits: u8) -> PlayerInput {
+        PlayerInput {
+            up: bits & UP_BIT != 0,
+            down: bits & DOWN_BIT != 0,
+            left: bits & LEFT_BIT != 0,
+            right: bits & RIGHT_BIT != 0,
+            shoot: bits & SHOOT_BIT != 0,
+        }
+    }
+}
+
 pub(crate) fn local_player_input() -> Rc<RefCell<PlayerInput>> {
     let keys_pressed = Rc::new(RefCell::new(PlayerInput::default()));
     let document = global_window().document().unwrap();
     {
         let keys_pressed = keys_pressed.clone();
         let keydown_listener = Closure::wrap(Box::new(move |event: KeyboardEvent| {
+            // keys typed in the chat box don't move the player
+            if let Some(target) = event.target() {
+                if target.has_type::<HtmlInputElement>() {
+                    return;
+                }
+            }
             let key = event.key();
             if key.as_str() == "Spacebar" || key.as_str() == " " {
                 keys_pressed.borrow_mut().shoot = true;
@@ -76,3 +121,271 @@ pub(crate) fn local_player_input() -> Rc<RefCell<PlayerInput>> {
     }
     keys_pressed
 }
+
+/// Flips the returned flag every time F3 is pressed, used to show the debug overlay.
+pub(crate) fn debug_overlay_toggle() -> Rc<Cell<bool>> {
+    let debug_overlay = Rc::new(Cell::new(false));
+    let document = global_window().document().unwrap();
+    {
+        let debug_overlay = debug_overlay.clone();
+        let keydown_listener = Closure::wrap(Box::new(move |event: KeyboardEvent| {
+            if event.key().as_str() == "F3" {
+                event.prevent_default();
+                debug_overlay.set(!debug_overlay.get());
+            }
+        }) as Box<dyn FnMut(KeyboardEvent)>);
+        document
+            .add_event_listener_with_callback("keydown", keydown_listener.as_ref().unchecked_ref())
+            .unwrap();
+        keydown_listener.forget();
+    }
+    debug_overlay
+}
+
+/// Inputs of a few ticks sent together, the first one being the tick `first_sequence`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputBatch {
+    pub(crate) first_sequence: u32,
+    pub(crate) inputs: Vec<PlayerInput>,
+}
+
+/// Collects the inputs of `batch_size` ticks before they are sent, on the client.
+pub(crate) struct InputBatcher {
+    batch_size: usize,
+    next_sequence: u32,
+    inputs: Vec<PlayerInput>,
+}
+
+impl InputBatcher {
+    pub(crate) fn new(batch_size: usize) -> Self {
+        InputBatcher {
+            batch_size: batch_size.max(1),
+            next_sequence: 0,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Add the input of this tick, returning the batch to send once it is full.
+    pub(crate) fn push(&mut self, input: PlayerInput) -> Option<InputBatch> {
+        self.inputs.push(input);
+        if self.inputs.len() < self.batch_size {
+            return None;
+        }
+        let batch = InputBatch {
+            first_sequence: self.next_sequence,
+            inputs: std::mem::take(&mut self.inputs),
+        };
+        self.next_sequence = self.next_sequence.wrapping_add(batch.inputs.len() as u32);
+        Some(batch)
+    }
+}
+
+/// Inputs received in batches, applied one per tick on the host.
+///
+/// Batches arriving late, after a newer one, are dropped. At most `max_queued` inputs
+/// are kept, the oldest being skipped when the host falls behind.
+///
+/// Inputs are held for `jitter_delay_ms` after they arrive, acting as a jitter buffer:
+/// the inputs waiting cover for the ones delayed by the network, so that the opponent
+/// keeps moving steadily instead of stuttering, at the cost of that much latency.
+pub(crate) struct InputQueue {
+    max_queued: usize,
+    jitter_delay_ms: f64,
+    next_sequence: u32,
+    /// Inputs with the time in milliseconds at which they can be applied
+    inputs: VecDeque<(f64, PlayerInput)>,
+}
+
+impl InputQueue {
+    pub(crate) fn new(max_queued: usize, jitter_delay_ms: f64) -> Self {
+        InputQueue {
+            max_queued: max_queued.max(1),
+            jitter_delay_ms,
+            next_sequence: 0,
+            inputs: VecDeque::new(),
+        }
+    }
+
+    /// Queue the new inputs of `batch`, received at `now` in milliseconds.
+    pub(crate) fn receive(&mut self, batch: InputBatch, now: f64) {
+        let end = batch.first_sequence.wrapping_add(batch.inputs.len() as u32);
+        // wrapping comparison, the batch is stale if it ends before the expected sequence
+        if (end.wrapping_sub(self.next_sequence) as i32) <= 0 {
+            return;
+        }
+        let already_received = self.next_sequence.wrapping_sub(batch.first_sequence) as i32;
+        let skip = already_received.max(0) as usize;
+        let ready_at = now + self.jitter_delay_ms;
+        let inputs = batch.inputs.into_iter().skip(skip);
+        self.inputs.extend(inputs.map(|input| (ready_at, input)));
+        self.next_sequence = end;
+        while self.inputs.len() > self.max_queued {
+            self.inputs.pop_front();
+        }
+    }
+
+    /// Input to apply this tick at `now`, `None` when there is no new one ready
+    /// and the previous input should be kept.
+    pub(crate) fn next(&mut self, now: f64) -> Option<PlayerInput> {
+        match self.inputs.front() {
+            Some((ready_at, _)) if *ready_at <= now => {
+                self.inputs.pop_front().map(|(_, input)| input)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Time of the last input received from a player, to tell when it went silent.
+pub(crate) struct IdleTimer {
+    timeout_ms: Option<f64>,
+    last_input: Option<f64>,
+}
+
+impl IdleTimer {
+    pub(crate) fn new(timeout_ms: Option<f64>) -> Self {
+        IdleTimer {
+            timeout_ms,
+            last_input: None,
+        }
+    }
+
+    /// Start counting from `now`, in milliseconds, as an input was received or the player joined.
+    pub(crate) fn reset(&mut self, now: f64) {
+        self.last_input = Some(now);
+    }
+
+    /// Whether no input was received for longer than the timeout, before `now`.
+    pub(crate) fn timed_out(&self, now: f64) -> bool {
+        match (self.timeout_ms, self.last_input) {
+            (Some(timeout_ms), Some(last_input)) => now - last_input > timeout_ms,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn input(shoot: bool) -> PlayerInput {
+        PlayerInput {
+            shoot,
+            ..PlayerInput::default()
+        }
+    }
+
+    #[test]
+    fn batched_inputs_are_applied_in_order() {
+        let mut batcher = InputBatcher::new(3);
+        let mut queue = InputQueue::new(16, 0.0);
+        let sent = [true, false, true, true, false, false];
+
+        let batches: Vec<InputBatch> = sent
+            .iter()
+            .filter_map(|&shoot| batcher.push(input(shoot)))
+            .collect();
+        assert_eq!(batches.len(), 2);
+        for batch in batches {
+            queue.receive(batch, 0.0);
+        }
+
+        let applied: Vec<bool> = std::iter::from_fn(|| queue.next(0.0))
+            .map(|input| input.shoot)
+            .collect();
+        assert_eq!(applied, sent);
+    }
+
+    #[test]
+    fn late_batches_are_dropped() {
+        let mut batcher = InputBatcher::new(2);
+        let mut queue = InputQueue::new(16, 0.0);
+        let first = batcher
+            .push(input(true))
+            .or_else(|| batcher.push(input(true)))
+            .unwrap();
+        let second = batcher
+            .push(input(false))
+            .or_else(|| batcher.push(input(false)))
+            .unwrap();
+
+        queue.receive(second, 0.0);
+        queue.receive(first, 0.0);
+
+        assert_eq!(queue.next(0.0), Some(input(false)));
+        assert_eq!(queue.next(0.0), Some(input(false)));
+        assert_eq!(queue.next(0.0), None);
+    }
+
+    #[test]
+    fn bunched_inputs_are_spread_over_ticks() {
+        let mut batcher = InputBatcher::new(1);
+        let mut queue = InputQueue::new(16, 50.0);
+        // two inputs arriving together after a gap, then nothing for a while
+        queue.receive(batcher.push(input(true)).unwrap(), 100.0);
+        queue.receive(batcher.push(input(false)).unwrap(), 100.0);
+
+        assert_eq!(queue.next(120.0), None, "held for the jitter delay");
+        assert_eq!(queue.next(150.0), Some(input(true)));
+        assert_eq!(queue.next(166.0), Some(input(false)));
+        assert_eq!(queue.next(183.0), None);
+    }
+
+    #[test]
+    fn inputs_from_a_rejoined_page_are_applied_after_a_resume() {
+        let mut first_page = InputBatcher::new(2);
+        let mut queue = InputQueue::new(16, 0.0);
+        for _ in 0..10 {
+            if let Some(batch) = first_page.push(input(false)) {
+                queue.receive(batch, 0.0);
+            }
+        }
+        while queue.next(0.0).is_some() {}
+
+        let mut new_page = InputBatcher::new(2);
+        new_page.push(input(true));
+        let batch = new_page.push(input(true)).unwrap();
+        assert_eq!(batch.first_sequence, 0);
+        queue.receive(batch.clone(), 0.0);
+        assert_eq!(
+            queue.next(0.0),
+            None,
+            "stale for the queue of the first page"
+        );
+
+        // as the host does when resuming the game
+        queue = InputQueue::new(16, 0.0);
+        queue.receive(batch, 0.0);
+        assert_eq!(queue.next(0.0), Some(input(true)));
+        assert_eq!(queue.next(0.0), Some(input(true)));
+    }
+
+    #[test]
+    fn every_key_combination_round_trips_as_one_byte() {
+        for bits in 0..32u8 {
+            let input = PlayerInput::from(bits);
+            let encoded = rmp_serde::to_vec(&input).unwrap();
+            assert_eq!(encoded, vec![bits], "positive fixint is the byte itself");
+            assert_eq!(
+                rmp_serde::from_slice::<PlayerInput>(&encoded).unwrap(),
+                input
+            );
+            assert_eq!(u8::from(input), bits);
+        }
+    }
+
+    #[test]
+    fn silent_player_times_out() {
+        let mut timer = IdleTimer::new(Some(1000.0));
+        assert!(!timer.timed_out(5000.0), "not before the player joined");
+        timer.reset(5000.0);
+        assert!(!timer.timed_out(5900.0));
+        timer.reset(5900.0);
+        assert!(!timer.timed_out(6800.0));
+        assert!(timer.timed_out(7000.0));
+
+        let mut never = IdleTimer::new(None);
+        never.reset(0.0);
+        assert!(!never.timed_out(f64::MAX));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::utils::now;
+use log::error;
+use std::cell::Cell;
+use std::rc::Rc;
+use wasm_peers::one_to_one::NetworkManager;
+
+/// Time in milliseconds between two reads of the round trip time.
+const POLL_INTERVAL_MS: f64 = 1000.0;
+/// Round trip times in milliseconds up to which the connection is good, then fair.
+const GOOD_RTT_MS: f64 = 100.0;
+const FAIR_RTT_MS: f64 = 200.0;
+
+/// How the connection with the other player feels, shown in a corner of the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConnectionQuality {
+    Good,
+    Fair,
+    Poor,
+}
+
+impl ConnectionQuality {
+    pub(crate) fn from_rtt(rtt_ms: f64) -> Self {
+        if rtt_ms <= GOOD_RTT_MS {
+            ConnectionQuality::Good
+        } else if rtt_ms <= FAIR_RTT_MS {
+            ConnectionQuality::Fair
+        } else {
+            ConnectionQuality::Poor
+        }
+    }
+}
+
+/// Latest round trip time to the other player, read from the connection statistics.
+#[derive(Debug, Default)]
+pub(crate) struct LatencyMeter {
+    /// Set once the statistics are read, after the poll that asked for them
+    rtt_ms: Rc<Cell<Option<f64>>>,
+    last_poll: Option<f64>,
+}
+
+impl LatencyMeter {
+    /// Ask `network_manager` for the round trip time, at most once every second.
+    pub(crate) fn poll(&mut self, network_manager: &NetworkManager) {
+        let now = match now() {
+            Some(now) => now,
+            None => return,
+        };
+        if matches!(self.last_poll, Some(last) if now - last < POLL_INTERVAL_MS) {
+            return;
+        }
+        self.last_poll = Some(now);
+        let rtt_ms = self.rtt_ms.clone();
+        let round_trip_time = network_manager.round_trip_time();
+        wasm_bindgen_futures::spawn_local(async move {
+            match round_trip_time.await {
+                Ok(rtt) => rtt_ms.set(rtt),
+                Err(error) => error!("failed to read the round trip time: {error:?}"),
+            }
+        });
+    }
+
+    /// `None` until the connection is established.
+    pub(crate) fn rtt_ms(&self) -> Option<f64> {
+        self.rtt_ms.get()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rtt_is_bucketed_by_quality() {
+        assert_eq!(ConnectionQuality::from_rtt(30.0), ConnectionQuality::Good);
+        assert_eq!(ConnectionQuality::from_rtt(100.0), ConnectionQuality::Good);
+        assert_eq!(ConnectionQuality::from_rtt(150.0), ConnectionQuality::Fair);
+        assert_eq!(ConnectionQuality::from_rtt(450.0), ConnectionQuality::Poor);
+    }
+}
@@ -1,20 +1,453 @@
+mod client;
 mod constants;
+mod game_loop;
 mod host;
 mod input;
+mod latency;
 mod rendering;
 mod utils;
 
-use crate::game::constants::{PITCH_HEIGHT, PITCH_WIDTH, PLAYER_DIAMETER};
+use crate::game::constants::{
+    BALL_AIR_DAMPING, BALL_DAMPING, BALL_GROUP, BALL_RADIUS, GOAL_BREADTH, GOAL_DEPTH,
+    GOAL_POSTS_GROUP, ICE_CANDIDATE_POOL_SIZE, ICE_RESTARTS, IDLE_TIMEOUT_MS,
+    INPUT_JITTER_DELAY_MS, PITCH_HEIGHT, PITCH_LINES_GROUP, PITCH_WIDTH, PLAYERS_GROUP,
+    PLAYER_ACCELERATION, PLAYER_AIR_DAMPING, PLAYER_DAMPING, PLAYER_DIAMETER, PLAYER_TOP_SPEED,
+    SIDE_VIEW_GRAVITY, STADIUM_WALLS_GROUP,
+};
+use rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use wasm_peers::ConnectionConfig;
 
+pub use crate::game::client::ClientGame;
+pub use crate::game::constants::{MAX_CHAT_LENGTH, MAX_QUEUED_INPUTS};
+pub use crate::game::game_loop::GameLoop;
 pub use crate::game::host::HostGame;
+pub use crate::game::utils::Score;
 
+/// Size of the canvas before the game first draws, which then fits it to the page.
 pub const GAME_CANVAS_WIDTH: f32 = 2.0 * PLAYER_DIAMETER + PITCH_WIDTH + 2.0 * PLAYER_DIAMETER;
 pub const GAME_CANVAS_HEIGHT: f32 = 2.0 * PLAYER_DIAMETER + PITCH_HEIGHT;
 
+/// Tweakable rules of the simulation, fixed for the whole game.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameSettings {
+    /// Gravity applied on each physics step. Zero for the usual top-down view.
+    pub gravity: Vector<Real>,
+    /// Number of ticks of input the client sends together, trading latency for fewer messages.
+    /// Chosen by the client when joining, see the `input_batch` query parameter.
+    pub input_batch_size: usize,
+    /// Whether the host plays in the red team, the other player being in the other one.
+    pub host_red: bool,
+    /// Whether the red team defends the left goal, to switch ends between games of a series.
+    pub red_on_left: bool,
+    /// Time in milliseconds without input from the client after which the host drops it,
+    /// so that a frozen client doesn't leave a motionless player on the pitch. Never when `None`.
+    pub idle_timeout_ms: Option<f64>,
+    /// Time in milliseconds the host holds the inputs of the client before applying them,
+    /// trading latency for a steadier opponent when the network is jittery.
+    pub input_jitter_delay_ms: f64,
+    /// Surface of the pitch, deciding how much bodies slide and bounce.
+    pub pitch: PitchProfile,
+    /// How the players move, for snappier or floatier controls.
+    pub movement: Movement,
+    /// Dimensions of the pitch and of the bodies on it.
+    pub arena: ArenaConfig,
+    /// Which bodies collide with each other.
+    pub collisions: CollisionRules,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            gravity: vector![0.0, 0.0],
+            input_batch_size: 1,
+            host_red: true,
+            red_on_left: true,
+            idle_timeout_ms: Some(IDLE_TIMEOUT_MS),
+            input_jitter_delay_ms: INPUT_JITTER_DELAY_MS,
+            pitch: PitchProfile::default(),
+            movement: Movement::default(),
+            arena: ArenaConfig::default(),
+            collisions: CollisionRules::default(),
+        }
+    }
+}
+
+impl GameSettings {
+    /// Settings for a pitch seen from the side, with the ball and players falling down.
+    pub fn side_view() -> Self {
+        GameSettings {
+            gravity: vector![0.0, SIDE_VIEW_GRAVITY],
+            ..GameSettings::default()
+        }
+    }
+
+    fn has_gravity(&self) -> bool {
+        self.gravity != vector![0.0, 0.0]
+    }
+
+    /// Without gravity damping stands in for the friction with the ground,
+    /// with it only the air slows bodies down.
+    pub(crate) fn player_damping(&self) -> f32 {
+        if self.has_gravity() {
+            PLAYER_AIR_DAMPING
+        } else {
+            self.movement.damping * self.pitch.damping_scale()
+        }
+    }
+
+    pub(crate) fn ball_damping(&self) -> f32 {
+        if self.has_gravity() {
+            BALL_AIR_DAMPING
+        } else {
+            BALL_DAMPING * self.pitch.damping_scale()
+        }
+    }
+}
+
+/// How the players move, chosen by the host and sent to the client with the layout.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Movement {
+    /// Impulse given to a player each tick a direction is held.
+    pub acceleration: f32,
+    /// Speed beyond which players don't accelerate.
+    pub top_speed: f32,
+    /// Damping of the players on a normal pitch seen from the top, scaled by the [PitchProfile].
+    pub damping: f32,
+}
+
+impl Default for Movement {
+    fn default() -> Self {
+        Movement {
+            acceleration: PLAYER_ACCELERATION,
+            top_speed: PLAYER_TOP_SPEED,
+            damping: PLAYER_DAMPING,
+        }
+    }
+}
+
+impl Movement {
+    /// Whether the players can move at all, and damping doesn't speed them up.
+    pub fn is_valid(&self) -> bool {
+        let speeds = [self.acceleration, self.top_speed];
+        speeds.iter().all(|speed| speed.is_finite() && *speed > 0.0)
+            && self.damping.is_finite()
+            && self.damping >= 0.0
+    }
+}
+
+/// Dimensions of the pitch, its goals and the bodies on it, chosen by the host and sent to the
+/// client with the layout. The stadium leaves room around the pitch for the players to go round
+/// the goals.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ArenaConfig {
+    pub pitch_width: f32,
+    pub pitch_height: f32,
+    /// Distance between the posts of a goal.
+    pub goal_breadth: f32,
+    /// How far the net goes behind the goal line.
+    pub goal_depth: f32,
+    pub player_radius: f32,
+    /// Radius of the ball, and of the goal posts.
+    pub ball_radius: f32,
+}
+
+impl Default for ArenaConfig {
+    fn default() -> Self {
+        ArenaConfig {
+            pitch_width: PITCH_WIDTH,
+            pitch_height: PITCH_HEIGHT,
+            goal_breadth: GOAL_BREADTH,
+            goal_depth: GOAL_DEPTH,
+            player_radius: PLAYER_DIAMETER / 2.0,
+            ball_radius: BALL_RADIUS,
+        }
+    }
+}
+
+impl ArenaConfig {
+    /// Whether the bodies fit on the pitch and the goals between its corners.
+    pub fn is_valid(&self) -> bool {
+        let sizes = [
+            self.pitch_width,
+            self.pitch_height,
+            self.goal_breadth,
+            self.goal_depth,
+            self.player_radius,
+            self.ball_radius,
+        ];
+        sizes.iter().all(|size| size.is_finite() && *size > 0.0)
+            && self.goal_breadth + 2.0 * self.ball_radius < self.pitch_height
+            && 4.0 * self.player_diameter() < self.pitch_width
+    }
+
+    pub(crate) fn player_diameter(&self) -> f32 {
+        2.0 * self.player_radius
+    }
+
+    /// Distance between the centres of a player and the ball within which the player can shoot.
+    pub(crate) fn shooting_distance(&self) -> f32 {
+        self.player_radius + self.ball_radius + self.ball_radius / 2.0
+    }
+
+    pub(crate) fn left_line(&self) -> f32 {
+        2.0 * self.player_diameter()
+    }
+
+    pub(crate) fn right_line(&self) -> f32 {
+        self.left_line() + self.pitch_width
+    }
+
+    pub(crate) fn top_line(&self) -> f32 {
+        self.player_diameter()
+    }
+
+    pub(crate) fn bottom_line(&self) -> f32 {
+        self.top_line() + self.pitch_height
+    }
+
+    /// Height of the lines on each side of a goal.
+    pub(crate) fn vertical_line_height(&self) -> f32 {
+        (self.pitch_height - self.goal_breadth) / 2.0
+    }
+
+    pub(crate) fn stadium_width(&self) -> f32 {
+        self.right_line() + 2.0 * self.player_diameter()
+    }
+
+    pub(crate) fn stadium_height(&self) -> f32 {
+        self.bottom_line() + self.player_diameter()
+    }
+}
+
+/// Groups of the bodies each kind of body collides with, for game modes changing the rules.
+/// Two bodies only collide when both include the group of the other.
+/// Only the host simulates, the client just draws the bodies where they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollisionRules {
+    pub players: u32,
+    pub ball: u32,
+    pub goal_posts: u32,
+    pub pitch_lines: u32,
+    pub stadium_walls: u32,
+}
+
+impl Default for CollisionRules {
+    /// Players stay in the stadium and the ball on the pitch, both bouncing off the goal posts.
+    fn default() -> Self {
+        CollisionRules {
+            players: PLAYERS_GROUP | STADIUM_WALLS_GROUP | BALL_GROUP | GOAL_POSTS_GROUP,
+            ball: PLAYERS_GROUP | PITCH_LINES_GROUP | GOAL_POSTS_GROUP,
+            goal_posts: PLAYERS_GROUP | BALL_GROUP,
+            pitch_lines: BALL_GROUP,
+            stadium_walls: PLAYERS_GROUP,
+        }
+    }
+}
+
+impl CollisionRules {
+    /// Players go through each other, bumping into everything else.
+    pub fn players_pass_through() -> Self {
+        let default = CollisionRules::default();
+        CollisionRules {
+            players: default.players & !PLAYERS_GROUP,
+            ..default
+        }
+    }
+
+    /// The ball goes through the players, which can still shoot it.
+    pub fn ghost_ball() -> Self {
+        let default = CollisionRules::default();
+        CollisionRules {
+            players: default.players & !BALL_GROUP,
+            ball: default.ball & !PLAYERS_GROUP,
+            ..default
+        }
+    }
+
+    pub(crate) fn players(&self) -> InteractionGroups {
+        InteractionGroups::new(PLAYERS_GROUP, self.players)
+    }
+
+    pub(crate) fn ball(&self) -> InteractionGroups {
+        InteractionGroups::new(BALL_GROUP, self.ball)
+    }
+
+    pub(crate) fn goal_posts(&self) -> InteractionGroups {
+        InteractionGroups::new(GOAL_POSTS_GROUP, self.goal_posts)
+    }
+
+    pub(crate) fn pitch_lines(&self) -> InteractionGroups {
+        InteractionGroups::new(PITCH_LINES_GROUP, self.pitch_lines)
+    }
+
+    pub(crate) fn stadium_walls(&self) -> InteractionGroups {
+        InteractionGroups::new(STADIUM_WALLS_GROUP, self.stadium_walls)
+    }
+}
+
+/// Surface of the pitch, chosen by the host and sent to the client with the layout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PitchProfile {
+    /// Ice: players and the ball slide a long way and bounce off each other.
+    Slippery,
+    /// Grass.
+    #[default]
+    Normal,
+    /// Mud: bodies stop quickly and barely bounce.
+    Heavy,
+}
+
+impl PitchProfile {
+    /// Factor of the damping of bodies rolling on the pitch.
+    fn damping_scale(self) -> f32 {
+        match self {
+            PitchProfile::Slippery => 0.3,
+            PitchProfile::Normal => 1.0,
+            PitchProfile::Heavy => 2.5,
+        }
+    }
+
+    /// Restitution of the colliders of the players and the ball.
+    pub(crate) fn restitution(self) -> f32 {
+        match self {
+            PitchProfile::Slippery => 0.9,
+            PitchProfile::Normal => 0.7,
+            PitchProfile::Heavy => 0.4,
+        }
+    }
+}
+
+impl FromStr for PitchProfile {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "slippery" => Ok(PitchProfile::Slippery),
+            "normal" => Ok(PitchProfile::Normal),
+            "heavy" => Ok(PitchProfile::Heavy),
+            _ => Err(format!("unknown pitch profile: {name}")),
+        }
+    }
+}
+
+/// Phase of a game, deciding whether it simulates and what is drawn over the pitch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameState {
+    /// The other player did not join yet, or is still setting up the pitch.
+    WaitingForPeer,
+    Playing,
+    /// The other player left, the game stays as it was.
+    Paused,
+    /// One of the teams won.
+    Ended,
+}
+
 pub trait Game {
     fn init(&mut self);
     fn tick(&mut self);
-    fn ended(&self) -> bool;
+    fn state(&self) -> GameState;
+    fn score(&self) -> Score;
+    /// Whether the game is over, after which it is not ticked anymore.
+    fn ended(&self) -> bool {
+        self.state() == GameState::Ended
+    }
+    /// Send a chat message to the other player, and display it locally.
+    fn send_chat(&mut self, text: &str);
+    /// Close the connection and leave the session, freeing it on the signaling server
+    /// if the other player did not join yet.
+    fn leave(&mut self);
 }
 
-pub type FootballersGame = HostGame;
+pub type FootballersGame = Box<dyn Game>;
+
+/// Connection options shared by the host and the client.
+pub(crate) fn connection_config() -> ConnectionConfig {
+    ConnectionConfig {
+        ice_candidate_pool_size: Some(ICE_CANDIDATE_POOL_SIZE),
+        ice_restarts: ICE_RESTARTS,
+        namespace: "footballers".to_string(),
+        ..ConnectionConfig::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Whether two players running into each other end up on the other side of one another.
+    fn players_cross(rules: &CollisionRules) -> bool {
+        let mut rigid_body_set = RigidBodySet::new();
+        let mut collider_set = ColliderSet::new();
+        let mut player = |x: f32, speed: f32| {
+            let body = RigidBodyBuilder::new_dynamic()
+                .translation(vector![x, 0.0])
+                .linvel(vector![speed, 0.0])
+                .build();
+            let handle = rigid_body_set.insert(body);
+            let collider = ColliderBuilder::ball(10.0)
+                .collision_groups(rules.players())
+                .build();
+            collider_set.insert_with_parent(collider, handle, &mut rigid_body_set);
+            handle
+        };
+        let (left, right) = (player(0.0, 100.0), player(40.0, -100.0));
+
+        let mut pipeline = PhysicsPipeline::new();
+        let mut island_manager = IslandManager::new();
+        let mut broad_phase = BroadPhase::new();
+        let mut narrow_phase = NarrowPhase::new();
+        let mut joint_set = JointSet::new();
+        let mut ccd_solver = CCDSolver::new();
+        for _ in 0..60 {
+            pipeline.step(
+                &vector![0.0, 0.0],
+                &IntegrationParameters::default(),
+                &mut island_manager,
+                &mut broad_phase,
+                &mut narrow_phase,
+                &mut rigid_body_set,
+                &mut collider_set,
+                &mut joint_set,
+                &mut ccd_solver,
+                &(),
+                &(),
+            );
+        }
+        rigid_body_set[left].translation().x > rigid_body_set[right].translation().x
+    }
+
+    #[test]
+    fn players_pass_through_each_other_only_when_configured() {
+        assert!(!players_cross(&CollisionRules::default()));
+        assert!(players_cross(&CollisionRules::players_pass_through()));
+        // the other modes leave the players bumping into each other
+        assert!(!players_cross(&CollisionRules::ghost_ball()));
+    }
+
+    #[test]
+    fn movement_must_let_players_move() {
+        assert!(Movement::default().is_valid());
+        let still = Movement {
+            top_speed: 0.0,
+            ..Movement::default()
+        };
+        assert!(!still.is_valid());
+        let undamped = Movement {
+            damping: 0.0,
+            ..Movement::default()
+        };
+        assert!(undamped.is_valid());
+        let boosting = Movement {
+            damping: -1.0,
+            ..Movement::default()
+        };
+        assert!(!boosting.is_valid());
+        let runaway = Movement {
+            acceleration: f32::INFINITY,
+            ..Movement::default()
+        };
+        assert!(!runaway.is_valid());
+    }
+}
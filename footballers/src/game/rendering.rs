@@ -1,9 +1,14 @@
-use crate::game::utils::{Circle, Edge, Score};
+use crate::game::latency::ConnectionQuality;
+use crate::game::utils::{ChatLog, Circle, Edge, Score, HOST_ID};
+use crate::game::PitchProfile;
+use crate::utils::global_window;
 use std::f64::consts;
-use wasm_bindgen::JsValue;
+use wasm_bindgen::{JsCast, JsValue};
 use web_sys::CanvasRenderingContext2d;
 
 const PITCH_COLOR: &str = "#619F5E";
+const ICE_PITCH_COLOR: &str = "#A9D3E0";
+const MUD_PITCH_COLOR: &str = "#7A6A45";
 const PITCH_LINE_COLOR: &str = "#C7E6BD";
 const BALL_COLOR: &str = "#EEEEEE";
 const RED_PLAYER_COLOR: &str = "#E56E56";
@@ -12,6 +17,73 @@ const OUTLINE_COLOR: &str = "#000000";
 const OUTLINE_WIDTH: f64 = 2.0;
 const STADIUM_COLOR: &str = "#718C5A";
 const TEXT_COLOR: &str = "#FFFFFF";
+const DEBUG_BACKGROUND_COLOR: &str = "rgba(0, 0, 0, 0.6)";
+const HOST_CHAT_COLOR: &str = "#FFF3B0";
+const GUEST_CHAT_COLOR: &str = "#FFFFFF";
+const OVERLAY_COLOR: &str = "rgba(0, 0, 0, 0.5)";
+const GOOD_CONNECTION_COLOR: &str = "#5FD35F";
+const FAIR_CONNECTION_COLOR: &str = "#F2C94C";
+const POOR_CONNECTION_COLOR: &str = "#E5484D";
+/// Time in milliseconds for the spinner to go round once.
+const SPINNER_PERIOD: f64 = 1200.0;
+
+/// Drawing context of the game canvas, set up for the drawing functions below.
+pub(crate) fn canvas_context() -> CanvasRenderingContext2d {
+    let document = global_window().document().unwrap();
+    let canvas = document.get_element_by_id("canvas").unwrap();
+    let canvas: web_sys::HtmlCanvasElement = canvas
+        .dyn_into::<web_sys::HtmlCanvasElement>()
+        .map_err(|_| ())
+        .unwrap();
+
+    let context = canvas
+        .get_context("2d")
+        .unwrap()
+        .unwrap()
+        .dyn_into::<web_sys::CanvasRenderingContext2d>()
+        .unwrap();
+    context.set_text_align("center");
+    context.set_text_baseline("middle");
+    context
+}
+
+/// Size the canvas to the width of its container and the height of the window, keeping the
+/// aspect ratio of the stadium, and scale the drawing so that it keeps using game units.
+/// Sharp on high density screens, as the canvas holds `devicePixelRatio` pixels per CSS pixel.
+pub(crate) fn fit_canvas(ctx: &CanvasRenderingContext2d, stadium_width: f64, stadium_height: f64) {
+    let canvas = match ctx.canvas() {
+        Some(canvas) => canvas,
+        None => return,
+    };
+    let window = global_window();
+    let available_width = canvas
+        .parent_element()
+        .map(|container| container.client_width() as f64)
+        .filter(|width| *width > 0.0)
+        .unwrap_or(stadium_width);
+    let available_height = window
+        .inner_height()
+        .ok()
+        .and_then(|height| height.as_f64())
+        .unwrap_or(stadium_height);
+    let scale = (available_width / stadium_width).min(available_height / stadium_height);
+    let pixel_ratio = window.device_pixel_ratio();
+
+    let width = (stadium_width * scale * pixel_ratio).round() as u32;
+    let height = (stadium_height * scale * pixel_ratio).round() as u32;
+    // resizing clears the canvas and resets the context, so only when the size changed
+    if canvas.width() != width || canvas.height() != height {
+        canvas.set_width(width);
+        canvas.set_height(height);
+        let style = canvas.style();
+        let _ = style.set_property("width", &format!("{}px", stadium_width * scale));
+        let _ = style.set_property("height", &format!("{}px", stadium_height * scale));
+        ctx.set_text_align("center");
+        ctx.set_text_baseline("middle");
+    }
+    let ratio = scale * pixel_ratio;
+    let _ = ctx.set_transform(ratio, 0.0, 0.0, ratio, 0.0, 0.0);
+}
 
 pub(crate) fn draw_stadium(
     ctx: &CanvasRenderingContext2d,
@@ -27,6 +99,7 @@ pub(crate) fn draw_stadium(
 pub(crate) fn draw_pitch(
     ctx: &CanvasRenderingContext2d,
     edges: &[Edge],
+    pitch: PitchProfile,
     pitch_left_line: f64,
     pitch_right_line: f64,
     pitch_top_line: f64,
@@ -36,8 +109,12 @@ pub(crate) fn draw_pitch(
     stadium_height: f64,
     goal_breadth: f64,
 ) {
-    // green field
-    ctx.set_fill_style(&JsValue::from(PITCH_COLOR));
+    let pitch_color = match pitch {
+        PitchProfile::Slippery => ICE_PITCH_COLOR,
+        PitchProfile::Normal => PITCH_COLOR,
+        PitchProfile::Heavy => MUD_PITCH_COLOR,
+    };
+    ctx.set_fill_style_str(pitch_color);
     ctx.fill_rect(
         pitch_left_line,
         pitch_top_line,
@@ -226,32 +303,57 @@ pub(crate) fn draw_ball(ctx: &CanvasRenderingContext2d, ball: &Circle) {
     ctx.stroke();
 }
 
-pub(crate) fn draw_red_scored(
+/// "GOAL!" banner in the colour of the scoring team, growing and fading out with `opacity`.
+pub(crate) fn draw_goal_banner(
     ctx: &CanvasRenderingContext2d,
+    red_scored: bool,
+    opacity: f64,
     stadium_width: f64,
     stadium_height: f64,
 ) {
-    ctx.set_font("bold 42px arial");
-    ctx.set_fill_style(&JsValue::from(RED_PLAYER_COLOR));
-    ctx.fill_text("Red Scores!", stadium_width / 2.0, stadium_height / 2.0)
+    let (color, caption) = if red_scored {
+        (RED_PLAYER_COLOR, "Red Scores!")
+    } else {
+        (BLUE_PLAYER_COLOR, "Blue Scores!")
+    };
+    let font_size = 42.0 + 18.0 * (1.0 - opacity);
+    ctx.set_global_alpha(opacity);
+    ctx.set_fill_style_str(color);
+    ctx.set_stroke_style_str(OUTLINE_COLOR);
+    ctx.set_font(&format!("bold {font_size:.0}px arial"));
+    let goal_y = stadium_height / 2.0 - font_size / 2.0;
+    ctx.fill_text("GOAL!", stadium_width / 2.0, goal_y).unwrap();
+    ctx.stroke_text("GOAL!", stadium_width / 2.0, goal_y)
         .unwrap();
-    ctx.set_stroke_style(&JsValue::from(OUTLINE_COLOR));
-    ctx.stroke_text("Red Scores!", stadium_width / 2.0, stadium_height / 2.0)
+    ctx.set_font("bold 24px arial");
+    let caption_y = stadium_height / 2.0 + 18.0;
+    ctx.fill_text(caption, stadium_width / 2.0, caption_y)
+        .unwrap();
+    ctx.stroke_text(caption, stadium_width / 2.0, caption_y)
         .unwrap();
+    ctx.set_global_alpha(1.0);
 }
 
-pub(crate) fn draw_blue_scored(
+/// Dot in the colour of the team which scored last, on its side of the score.
+pub(crate) fn draw_last_scorer(
     ctx: &CanvasRenderingContext2d,
+    red_scored: bool,
     stadium_width: f64,
-    stadium_height: f64,
+    pitch_top_line: f64,
 ) {
-    ctx.set_font("bold 42px arial");
-    ctx.set_fill_style(&JsValue::from(BLUE_PLAYER_COLOR));
-    ctx.fill_text("Blue Scores!", stadium_width / 2.0, stadium_height / 2.0)
-        .unwrap();
-    ctx.set_stroke_style(&JsValue::from(OUTLINE_COLOR));
-    ctx.stroke_text("Blue Scores!", stadium_width / 2.0, stadium_height / 2.0)
+    let (color, x) = if red_scored {
+        (RED_PLAYER_COLOR, stadium_width / 2.0 - 45.0)
+    } else {
+        (BLUE_PLAYER_COLOR, stadium_width / 2.0 + 45.0)
+    };
+    ctx.begin_path();
+    ctx.arc(x, pitch_top_line / 2.0, 5.0, 0.0, 2.0 * consts::PI)
         .unwrap();
+    ctx.set_fill_style_str(color);
+    ctx.fill();
+    ctx.set_line_width(OUTLINE_WIDTH);
+    ctx.set_stroke_style_str(OUTLINE_COLOR);
+    ctx.stroke();
 }
 
 pub(crate) fn draw_game_ended(
@@ -306,3 +408,134 @@ pub(crate) fn draw_game_ended(
     )
     .unwrap();
 }
+
+/// Spinner over the dimmed pitch, with `session_link` below it for the host to share.
+pub(crate) fn draw_waiting_for_peer(
+    ctx: &CanvasRenderingContext2d,
+    session_link: Option<&str>,
+    time: f64,
+    stadium_width: f64,
+    stadium_height: f64,
+) {
+    let (x, y) = (stadium_width / 2.0, stadium_height / 2.0);
+    ctx.set_fill_style_str(OVERLAY_COLOR);
+    ctx.fill_rect(0.0, 0.0, stadium_width, stadium_height);
+
+    let start = 2.0 * consts::PI * (time % SPINNER_PERIOD) / SPINNER_PERIOD;
+    ctx.set_stroke_style_str(TEXT_COLOR);
+    ctx.set_line_width(4.0);
+    ctx.begin_path();
+    ctx.arc(x, y - 40.0, 20.0, start, start + 1.5 * consts::PI)
+        .unwrap();
+    ctx.stroke();
+
+    ctx.set_fill_style_str(TEXT_COLOR);
+    ctx.set_font("bold 24px arial");
+    ctx.fill_text("Waiting for the other player", x, y + 10.0)
+        .unwrap();
+    if let Some(session_link) = session_link {
+        ctx.set_font("14px monospace");
+        ctx.fill_text(session_link, x, y + 40.0).unwrap();
+    }
+}
+
+/// Notice over the dimmed pitch that the other player left.
+pub(crate) fn draw_paused(ctx: &CanvasRenderingContext2d, stadium_width: f64, stadium_height: f64) {
+    ctx.set_fill_style_str(OVERLAY_COLOR);
+    ctx.fill_rect(0.0, 0.0, stadium_width, stadium_height);
+    ctx.set_fill_style_str(TEXT_COLOR);
+    ctx.set_font("bold 24px arial");
+    ctx.fill_text(
+        "The other player left",
+        stadium_width / 2.0,
+        stadium_height / 2.0,
+    )
+    .unwrap();
+}
+
+pub(crate) fn draw_debug_overlay(
+    ctx: &CanvasRenderingContext2d,
+    fps: f64,
+    connection_state: &str,
+    last_message: Option<&str>,
+) {
+    let lines = [
+        format!("fps: {fps:.0}"),
+        format!("connection: {connection_state}"),
+        format!("last message: {}", last_message.unwrap_or("none")),
+    ];
+    let line_height = 14.0;
+    ctx.save();
+    ctx.set_fill_style_str(DEBUG_BACKGROUND_COLOR);
+    ctx.fill_rect(0.0, 0.0, 160.0, line_height * lines.len() as f64 + 4.0);
+
+    ctx.set_font("12px monospace");
+    ctx.set_text_align("left");
+    ctx.set_text_baseline("top");
+    ctx.set_fill_style_str(TEXT_COLOR);
+    for (i, line) in lines.iter().enumerate() {
+        ctx.fill_text(line, 4.0, 2.0 + line_height * i as f64)
+            .unwrap();
+    }
+    ctx.restore();
+}
+
+/// Bar colored by the quality of the connection, with the round trip time next to it,
+/// at the top right of the stadium. Nothing until the round trip time is known.
+pub(crate) fn draw_latency(
+    ctx: &CanvasRenderingContext2d,
+    rtt_ms: Option<f64>,
+    stadium_width: f64,
+) {
+    let rtt_ms = match rtt_ms {
+        Some(rtt_ms) => rtt_ms,
+        None => return,
+    };
+    let color = match ConnectionQuality::from_rtt(rtt_ms) {
+        ConnectionQuality::Good => GOOD_CONNECTION_COLOR,
+        ConnectionQuality::Fair => FAIR_CONNECTION_COLOR,
+        ConnectionQuality::Poor => POOR_CONNECTION_COLOR,
+    };
+    let (bar_width, bar_height, margin) = (24.0, 8.0, 8.0);
+    let bar_x = stadium_width - margin - bar_width;
+    ctx.save();
+    ctx.set_fill_style_str(color);
+    ctx.fill_rect(bar_x, margin, bar_width, bar_height);
+
+    ctx.set_font("12px monospace");
+    ctx.set_text_align("right");
+    ctx.set_fill_style_str(TEXT_COLOR);
+    ctx.fill_text(
+        &format!("{rtt_ms:.0} ms"),
+        bar_x - 4.0,
+        margin + bar_height / 2.0,
+    )
+    .unwrap();
+    ctx.restore();
+}
+
+/// Latest chat messages, at the bottom left of the pitch, the newest one last.
+pub(crate) fn draw_chat(
+    ctx: &CanvasRenderingContext2d,
+    chat: &ChatLog,
+    pitch_left_line: f64,
+    pitch_bottom_line: f64,
+) {
+    let line_height = 14.0;
+    ctx.save();
+    ctx.set_font("12px arial");
+    ctx.set_text_align("left");
+    ctx.set_text_baseline("bottom");
+    for (i, (from, text)) in chat.messages().rev().enumerate() {
+        let (name, color) = if *from == HOST_ID {
+            ("host", HOST_CHAT_COLOR)
+        } else {
+            ("guest", GUEST_CHAT_COLOR)
+        };
+        let line = format!("{name}: {text}");
+        let y = pitch_bottom_line - 4.0 - line_height * i as f64;
+        ctx.set_fill_style_str(color);
+        ctx.fill_text(&line, pitch_left_line + 4.0, y).unwrap();
+    }
+    ctx.restore();
+}
@@ -1,17 +1,47 @@
-use crate::game::constants::{PITCH_LEFT_LINE, PITCH_RIGHT_LINE, PLAYER_DIAMETER, STADIUM_HEIGHT};
-use crate::game::input::PlayerInput;
+use crate::game::constants::{
+    CHAT_HISTORY, DELTA_THRESHOLD, KEYFRAME_INTERVAL, LAYOUT_VERSION, MAX_CHAT_LENGTH, RESET_TIME,
+};
+use crate::game::input::{InputBatch, PlayerInput};
+use crate::game::{ArenaConfig, Movement, PitchProfile};
 use rapier2d::dynamics::{RigidBodyHandle, RigidBodySet};
 use rapier2d::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use wasm_peers::text::clamp_text;
+use wasm_peers::{SessionId, UserId};
 
+/// Author of the chat messages of the host.
+pub(crate) const HOST_ID: UserId = UserId(1);
+/// Author of the chat messages of the client.
+pub(crate) const CLIENT_ID: UserId = UserId(2);
+
+/// Angle in degrees of the direction from the first point to the second,
+/// `0.0` if the points coincide.
 pub fn angle(x1: f32, y1: f32, x2: f32, y2: f32) -> f32 {
     const RADIAN: f32 = 180.0 / std::f32::consts::PI;
     let dx = x2 - x1;
     let dy = y2 - y1;
     let dist = f32::sqrt(dx * dx + dy * dy);
+    if dist <= f32::EPSILON {
+        return 0.0;
+    }
     RADIAN * (dx / dist).acos() * num::signum(dy)
 }
 
+/// Scale the velocity of `rigid_body` down to `top_speed` if it goes faster.
+/// A velocity that is not finite anymore is reset to zero.
+pub fn limit_speed(rigid_body: &mut RigidBody, top_speed: f32) {
+    let velocity = *rigid_body.linvel();
+    if !velocity.x.is_finite() || !velocity.y.is_finite() {
+        rigid_body.set_linvel(vector![0.0, 0.0], true);
+        return;
+    }
+    let speed = velocity.norm();
+    if speed > top_speed && speed > f32::EPSILON {
+        rigid_body.set_linvel(velocity * (top_speed / speed), true);
+    }
+}
+
 #[derive(Clone)]
 pub struct Player {
     pub rigid_body_handle: RigidBodyHandle,
@@ -65,25 +95,34 @@ impl Player {
     pub fn reset_position(
         &mut self,
         rigid_body_set: &mut RigidBodySet,
+        arbiter: &Arbiter,
         x_offset: f32,
         y_offset: f32,
     ) {
-        let mut x = x_offset;
-        let mut y = y_offset;
-        if self.red {
-            x += PITCH_LEFT_LINE + 2.0 * PLAYER_DIAMETER;
-            y += STADIUM_HEIGHT / 2.0
-        } else {
-            x += PITCH_RIGHT_LINE - 2.0 * PLAYER_DIAMETER;
-            y += STADIUM_HEIGHT / 2.0
-        }
+        let (x, y) = spawn_position(&arbiter.arena, arbiter.defends_left(self.red));
+        let (x, y) = (x + x_offset, y + y_offset);
         let player_body = &mut rigid_body_set[self.rigid_body_handle];
         player_body.set_position(Isometry::new(vector![x, y], 0.0), false);
         player_body.set_linvel(vector![0.0, 0.0], false);
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+/// Kick-off position of a player defending the left or the right goal.
+pub fn spawn_position(arena: &ArenaConfig, defends_left: bool) -> (f32, f32) {
+    if defends_left {
+        (
+            arena.left_line() + 2.0 * arena.player_diameter(),
+            arena.stadium_height() / 2.0,
+        )
+    } else {
+        (
+            arena.right_line() - 2.0 * arena.player_diameter(),
+            arena.stadium_height() / 2.0,
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Circle {
     pub x: f32,
     pub y: f32,
@@ -104,7 +143,7 @@ impl Circle {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Edge {
     pub(crate) x: f32,
     pub(crate) y: f32,
@@ -125,7 +164,7 @@ impl Edge {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Score {
     pub(crate) red_score: u32,
     pub(crate) blue_score: u32,
@@ -146,7 +185,18 @@ pub struct Arbiter {
     pub blue_score: u32,
     pub send_score_message: bool,
     pub reset_timer: u32,
-    pub game_ended: bool,
+    /// Whether the red team defends the left goal, teams switch ends between games of a series.
+    pub red_on_left: bool,
+    /// Team which scored last, `Some(true)` for red. Unlike `red_scored` and `blue_scored`,
+    /// it is kept once the game is reset after the goal.
+    pub last_scorer: Option<bool>,
+    /// Whether the ball crossed a goal line and did not come back on the pitch since,
+    /// so that the crossing is only awarded once.
+    pub ball_in_goal: bool,
+    /// Where the goal lines are.
+    pub arena: ArenaConfig,
+    /// Source of every random choice of the game, which the client can reproduce from its seed.
+    pub rng: GameRng,
 }
 
 impl Arbiter {
@@ -158,16 +208,51 @@ impl Arbiter {
             blue_score: 0,
             send_score_message: false,
             reset_timer: 0,
-            game_ended: false,
+            red_on_left: true,
+            last_scorer: None,
+            ball_in_goal: false,
+            arena: ArenaConfig::default(),
+            rng: GameRng::new(0),
+        }
+    }
+    pub fn defends_left(&self, red: bool) -> bool {
+        red == self.red_on_left
+    }
+    /// Award a goal if the whole ball, centred at `ball_x`, crossed one of the goal lines.
+    /// A ball on the line doesn't count, and a crossing only scores once: the ball has to come
+    /// back on the pitch before it can score again.
+    pub fn check_goal(&mut self, ball_x: f32) -> bool {
+        let (left_line, right_line) = (self.arena.left_line(), self.arena.right_line());
+        let in_left_goal = if ball_x < left_line - self.arena.ball_radius {
+            true
+        } else if ball_x > right_line + self.arena.ball_radius {
+            false
+        } else {
+            if (left_line..=right_line).contains(&ball_x) {
+                self.ball_in_goal = false;
+            }
+            return false;
+        };
+        if std::mem::replace(&mut self.ball_in_goal, true) {
+            return false;
+        }
+        // the team defending the goal the ball is in concedes
+        if self.defends_left(true) == in_left_goal {
+            self.set_blue_scored();
+        } else {
+            self.set_red_scored();
         }
+        true
     }
     pub fn set_red_scored(&mut self) {
         self.red_scored = true;
+        self.last_scorer = Some(true);
         self.red_score += 1;
         self.send_score_message = true;
     }
     pub fn set_blue_scored(&mut self) {
         self.blue_scored = true;
+        self.last_scorer = Some(false);
         self.blue_score += 1;
         self.send_score_message = true;
     }
@@ -177,21 +262,750 @@ impl Arbiter {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// Deterministic pseudo-random numbers (SplitMix64), the same sequence on both peers
+/// for the same seed, unlike the random number generator of the browser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameRng {
+    seed: u64,
+    state: u64,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        GameRng { seed, state: seed }
+    }
+
+    /// Seed of the session, so that both players derive the same one without agreeing on it.
+    pub fn for_session(session_id: SessionId) -> Self {
+        let id = session_id.get();
+        GameRng::new(id as u64 ^ (id >> 64) as u64)
+    }
+
+    /// Seed the numbers are drawn from, sent to the client in [Message::GameInit].
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Number in `low..high`.
+    // for the randomized elements to come, like power-ups
+    #[allow(dead_code)]
+    pub fn range(&mut self, low: f32, high: f32) -> f32 {
+        // the 24 high bits, as many as the mantissa of an f32 holds
+        let unit = (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+        low + unit * (high - low)
+    }
+}
+
+/// Opacity of the goal banner with `time_left` ticks before the game is reset:
+/// opaque for the first half of [RESET_TIME], then fading out until the reset.
+pub(crate) fn celebration_opacity(time_left: u32) -> f64 {
+    let half = RESET_TIME as f64 / 2.0;
+    (time_left as f64 / half).min(1.0)
+}
+
+/// Game state sent by the host, and chat messages sent by either player.
+/// Both players must run the same version, a message of a variant unknown to one of them
+/// fails to decode, and is logged and dropped by the network manager.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum Message {
     GameInit {
+        /// [LAYOUT_VERSION] of the host, which the client checks against its own.
+        /// The dimensions of the pitch are those of `arena`.
+        layout: u32,
+        arena: ArenaConfig,
+        /// Seed of the [GameRng] of the host.
+        seed: u64,
         edges: Vec<Edge>,
         goal_posts: Vec<Circle>,
         players: Vec<Circle>,
         ball: Circle,
+        pitch: PitchProfile,
+        movement: Movement,
+        /// Score so far, for a client joining the game again to catch up with it.
+        score: Score,
     },
+    /// Keyframe holding the whole state, sent every [KEYFRAME_INTERVAL] updates on the unordered
+    /// channel. `sequence` tells the latest state apart, and identifies the keyframe.
     GameState {
+        sequence: u32,
         players: Vec<Circle>,
         ball: Circle,
     },
+    /// Sent between keyframes, with the positions of the entities which moved away from the ones
+    /// of the keyframe numbered `keyframe`, players being referred to by their index.
+    GameDelta {
+        sequence: u32,
+        keyframe: u32,
+        players: Vec<(u8, Position)>,
+        ball: Option<Position>,
+    },
+    /// Sent by the client when it misses the keyframe of a delta, for the host to send one right away.
+    KeyframeRequest,
     GoalScored {
         red_scored: bool,
         score: Score,
     },
     GameEnded,
+    Chat {
+        from: UserId,
+        text: String,
+    },
+    /// Sent by the client once it applied [Message::GameInit], which the host repeats until then.
+    Ready,
+}
+
+impl Message {
+    /// Layout version of a [Message::GameInit] sent by a build with another [LAYOUT_VERSION].
+    pub fn layout_mismatch(&self) -> Option<u32> {
+        match self {
+            Message::GameInit { layout, .. } if *layout != LAYOUT_VERSION => Some(*layout),
+            _ => None,
+        }
+    }
+}
+
+/// Position of an entity in a [Message::GameDelta].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Position {
+    fn of(circle: &Circle) -> Self {
+        Position {
+            x: circle.x,
+            y: circle.y,
+        }
+    }
+
+    fn moved_from(self, circle: &Circle) -> bool {
+        (self.x - circle.x).abs() > DELTA_THRESHOLD || (self.y - circle.y).abs() > DELTA_THRESHOLD
+    }
+
+    fn apply(self, circle: &mut Circle) {
+        circle.x = self.x;
+        circle.y = self.y;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Keyframe {
+    sequence: u32,
+    players: Vec<Circle>,
+    ball: Circle,
+}
+
+/// Turns the states sent by the host into keyframes and the deltas from the last one.
+#[derive(Debug, Default)]
+pub(crate) struct DeltaEncoder {
+    keyframe: Option<Keyframe>,
+    since_keyframe: u32,
+}
+
+impl DeltaEncoder {
+    /// A keyframe when one is due, otherwise the delta from the last keyframe.
+    pub(crate) fn encode(&mut self, sequence: u32, players: Vec<Circle>, ball: Circle) -> Message {
+        let keyframe = match &self.keyframe {
+            Some(keyframe)
+                if self.since_keyframe < KEYFRAME_INTERVAL
+                    && keyframe.players.len() == players.len() =>
+            {
+                keyframe
+            }
+            _ => {
+                self.since_keyframe = 0;
+                self.keyframe = Some(Keyframe {
+                    sequence,
+                    players: players.clone(),
+                    ball: ball.clone(),
+                });
+                return Message::GameState {
+                    sequence,
+                    players,
+                    ball,
+                };
+            }
+        };
+        let moved = |circle: &Circle, before: &Circle| {
+            let position = Position::of(circle);
+            position.moved_from(before).then_some(position)
+        };
+        let message = Message::GameDelta {
+            sequence,
+            keyframe: keyframe.sequence,
+            players: players
+                .iter()
+                .zip(&keyframe.players)
+                .enumerate()
+                .filter_map(|(index, (player, before))| Some((index as u8, moved(player, before)?)))
+                .collect(),
+            ball: moved(&ball, &keyframe.ball),
+        };
+        self.since_keyframe += 1;
+        message
+    }
+
+    /// Send a keyframe next, for a client which missed the last one.
+    pub(crate) fn request_keyframe(&mut self) {
+        self.keyframe = None;
+    }
+}
+
+/// Applies the deltas received by the client onto the latest keyframe.
+#[derive(Debug, Default)]
+pub(crate) struct DeltaDecoder {
+    keyframe: Option<Keyframe>,
+    keyframe_order: StateOrder,
+    /// Keyframe last asked for, not to ask again for each of its deltas
+    requested: Option<u32>,
+}
+
+impl DeltaDecoder {
+    /// Keep the keyframe unless a newer one arrived first, deltas being applied onto it.
+    pub(crate) fn keyframe(&mut self, sequence: u32, players: &[Circle], ball: &Circle) {
+        if self.keyframe_order.accept(sequence) {
+            self.keyframe = Some(Keyframe {
+                sequence,
+                players: players.to_vec(),
+                ball: ball.clone(),
+            });
+        }
+    }
+
+    /// Players and ball after a delta from the keyframe numbered `keyframe`,
+    /// `None` if that keyframe is not the latest one received.
+    pub(crate) fn apply(
+        &self,
+        keyframe: u32,
+        players: &[(u8, Position)],
+        ball: Option<Position>,
+    ) -> Option<(Vec<Circle>, Circle)> {
+        let mut state = self
+            .keyframe
+            .clone()
+            .filter(|state| state.sequence == keyframe)?;
+        for (index, position) in players {
+            position.apply(state.players.get_mut(usize::from(*index))?);
+        }
+        if let Some(position) = ball {
+            position.apply(&mut state.ball);
+        }
+        Some((state.players, state.ball))
+    }
+
+    /// Whether to ask the host for a keyframe, missing `keyframe`, only once per keyframe.
+    pub(crate) fn request(&mut self, keyframe: u32) -> bool {
+        let first = self.requested != Some(keyframe);
+        self.requested = Some(keyframe);
+        first
+    }
+}
+
+/// Sequence number of the latest game state applied, to drop the older ones
+/// overtaken by a newer state on the unordered data channel.
+#[derive(Debug, Default)]
+pub(crate) struct StateOrder {
+    latest: Option<u32>,
+}
+
+impl StateOrder {
+    /// Whether the state numbered `sequence` is newer than the latest one, which it then becomes.
+    pub(crate) fn accept(&mut self, sequence: u32) -> bool {
+        let newer = match self.latest {
+            // wrapping comparison, so that the game can outlast the counter
+            Some(latest) => (sequence.wrapping_sub(latest) as i32) > 0,
+            None => true,
+        };
+        if newer {
+            self.latest = Some(sequence);
+        }
+        newer
+    }
+}
+
+/// Text of a chat message as it is sent, without control characters and cut to [MAX_CHAT_LENGTH].
+/// `None` if nothing is left to send.
+pub(crate) fn sanitize_chat(text: &str) -> Option<String> {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    let text = clamp_text(&text, MAX_CHAT_LENGTH).trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Latest chat messages, oldest first.
+#[derive(Debug, Default)]
+pub(crate) struct ChatLog {
+    messages: VecDeque<(UserId, String)>,
+}
+
+impl ChatLog {
+    pub(crate) fn push(&mut self, from: UserId, text: String) {
+        if self.messages.len() == CHAT_HISTORY {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((from, text));
+    }
+
+    pub(crate) fn messages(&self) -> impl DoubleEndedIterator<Item = &(UserId, String)> {
+        self.messages.iter()
+    }
+}
+
+/// Everything sent over the connection, in either direction,
+/// so that both peers always agree on the type of messages.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum GameMessage {
+    /// Input of the other player, sent to the host.
+    Input(PlayerInput),
+    /// Inputs of the other player over several ticks, applied by the host one per tick.
+    InputBatch(InputBatch),
+    /// State of the game, sent by the host.
+    State(Message),
+}
+
+impl From<PlayerInput> for GameMessage {
+    fn from(input: PlayerInput) -> Self {
+        GameMessage::Input(input)
+    }
+}
+
+impl From<Message> for GameMessage {
+    fn from(message: Message) -> Self {
+        GameMessage::State(message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn player_numbers_survive_serialization() {
+        let mut rigid_body_set = RigidBodySet::new();
+        let players: Vec<Player> = [(true, 1), (false, 2)]
+            .into_iter()
+            .map(|(red, number)| {
+                let body = RigidBodyBuilder::new_dynamic()
+                    .translation(vector![10.0, 20.0])
+                    .build();
+                Player::new(rigid_body_set.insert(body), 15.0, red, number)
+            })
+            .collect();
+        let message = Message::GameState {
+            sequence: 0,
+            players: players
+                .iter()
+                .map(|p| p.to_circle(&rigid_body_set))
+                .collect(),
+            ball: Circle::new(0.0, 0.0, 10.0, false, -1),
+        };
+
+        let message = rmp_serde::to_vec(&message).unwrap();
+        let players = match rmp_serde::from_slice(&message).unwrap() {
+            Message::GameState { players, .. } => players,
+            _ => panic!("expected a GameState message"),
+        };
+        let teams_and_numbers: Vec<(bool, i32)> =
+            players.iter().map(|p| (p.red, p.player_number)).collect();
+        assert_eq!(teams_and_numbers, vec![(true, 1), (false, 2)]);
+    }
+
+    #[test]
+    fn zero_velocity_body_stays_finite_through_a_tick() {
+        let mut rigid_body_set = RigidBodySet::new();
+        let mut collider_set = ColliderSet::new();
+        let body = RigidBodyBuilder::new_dynamic()
+            .translation(vector![10.0, 20.0])
+            .build();
+        let handle = rigid_body_set.insert(body);
+        collider_set.insert_with_parent(
+            ColliderBuilder::ball(15.0).build(),
+            handle,
+            &mut rigid_body_set,
+        );
+
+        limit_speed(&mut rigid_body_set[handle], 0.0);
+        assert_eq!(angle(10.0, 20.0, 10.0, 20.0), 0.0);
+        PhysicsPipeline::new().step(
+            &vector![0.0, 0.0],
+            &IntegrationParameters::default(),
+            &mut IslandManager::new(),
+            &mut BroadPhase::new(),
+            &mut NarrowPhase::new(),
+            &mut rigid_body_set,
+            &mut collider_set,
+            &mut JointSet::new(),
+            &mut CCDSolver::new(),
+            &(),
+            &(),
+        );
+        limit_speed(&mut rigid_body_set[handle], 5.0);
+
+        let body = &rigid_body_set[handle];
+        assert!(body.linvel().x.is_finite() && body.linvel().y.is_finite());
+        assert!(body.translation().x.is_finite() && body.translation().y.is_finite());
+    }
+
+    #[test]
+    fn every_game_message_round_trips() {
+        let circle = Circle::new(1.0, 2.0, 3.0, true, 1);
+        let messages = vec![
+            GameMessage::Input(PlayerInput {
+                up: true,
+                down: false,
+                left: true,
+                right: false,
+                shoot: true,
+            }),
+            GameMessage::InputBatch(InputBatch {
+                first_sequence: 7,
+                inputs: vec![PlayerInput::default(); 3],
+            }),
+            GameMessage::State(Message::GameInit {
+                layout: LAYOUT_VERSION,
+                arena: ArenaConfig {
+                    pitch_width: 800.0,
+                    ..ArenaConfig::default()
+                },
+                seed: u64::MAX,
+                edges: vec![Edge::new(10.0, 20.0, 30.0, 40.0, true)],
+                goal_posts: vec![circle.clone()],
+                players: vec![circle.clone()],
+                ball: circle.clone(),
+                pitch: PitchProfile::Slippery,
+                movement: Movement {
+                    acceleration: 1_500.0,
+                    top_speed: 120.0,
+                    damping: 0.5,
+                },
+                score: Score::new(2, 1),
+            }),
+            GameMessage::State(Message::GameState {
+                sequence: u32::MAX,
+                players: vec![circle.clone()],
+                ball: circle,
+            }),
+            GameMessage::State(Message::GameDelta {
+                sequence: 8,
+                keyframe: 3,
+                players: vec![(1, Position { x: 4.0, y: 5.0 })],
+                ball: None,
+            }),
+            GameMessage::State(Message::KeyframeRequest),
+            GameMessage::State(Message::GoalScored {
+                red_scored: true,
+                score: Score::new(2, 1),
+            }),
+            GameMessage::State(Message::GameEnded),
+            GameMessage::State(Message::Chat {
+                from: CLIENT_ID,
+                text: "gg".to_string(),
+            }),
+            GameMessage::State(Message::Ready),
+        ];
+        for message in messages {
+            let encoded = rmp_serde::to_vec(&message).unwrap();
+            let decoded: GameMessage = rmp_serde::from_slice(&encoded).unwrap();
+            assert_eq!(decoded, message);
+        }
+    }
+
+    #[test]
+    fn deltas_rebuild_the_state_from_the_keyframe() {
+        let at = |x| Circle::new(x, 10.0, 15.0, true, 1);
+        let mut encoder = DeltaEncoder::default();
+        let mut decoder = DeltaDecoder::default();
+        let mut receive = |message: Message| match message {
+            Message::GameState {
+                sequence,
+                players,
+                ball,
+            } => {
+                decoder.keyframe(sequence, &players, &ball);
+                Some((players, ball))
+            }
+            Message::GameDelta {
+                keyframe,
+                players,
+                ball,
+                ..
+            } => decoder.apply(keyframe, &players, ball),
+            other => panic!("expected a game state, got {other:?}"),
+        };
+
+        let keyframe = encoder.encode(1, vec![at(0.0), at(50.0)], at(100.0));
+        assert!(matches!(keyframe, Message::GameState { .. }));
+        assert!(receive(keyframe).is_some());
+        let delta = encoder.encode(2, vec![at(0.1), at(60.0)], at(100.0));
+        match &delta {
+            Message::GameDelta { players, ball, .. } => {
+                assert_eq!(players, &[(1, Position { x: 60.0, y: 10.0 })]);
+                assert_eq!(ball, &None);
+            }
+            other => panic!("expected a delta, got {other:?}"),
+        }
+        assert_eq!(receive(delta), Some((vec![at(0.0), at(60.0)], at(100.0))));
+
+        for sequence in 3..KEYFRAME_INTERVAL + 2 {
+            let message = encoder.encode(sequence, vec![at(0.0), at(50.0)], at(100.0));
+            assert!(matches!(message, Message::GameDelta { .. }));
+        }
+        let message = encoder.encode(KEYFRAME_INTERVAL + 2, vec![at(0.0), at(50.0)], at(100.0));
+        assert!(matches!(message, Message::GameState { .. }));
+    }
+
+    #[test]
+    fn missing_keyframe_is_requested_once() {
+        let at = |x| Circle::new(x, 10.0, 10.0, false, 0);
+        let mut encoder = DeltaEncoder::default();
+        let mut decoder = DeltaDecoder::default();
+        // the first keyframe is lost
+        encoder.encode(1, vec![at(0.0)], at(0.0));
+        let (keyframe, players, ball) = match encoder.encode(2, vec![at(5.0)], at(0.0)) {
+            Message::GameDelta {
+                keyframe,
+                players,
+                ball,
+                ..
+            } => (keyframe, players, ball),
+            other => panic!("expected a delta, got {other:?}"),
+        };
+        assert_eq!(decoder.apply(keyframe, &players, ball), None);
+        assert!(decoder.request(keyframe));
+        assert!(!decoder.request(keyframe));
+
+        encoder.request_keyframe();
+        match encoder.encode(3, vec![at(5.0)], at(0.0)) {
+            Message::GameState {
+                sequence,
+                players,
+                ball,
+            } => decoder.keyframe(sequence, &players, &ball),
+            other => panic!("expected a keyframe, got {other:?}"),
+        }
+        // a keyframe overtaken by a newer one is not kept
+        decoder.keyframe(1, &[at(0.0)], &at(0.0));
+        let delta = encoder.encode(4, vec![at(9.0)], at(0.0));
+        match delta {
+            Message::GameDelta {
+                keyframe,
+                players,
+                ball,
+                ..
+            } => assert_eq!(
+                decoder.apply(keyframe, &players, ball),
+                Some((vec![at(9.0)], at(0.0)))
+            ),
+            other => panic!("expected a delta, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn goals_are_awarded_by_the_side_each_team_defends() {
+        let arena = ArenaConfig::default();
+        let in_left_goal = arena.left_line() - arena.ball_radius - 1.0;
+        let mut arbiter = Arbiter::new();
+        assert!(!arbiter.check_goal((arena.left_line() + arena.right_line()) / 2.0));
+        assert!(arbiter.check_goal(in_left_goal));
+        assert_eq!((arbiter.red_score, arbiter.blue_score), (0, 1));
+
+        let mut arbiter = Arbiter {
+            red_on_left: false,
+            ..Arbiter::new()
+        };
+        assert!(arbiter.check_goal(in_left_goal));
+        assert_eq!((arbiter.red_score, arbiter.blue_score), (1, 0));
+        assert!(arbiter.defends_left(false));
+        assert_eq!(
+            spawn_position(&arena, arbiter.defends_left(true)).0,
+            arena.right_line() - 2.0 * arena.player_diameter()
+        );
+    }
+
+    #[test]
+    fn goal_needs_the_whole_ball_over_the_line_and_scores_once() {
+        let mut arbiter = Arbiter::new();
+        let (right_line, ball_radius) = (arbiter.arena.right_line(), arbiter.arena.ball_radius);
+        // straddling the line, then exactly touching it from inside the goal
+        assert!(!arbiter.check_goal(right_line));
+        assert!(!arbiter.check_goal(right_line + ball_radius));
+        assert!(arbiter.check_goal(right_line + ball_radius + 0.1));
+        // bouncing around in the goal, and back onto the line
+        assert!(!arbiter.check_goal(right_line + 2.0 * ball_radius));
+        assert!(!arbiter.check_goal(right_line + ball_radius / 2.0));
+        assert!(!arbiter.check_goal(right_line + 2.0 * ball_radius));
+        assert_eq!((arbiter.red_score, arbiter.blue_score), (1, 0));
+
+        // once back on the pitch, the next crossing counts
+        assert!(!arbiter.check_goal(right_line - ball_radius));
+        assert!(arbiter.check_goal(right_line + 2.0 * ball_radius));
+        assert_eq!((arbiter.red_score, arbiter.blue_score), (2, 0));
+    }
+
+    #[test]
+    fn game_init_of_another_layout_version_is_told_apart() {
+        let game_init = |layout| Message::GameInit {
+            layout,
+            arena: ArenaConfig {
+                pitch_width: 800.0,
+                ..ArenaConfig::default()
+            },
+            seed: 0,
+            edges: Vec::new(),
+            goal_posts: Vec::new(),
+            players: Vec::new(),
+            ball: Circle::new(0.0, 0.0, 1.0, false, 0),
+            pitch: PitchProfile::default(),
+            movement: Movement::default(),
+            score: Score::new(0, 0),
+        };
+        assert_eq!(game_init(LAYOUT_VERSION).layout_mismatch(), None);
+        assert_eq!(
+            game_init(LAYOUT_VERSION + 1).layout_mismatch(),
+            Some(LAYOUT_VERSION + 1)
+        );
+        assert_eq!(Message::Ready.layout_mismatch(), None);
+    }
+
+    #[test]
+    fn goals_follow_the_lines_of_a_custom_arena() {
+        let arena = ArenaConfig {
+            pitch_width: 800.0,
+            ball_radius: 5.0,
+            ..ArenaConfig::default()
+        };
+        assert!(arena.is_valid());
+        let mut arbiter = Arbiter {
+            arena,
+            ..Arbiter::new()
+        };
+        assert!(!arbiter.check_goal(ArenaConfig::default().right_line() + 20.0));
+        assert!(arbiter.check_goal(arena.right_line() + 6.0));
+
+        let too_wide_goals = ArenaConfig {
+            goal_breadth: 400.0,
+            ..ArenaConfig::default()
+        };
+        assert!(!too_wide_goals.is_valid());
+    }
+
+    #[test]
+    fn fast_ball_crossing_the_line_scores_once() {
+        let arena = ArenaConfig::default();
+        let mut rigid_body_set = RigidBodySet::new();
+        let mut collider_set = ColliderSet::new();
+        let body = RigidBodyBuilder::new_dynamic()
+            .translation(vector![
+                arena.left_line() + arena.ball_radius,
+                arena.stadium_height() / 2.0
+            ])
+            .linvel(vector![-600.0, 0.0])
+            .build();
+        let handle = rigid_body_set.insert(body);
+        collider_set.insert_with_parent(
+            ColliderBuilder::ball(arena.ball_radius).build(),
+            handle,
+            &mut rigid_body_set,
+        );
+        let mut island_manager = IslandManager::new();
+        let mut broad_phase = BroadPhase::new();
+        let mut narrow_phase = NarrowPhase::new();
+        let mut joint_set = JointSet::new();
+        let mut ccd_solver = CCDSolver::new();
+
+        let mut arbiter = Arbiter::new();
+        let mut goals = 0;
+        for _ in 0..20 {
+            PhysicsPipeline::new().step(
+                &vector![0.0, 0.0],
+                &IntegrationParameters::default(),
+                &mut island_manager,
+                &mut broad_phase,
+                &mut narrow_phase,
+                &mut rigid_body_set,
+                &mut collider_set,
+                &mut joint_set,
+                &mut ccd_solver,
+                &(),
+                &(),
+            );
+            if arbiter.check_goal(rigid_body_set[handle].translation().x) {
+                goals += 1;
+            }
+        }
+        assert!(rigid_body_set[handle].translation().x < arena.left_line() - arena.ball_radius);
+        assert_eq!(goals, 1);
+    }
+
+    #[test]
+    fn rng_draws_the_same_numbers_from_the_same_seed() {
+        let session_id = SessionId::new(0x0123_4567_89AB_CDEF_0011_2233_4455_6677);
+        let mut host = GameRng::for_session(session_id);
+        let mut client = GameRng::new(host.seed());
+        let draws: Vec<f32> = (0..100).map(|_| host.range(-2.0, 2.0)).collect();
+        assert!(draws.iter().all(|draw| (-2.0..2.0).contains(draw)));
+        assert_eq!(
+            draws,
+            (0..100)
+                .map(|_| client.range(-2.0, 2.0))
+                .collect::<Vec<_>>()
+        );
+        assert_ne!(
+            GameRng::new(1).next_u64(),
+            GameRng::new(2).next_u64(),
+            "different seeds draw the same numbers"
+        );
+    }
+
+    #[test]
+    fn chat_messages_are_sanitized_and_clamped() {
+        assert_eq!(sanitize_chat("  gg\n").as_deref(), Some("gg"));
+        assert_eq!(sanitize_chat("g\u{7}g").as_deref(), Some("gg"));
+        assert_eq!(sanitize_chat(" \t "), None);
+        let long = "é".repeat(2 * MAX_CHAT_LENGTH);
+        assert_eq!(
+            sanitize_chat(&long).unwrap().chars().count(),
+            MAX_CHAT_LENGTH
+        );
+    }
+
+    #[test]
+    fn last_scorer_outlives_the_celebration() {
+        let mut arbiter = Arbiter::new();
+        arbiter.set_blue_scored();
+        arbiter.set_red_scored();
+        arbiter.reset_who_scored();
+        assert!(!arbiter.red_scored);
+        assert_eq!(arbiter.last_scorer, Some(true));
+    }
+
+    #[test]
+    fn goal_banner_fades_out_until_the_reset() {
+        assert_eq!(celebration_opacity(RESET_TIME), 1.0);
+        assert_eq!(celebration_opacity(RESET_TIME / 2), 1.0);
+        assert!(celebration_opacity(RESET_TIME / 4) < 1.0);
+        assert_eq!(celebration_opacity(0), 0.0);
+    }
+
+    #[test]
+    fn states_overtaken_by_newer_ones_are_dropped() {
+        let mut order = StateOrder::default();
+        assert!(order.accept(5));
+        assert!(!order.accept(4), "older state arrived late");
+        assert!(!order.accept(5), "duplicate");
+        assert!(order.accept(7));
+        assert!(!order.accept(6));
+
+        let mut order = StateOrder::default();
+        assert!(order.accept(u32::MAX));
+        assert!(order.accept(0), "counter wrapped around");
+    }
 }
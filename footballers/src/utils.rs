@@ -1,5 +1,22 @@
+use wasm_bindgen::JsValue;
+use wasm_peers::SessionId;
 use web_sys::Window;
 
 pub fn global_window() -> Window {
     web_sys::window().expect("there was no window global object!")
 }
+
+/// Time in milliseconds since the page loaded.
+pub fn now() -> Option<f64> {
+    global_window()
+        .performance()
+        .map(|performance| performance.now())
+}
+
+/// Link to this page joining the session, to share with the other player.
+pub fn session_link(session_id: &SessionId) -> Result<String, JsValue> {
+    let location = global_window().location();
+    let origin = location.origin()?;
+    let pathname = location.pathname()?;
+    Ok(format!("{origin}{pathname}?session_id={session_id}"))
+}
@@ -0,0 +1,122 @@
+//! Measures serializing a game state sent every tick, as footballers does, with a new `Vec` for
+//! each message against a buffer cleared and kept across messages, as the network manager does
+//! for binary messages sent right away. Also counts the allocations each way takes per message.
+//!
+//! Run with `cargo bench -p wasm-peers`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde::Serialize;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts the allocations made, to compare them between the two ways of serializing.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Shaped like the state the footballers host sends each tick.
+#[derive(Serialize)]
+struct GameState {
+    sequence: u32,
+    players: Vec<Player>,
+    ball: Body,
+}
+
+#[derive(Serialize)]
+struct Player {
+    id: u64,
+    body: Body,
+    shooting: bool,
+}
+
+#[derive(Serialize)]
+struct Body {
+    position: (f32, f32),
+    velocity: (f32, f32),
+}
+
+fn game_state(players: u64) -> GameState {
+    let body = |i: u64| Body {
+        position: (i as f32 * 12.5, 300.0 - i as f32),
+        velocity: (0.75, -1.5),
+    };
+    GameState {
+        sequence: 42,
+        players: (0..players)
+            .map(|id| Player {
+                id,
+                body: body(id),
+                shooting: id % 3 == 0,
+            })
+            .collect(),
+        ball: body(players),
+    }
+}
+
+/// Allocations made by `serialize` on average over many calls.
+fn allocations_per_message(mut serialize: impl FnMut()) -> f64 {
+    const MESSAGES: usize = 1000;
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..MESSAGES {
+        serialize();
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / MESSAGES as f64
+}
+
+fn serialization_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize game state");
+    for players in [2, 8, 32] {
+        let state = game_state(players);
+        let mut buffer = Vec::new();
+        let fresh = allocations_per_message(|| {
+            black_box(rmp_serde::to_vec(&state).unwrap());
+        });
+        let reused = allocations_per_message(|| {
+            buffer.clear();
+            rmp_serde::encode::write(&mut buffer, &state).unwrap();
+            black_box(&buffer);
+        });
+        println!(
+            "{players} players, {} bytes: {fresh} allocations per message with a new Vec, {reused} with a reused buffer",
+            buffer.len()
+        );
+
+        group.bench_with_input(BenchmarkId::new("new Vec", players), &state, |b, state| {
+            b.iter(|| black_box(rmp_serde::to_vec(state).unwrap()))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("reused buffer", players),
+            &state,
+            |b, state| {
+                b.iter(|| {
+                    buffer.clear();
+                    rmp_serde::encode::write(&mut buffer, state).unwrap();
+                    black_box(buffer.len())
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, serialization_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,205 @@
+/*!
+Application level acknowledgement of messages sent over the unreliable data channel.
+
+A reliable message is prefixed with a MessagePack `fixext4` value holding its sequence number,
+and is acknowledged by sending back that same value alone. Plain messages are MessagePack
+encodings of the user type, which never start with this extension, so both kinds of messages
+can share the data channel.
+
+The same way, a peer asks the other one to connect to the signaling server again with a lone
+extension value of another type, before renegotiating, and messages sent with redundancy
+are prefixed with a third type, see [crate::redundancy].
+ */
+
+use js_sys::Function;
+use log::debug;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::rc::Rc;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::RtcDataChannel;
+
+/// MessagePack marker of an extension value holding 4 bytes of data.
+const FIXEXT4: u8 = 0xd6;
+/// MessagePack extension type reserved to acknowledgement frames.
+const ACK_EXT_TYPE: u8 = 0x61;
+/// MessagePack extension type reserved to requests to connect to the signaling server again.
+const SIGNALING_EXT_TYPE: u8 = 0x62;
+/// MessagePack extension type reserved to frames repeating the previous messages.
+const REDUNDANT_EXT_TYPE: u8 = 0x63;
+const HEADER_LEN: usize = 6;
+/// How many sequence numbers of received reliable messages are remembered to drop retries.
+const RECEIVED_HISTORY: usize = 64;
+
+/// Delay in milliseconds after which an unacknowledged reliable message is sent again.
+pub(crate) const ACK_TIMEOUT_MS: i32 = 250;
+
+pub(crate) enum Frame<'a> {
+    Plain(&'a [u8]),
+    Reliable(u32, &'a [u8]),
+    Ack(u32),
+    ReopenSignaling,
+    /// Sequence number of the newest message, and the messages, see [crate::redundancy].
+    Redundant(u32, &'a [u8]),
+}
+
+pub(crate) fn parse_frame(frame: &[u8]) -> Frame<'_> {
+    match frame {
+        [FIXEXT4, ACK_EXT_TYPE, rest @ ..] if rest.len() >= 4 => {
+            let sequence = u32::from_be_bytes(rest[..4].try_into().unwrap());
+            if frame.len() == HEADER_LEN {
+                Frame::Ack(sequence)
+            } else {
+                Frame::Reliable(sequence, &frame[HEADER_LEN..])
+            }
+        }
+        [FIXEXT4, SIGNALING_EXT_TYPE, _, _, _, _] => Frame::ReopenSignaling,
+        [FIXEXT4, REDUNDANT_EXT_TYPE, rest @ ..] if rest.len() >= 4 => {
+            let sequence = u32::from_be_bytes(rest[..4].try_into().unwrap());
+            Frame::Redundant(sequence, &frame[HEADER_LEN..])
+        }
+        _ => Frame::Plain(frame),
+    }
+}
+
+pub(crate) fn reopen_signaling_frame() -> Vec<u8> {
+    vec![FIXEXT4, SIGNALING_EXT_TYPE, 0, 0, 0, 0]
+}
+
+pub(crate) fn ack_frame(sequence: u32) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN);
+    frame.extend_from_slice(&[FIXEXT4, ACK_EXT_TYPE]);
+    frame.extend_from_slice(&sequence.to_be_bytes());
+    frame
+}
+
+pub(crate) fn reliable_frame(sequence: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = ack_frame(sequence);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+pub(crate) fn redundant_frame(sequence: u32, body: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + body.len());
+    frame.extend_from_slice(&[FIXEXT4, REDUNDANT_EXT_TYPE]);
+    frame.extend_from_slice(&sequence.to_be_bytes());
+    frame.extend_from_slice(body);
+    frame
+}
+
+/// Bookkeeping of reliable messages, shared between the sending and receiving ends of a peer.
+#[derive(Debug, Default)]
+pub(crate) struct Acknowledgements {
+    next_sequence: u32,
+    /// `resolve` function of the promise of each message waiting for its acknowledgement
+    pending: HashMap<u32, Function>,
+    received: VecDeque<u32>,
+}
+
+impl Acknowledgements {
+    pub(crate) fn next_sequence(&mut self) -> u32 {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        sequence
+    }
+
+    pub(crate) fn wait_for(&mut self, sequence: u32, resolve: Function) {
+        self.pending.insert(sequence, resolve);
+    }
+
+    pub(crate) fn is_pending(&self, sequence: u32) -> bool {
+        self.pending.contains_key(&sequence)
+    }
+
+    pub(crate) fn give_up(&mut self, sequence: u32) {
+        self.pending.remove(&sequence);
+    }
+
+    /// Returns the `resolve` function of the acknowledged message, if it was still waiting.
+    pub(crate) fn acknowledge(&mut self, sequence: u32) -> Option<Function> {
+        self.pending.remove(&sequence)
+    }
+
+    /// Returns `false` if the message was already received, and this is a retry.
+    pub(crate) fn receive(&mut self, sequence: u32) -> bool {
+        if self.received.contains(&sequence) {
+            return false;
+        }
+        if self.received.len() == RECEIVED_HISTORY {
+            self.received.pop_front();
+        }
+        self.received.push_back(sequence);
+        true
+    }
+}
+
+/// Send `frame` again every [ACK_TIMEOUT_MS] until it is acknowledged,
+/// calling `reject` once `retries_left` runs out.
+pub(crate) fn schedule_retry(
+    data_channel: RtcDataChannel,
+    acknowledgements: Rc<RefCell<Acknowledgements>>,
+    sequence: u32,
+    frame: Vec<u8>,
+    retries_left: u32,
+    reject: Function,
+) {
+    let retry = Closure::once_into_js(move || {
+        if !acknowledgements.borrow().is_pending(sequence) {
+            return;
+        }
+        if retries_left == 0 {
+            acknowledgements.borrow_mut().give_up(sequence);
+            let error = JsValue::from_str("reliable message was never acknowledged");
+            let _ = reject.call1(&JsValue::NULL, &error);
+            return;
+        }
+        debug!("resending unacknowledged message {sequence}");
+        let _ = data_channel.send_with_u8_array(&frame);
+        schedule_retry(
+            data_channel,
+            acknowledgements,
+            sequence,
+            frame,
+            retries_left - 1,
+            reject,
+        );
+    });
+    let window = web_sys::window().expect("there was no window global object!");
+    let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+        retry.unchecked_ref(),
+        ACK_TIMEOUT_MS,
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn test_frames_are_told_apart() {
+        let payload = rmp_serde::to_vec("hello").unwrap();
+        assert!(matches!(parse_frame(&payload), Frame::Plain(p) if p == payload));
+        assert!(matches!(parse_frame(&ack_frame(42)), Frame::Ack(42)));
+        assert!(matches!(
+            parse_frame(&reliable_frame(7, &payload)),
+            Frame::Reliable(7, p) if p == payload
+        ));
+        assert!(matches!(
+            parse_frame(&reopen_signaling_frame()),
+            Frame::ReopenSignaling
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_retries_are_received_once() {
+        let mut acknowledgements = Acknowledgements::default();
+        assert!(acknowledgements.receive(3));
+        assert!(!acknowledgements.receive(3));
+        assert!(acknowledgements.receive(4));
+    }
+}
@@ -1,103 +1,662 @@
-use crate::one_to_one::NetworkManager;
+use crate::acknowledgement::{self, Acknowledgements, Frame};
+use crate::encryption::Encryption;
+use crate::error::WebRtcError;
+use crate::one_to_one::{close_data_channel, NetworkManagerInner};
+use crate::outbox::{Outbox, LOW_BUFFERED_AMOUNT, MAX_BUFFERED_AMOUNT};
+use crate::peer_connection::PendingCandidates;
+use crate::redundancy::RedundantReceiver;
+use crate::signaling::JoinRetry;
+use crate::signaling::Signaling;
+use crate::transport::{Payload, Transport};
+use crate::utils::{DtlsFingerprint, PeerState};
 use crate::websocket_handler;
-use js_sys::Uint8Array;
+use futures_channel::oneshot;
+use js_sys::{ArrayBuffer, Date, Uint8Array};
 use log::{debug, error, info};
-use serde::de::DeserializeOwned;
+use serde::de::value::Error as ValueError;
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::rc::{Rc, Weak};
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::{JsCast, JsValue};
-use wasm_peers_protocol::one_to_one::{IceCandidate, SignalMessage};
-use wasm_peers_protocol::SessionId;
+use wasm_peers_protocol::one_to_one::{CandidateType, IceCandidate, SignalMessage};
+use wasm_peers_protocol::{SessionId, UserId};
 use web_sys::{
     MessageEvent, RtcDataChannel, RtcDataChannelEvent, RtcPeerConnection,
     RtcPeerConnectionIceEvent, WebSocket,
 };
 
+/// JS event handlers set up by a network manager.
+///
+/// They are kept here instead of being forgotten, so that they are freed with the manager.
+#[derive(Default)]
+pub(crate) struct Closures(Vec<Box<dyn Any>>);
+
+impl Closures {
+    pub(crate) fn keep<T: ?Sized + 'static>(&mut self, closure: Closure<T>) {
+        self.0.push(Box::new(closure));
+    }
+
+    pub(crate) fn append(&mut self, mut other: Closures) {
+        self.0.append(&mut other.0);
+    }
+
+    fn keep_window_listener(&mut self, listener: WindowListener) {
+        self.0.push(Box::new(listener));
+    }
+}
+
+/// Handler of an event of the window, which outlives the network manager,
+/// so it is removed from the window when freed.
+struct WindowListener {
+    event: &'static str,
+    closure: Closure<dyn FnMut()>,
+}
+
+impl Drop for WindowListener {
+    fn drop(&mut self) {
+        if let Some(window) = web_sys::window() {
+            let _ = window.remove_event_listener_with_callback(
+                self.event,
+                self.closure.as_ref().unchecked_ref(),
+            );
+        }
+    }
+}
+
+impl Debug for Closures {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Closures({})", self.0.len())
+    }
+}
+
+type Callback = Box<dyn FnMut()>;
+
+/// Callback run when the connection with the other peer is lost, shared with the data channel handlers.
+#[derive(Clone, Default)]
+pub(crate) struct DisconnectHook {
+    callback: Rc<RefCell<Option<Callback>>>,
+    fired: Rc<Cell<bool>>,
+}
+
+impl DisconnectHook {
+    pub(crate) fn set(&self, callback: impl FnMut() + 'static) {
+        *self.callback.borrow_mut() = Some(Box::new(callback));
+    }
+
+    /// Runs the callback again on the next disconnection, once the data channel is replaced.
+    pub(crate) fn rearm(&self) {
+        self.fired.set(false);
+    }
+
+    /// Runs the callback the first time only, as both data channels close together.
+    fn call(&self) {
+        if self.fired.replace(true) {
+            return;
+        }
+        // taken out so that the callback can replace itself
+        let callback = self.callback.borrow_mut().take();
+        if let Some(mut callback) = callback {
+            callback();
+            self.callback.borrow_mut().get_or_insert(callback);
+        }
+    }
+}
+
+type StateCallback = Box<dyn FnMut(PeerState)>;
+
+/// Callback run on each change of the state of the peer connection.
+#[derive(Clone, Default)]
+pub(crate) struct StateHook(Rc<RefCell<Option<StateCallback>>>);
+
+impl StateHook {
+    pub(crate) fn set(&self, callback: impl FnMut(PeerState) + 'static) {
+        *self.0.borrow_mut() = Some(Box::new(callback));
+    }
+
+    fn call(&self, state: PeerState) {
+        // taken out so that the callback can replace itself
+        let callback = self.0.borrow_mut().take();
+        if let Some(mut callback) = callback {
+            callback(state);
+            self.0.borrow_mut().get_or_insert(callback);
+        }
+    }
+}
+
+impl Debug for StateHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StateHook")
+            .field("set", &self.0.borrow().is_some())
+            .finish()
+    }
+}
+
+type NetworkCallback = Box<dyn FnMut(bool)>;
+
+/// Callback run when the browser goes offline, with `false`, and back online, with `true`.
+#[derive(Clone, Default)]
+pub(crate) struct NetworkHook(Rc<RefCell<Option<NetworkCallback>>>);
+
+impl NetworkHook {
+    pub(crate) fn set(&self, callback: impl FnMut(bool) + 'static) {
+        *self.0.borrow_mut() = Some(Box::new(callback));
+    }
+
+    fn call(&self, online: bool) {
+        // taken out so that the callback can replace itself
+        let callback = self.0.borrow_mut().take();
+        if let Some(mut callback) = callback {
+            callback(online);
+            self.0.borrow_mut().get_or_insert(callback);
+        }
+    }
+}
+
+impl Debug for NetworkHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NetworkHook")
+            .field("set", &self.0.borrow().is_some())
+            .finish()
+    }
+}
+
+type SignalCallback = Box<dyn FnMut(&SignalMessage)>;
+
+/// Callback run on each message received from the signaling server, before it is handled.
+#[derive(Clone, Default)]
+pub(crate) struct SignalHook(Rc<RefCell<Option<SignalCallback>>>);
+
+impl SignalHook {
+    pub(crate) fn set(&self, callback: impl FnMut(&SignalMessage) + 'static) {
+        *self.0.borrow_mut() = Some(Box::new(callback));
+    }
+
+    pub(crate) fn call(&self, message: &SignalMessage) {
+        // taken out so that the callback can replace itself
+        let callback = self.0.borrow_mut().take();
+        if let Some(mut callback) = callback {
+            callback(message);
+            self.0.borrow_mut().get_or_insert(callback);
+        }
+    }
+}
+
+impl Debug for SignalHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SignalHook")
+            .field("set", &self.0.borrow().is_some())
+            .finish()
+    }
+}
+
+/// Creates the data channel of the session owner, unless there is one already.
+/// Only the owner creates it, before its offer, the other peer receiving it,
+/// see [set_peer_connection_on_data_channel].
+#[derive(Clone)]
+pub(crate) struct InitialChannel(Rc<dyn Fn()>);
+
+impl InitialChannel {
+    pub(crate) fn new(open: impl Fn() + 'static) -> Self {
+        InitialChannel(Rc::new(open))
+    }
+
+    pub(crate) fn open(&self) {
+        (self.0)()
+    }
+}
+
+/// Restarts ICE when the connection fails, up to `attempts` times in a row.
+/// Only the session owner restarts, the other peer answers the new offer as usual.
+/// Also releases the connection to the signaling server once connected, unless kept open.
+#[derive(Clone)]
+pub(crate) struct IceRestart {
+    pub(crate) attempts: u8,
+    pub(crate) remaining: Rc<Cell<u8>>,
+    /// Set from the start of a restart until its candidates are checked, the connection
+    /// failing meanwhile being the failure it restarts from
+    pub(crate) restarting: Rc<Cell<bool>>,
+    pub(crate) signaling: Signaling,
+    pub(crate) session_id: SessionId,
+    pub(crate) compression: Rc<Cell<bool>>,
+    pub(crate) session_owner: Rc<Cell<Option<bool>>>,
+    pub(crate) sdp_transform: SdpTransform,
+}
+
+impl IceRestart {
+    fn on_state_change(&self, peer_connection: &RtcPeerConnection, state: PeerState) {
+        match state {
+            PeerState::Connected => {
+                self.restarting.set(false);
+                self.remaining.set(self.attempts);
+                self.signaling.release_if_settled(peer_connection);
+            }
+            PeerState::Connecting | PeerState::Closed => self.restarting.set(false),
+            PeerState::Failed if self.session_owner.get() == Some(true) => {
+                self.restart(peer_connection)
+            }
+            // to receive the offer restarting ICE
+            PeerState::Failed if self.attempts > 0 => self.reopen_signaling(),
+            _ => {}
+        }
+    }
+
+    /// The network is back, restart ICE right away if the connection dropped meanwhile,
+    /// rather than wait for the browser to notice it failed.
+    fn on_network_back(&self, peer_connection: &RtcPeerConnection) {
+        let state = PeerState::from_ice_connection_state(peer_connection.ice_connection_state());
+        if matches!(state, PeerState::Disconnected | PeerState::Failed) {
+            self.on_state_change(peer_connection, PeerState::Failed);
+        }
+    }
+
+    fn restart(&self, peer_connection: &RtcPeerConnection) {
+        if self.restarting.get() {
+            debug!("ICE is already restarting");
+            return;
+        }
+        let remaining = self.remaining.get();
+        if remaining == 0 {
+            if self.attempts > 0 {
+                error!("connection failed after {} ICE restarts", self.attempts);
+            }
+            return;
+        }
+        self.remaining.set(remaining - 1);
+        self.restarting.set(true);
+        info!(
+            "connection failed, restarting ICE, {} attempts left",
+            remaining - 1
+        );
+        if self.signaling.is_released() {
+            // both peers offer once the server sees them again, restarting ICE
+            if let Err(error) = self.signaling.reopen() {
+                error!("failed to reconnect to the signaling server: {error:?}");
+                self.restarting.set(false);
+            }
+            return;
+        }
+        let peer_connection = peer_connection.clone();
+        let ice_restart = self.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            websocket_handler::send_sdp_offer(
+                &peer_connection,
+                &ice_restart.signaling,
+                ice_restart.session_id,
+                ice_restart.compression.get(),
+                &ice_restart.sdp_transform,
+                true,
+            )
+            .await
+            .unwrap_or_else(|error| {
+                error!("failed to restart ICE: {error:?}");
+                ice_restart.restarting.set(false);
+            });
+        });
+    }
+
+    fn reopen_signaling(&self) {
+        if let Err(error) = self.signaling.reopen() {
+            error!("failed to reconnect to the signaling server: {error:?}");
+        }
+    }
+}
+
+type OpenWaiter = Rc<RefCell<Option<oneshot::Sender<Result<(), WebRtcError>>>>>;
+
+/// Outcome of the opening of the data channel, shared with the data channel handlers
+/// and awaited through [crate::one_to_one::NetworkManager::connected].
+#[derive(Clone, Default)]
+pub(crate) struct OpenHook(Rc<RefCell<OpenState>>);
+
+#[derive(Default)]
+struct OpenState {
+    outcome: Option<Result<(), WebRtcError>>,
+    waiting: Vec<OpenWaiter>,
+}
+
+impl OpenHook {
+    /// Receives the outcome once known, or [WebRtcError::Timeout] after `timeout_ms`.
+    pub(crate) fn wait(&self, timeout_ms: i32) -> oneshot::Receiver<Result<(), WebRtcError>> {
+        let (sender, receiver) = oneshot::channel();
+        if let Some(outcome) = self.0.borrow().outcome.clone() {
+            let _ = sender.send(outcome);
+            return receiver;
+        }
+        let waiter = Rc::new(RefCell::new(Some(sender)));
+        self.0.borrow_mut().waiting.push(waiter.clone());
+        let timeout = Closure::once_into_js(move || {
+            if let Some(sender) = waiter.borrow_mut().take() {
+                let _ = sender.send(Err(WebRtcError::Timeout));
+            }
+        });
+        let window = web_sys::window().expect("there was no window global object!");
+        let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+            timeout.unchecked_ref(),
+            timeout_ms,
+        );
+        receiver
+    }
+
+    /// Only the first outcome counts, a channel closing after it opened is left to [DisconnectHook].
+    pub(crate) fn settle(&self, outcome: Result<(), WebRtcError>) {
+        let waiting = {
+            let mut state = self.0.borrow_mut();
+            if state.outcome.is_some() {
+                return;
+            }
+            state.outcome = Some(outcome.clone());
+            std::mem::take(&mut state.waiting)
+        };
+        for waiter in waiting {
+            if let Some(sender) = waiter.borrow_mut().take() {
+                let _ = sender.send(outcome.clone());
+            }
+        }
+    }
+}
+
+impl Debug for OpenHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OpenHook")
+            .field("outcome", &self.0.borrow().outcome)
+            .finish()
+    }
+}
+
+type Verifier = Box<dyn FnMut(&DtlsFingerprint) -> bool>;
+
+/// Check of the DTLS fingerprint in the SDP of the other peer, shared with the websocket handler.
+#[derive(Clone, Default)]
+pub(crate) struct FingerprintCheck(Rc<RefCell<Option<Verifier>>>);
+
+impl FingerprintCheck {
+    pub(crate) fn set(&self, verifier: impl FnMut(&DtlsFingerprint) -> bool + 'static) {
+        *self.0.borrow_mut() = Some(Box::new(verifier));
+    }
+
+    /// Whether `sdp` may be applied. Always when no check is set, never without a fingerprint otherwise.
+    pub(crate) fn accepts(&self, sdp: &str) -> bool {
+        let mut verifier = self.0.borrow_mut();
+        let verifier = match verifier.as_mut() {
+            Some(verifier) => verifier,
+            None => return true,
+        };
+        match DtlsFingerprint::from_sdp(sdp) {
+            Some(fingerprint) => verifier(&fingerprint),
+            None => {
+                error!("the SDP of the other peer has no DTLS fingerprint to verify");
+                false
+            }
+        }
+    }
+}
+
+impl Debug for FingerprintCheck {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FingerprintCheck")
+            .field("set", &self.0.borrow().is_some())
+            .finish()
+    }
+}
+
+type Transform = Box<dyn Fn(String) -> String>;
+
+/// Edit of the local SDP before it is set and sent to the other peer, shared with the websocket handler.
+#[derive(Clone, Default)]
+pub(crate) struct SdpTransform(Rc<RefCell<Option<Transform>>>);
+
+impl SdpTransform {
+    pub(crate) fn set(&self, transform: impl Fn(String) -> String + 'static) {
+        *self.0.borrow_mut() = Some(Box::new(transform));
+    }
+
+    /// `sdp` as edited by the transform, unchanged when none is set.
+    pub(crate) fn apply(&self, sdp: String) -> String {
+        match self.0.borrow().as_ref() {
+            Some(transform) => transform(sdp),
+            None => sdp,
+        }
+    }
+}
+
+impl Debug for SdpTransform {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SdpTransform")
+            .field("set", &self.0.borrow().is_some())
+            .finish()
+    }
+}
+
+type RttCallback = Box<dyn FnOnce(f64)>;
+
+/// Time in milliseconds after which a ping is given up on, its pong being lost.
+const PING_TIMEOUT_MS: f64 = 10_000.0;
+
+/// Pings sent to the other peer through the signaling server, waiting for their pong.
+#[derive(Clone, Default)]
+pub(crate) struct Pings(Rc<RefCell<PingState>>);
+
+#[derive(Default)]
+struct PingState {
+    next_token: u64,
+    /// Time each ping was sent at, and the callback to give the round trip time to
+    pending: HashMap<u64, (f64, RttCallback)>,
+}
+
+impl Pings {
+    /// Token of a new ping, whose round trip time is given to `on_rtt` once it is answered.
+    pub(crate) fn start(&self, on_rtt: impl FnOnce(f64) + 'static) -> u64 {
+        let now = Date::now();
+        let mut state = self.0.borrow_mut();
+        state.expire(now);
+        let token = state.next_token;
+        state.next_token = token.wrapping_add(1);
+        state.pending.insert(token, (now, Box::new(on_rtt)));
+        token
+    }
+
+    pub(crate) fn cancel(&self, token: u64) {
+        self.0.borrow_mut().pending.remove(&token);
+    }
+
+    pub(crate) fn pong(&self, token: u64) {
+        // taken out so that the callback can send another ping
+        let pending = self.0.borrow_mut().pending.remove(&token);
+        match pending {
+            Some((sent_at, on_rtt)) => on_rtt(Date::now() - sent_at),
+            None => debug!("dropping pong {token} which was not pinged"),
+        }
+    }
+}
+
+impl PingState {
+    /// Forget the pings sent [PING_TIMEOUT_MS] before `now`, so that lost pongs don't pile up.
+    fn expire(&mut self, now: f64) {
+        self.pending
+            .retain(|_, (sent_at, _)| now - *sent_at < PING_TIMEOUT_MS);
+    }
+}
+
+impl Debug for Pings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pings")
+            .field("pending", &self.0.borrow().pending.len())
+            .finish()
+    }
+}
+
+impl Debug for DisconnectHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DisconnectHook")
+            .field("fired", &self.fired.get())
+            .finish()
+    }
+}
+
 /// also calls:
 /// * set_data_channel_on_open
 /// * set_data_channel_on_message
 /// * set_data_channel_on_error
-pub(crate) fn set_peer_connection_on_data_channel<T: DeserializeOwned>(
+/// * set_data_channel_on_close
+pub(crate) fn set_peer_connection_on_data_channel(
     peer_connection: &RtcPeerConnection,
-    network_manager: NetworkManager,
+    network_manager: Weak<RefCell<NetworkManagerInner>>,
     on_open_callback: impl FnMut() + Clone + 'static,
-    on_message_callback: impl FnMut(T) + Clone + 'static,
+    closures: &mut Closures,
 ) {
     let on_datachannel = Closure::wrap(Box::new(move |data_channel_event: RtcDataChannelEvent| {
         info!("received data channel");
+        let network_manager = match network_manager.upgrade() {
+            Some(network_manager) => network_manager,
+            None => return,
+        };
         let data_channel = data_channel_event.channel();
+        let on_disconnect = network_manager.borrow().on_disconnect.clone();
+        let open_hook = network_manager.borrow().open_hook.clone();
+        let outbox = network_manager.borrow().outbox.clone();
 
-        set_data_channel_on_open(&data_channel, on_open_callback.clone());
-        set_data_channel_on_error(&data_channel);
-        set_data_channel_on_message(&data_channel, on_message_callback.clone());
+        let mut closures = Closures::default();
+        set_data_channel_on_open(
+            &data_channel,
+            open_hook.clone(),
+            on_open_callback.clone(),
+            &mut closures,
+        );
+        set_data_channel_on_error(&data_channel, open_hook.clone(), &mut closures);
+        set_data_channel_on_close(&data_channel, open_hook, on_disconnect, &mut closures);
+        set_data_channel_on_buffered_amount_low(&data_channel, outbox, &mut closures);
 
-        network_manager.inner.borrow_mut().data_channel = Some(data_channel);
+        let mut network_manager = network_manager.borrow_mut();
+        network_manager.receive_on(&data_channel);
+        // the other peer may have replaced the channel after it closed
+        if let Some(previous) = network_manager.data_channel.replace(data_channel) {
+            close_data_channel(&previous);
+        }
+        network_manager.on_disconnect.rearm();
+        network_manager.channel_closures = closures;
     }) as Box<dyn FnMut(RtcDataChannelEvent)>);
     peer_connection.set_ondatachannel(Some(on_datachannel.as_ref().unchecked_ref()));
-    on_datachannel.forget();
+    closures.keep(on_datachannel);
 }
 
 /// handle message sent by signaling server
-pub(crate) fn set_websocket_on_message(websocket: &WebSocket, peer_connection: RtcPeerConnection) {
-    let websocket_clone = websocket.clone();
+pub(crate) fn set_websocket_on_message(
+    websocket: &WebSocket,
+    mut on_signal_message: impl FnMut(SignalMessage) + 'static,
+    closures: &mut Closures,
+) {
     let onmessage_callback = Closure::wrap(Box::new(move |ev: MessageEvent| {
-        let message = match ev.data().dyn_into::<Uint8Array>() {
-            Ok(message) => message.to_vec(),
-
+        let message = match ev.data().dyn_into::<ArrayBuffer>() {
+            Ok(message) => Uint8Array::new(&message).to_vec(),
             Err(_) => {
                 error!("failed to deserialize onmessage callback content.");
                 return;
             }
         };
-        let message = match rmp_serde::from_slice(&message) {
+        let message: SignalMessage = match rmp_serde::from_slice(&message) {
             Ok(message) => message,
-            Err(_) => {
-                error!("failed to deserialize onmessage callback content.");
+            Err(error) => {
+                error!(
+                    "ignoring undecodable signal message, possibly from a newer server: {error:?}"
+                );
                 return;
             }
         };
-        let websocket_clone = websocket_clone.clone();
+        #[cfg(feature = "compression")]
+        let message = match message.decompress() {
+            Ok(message) => message,
+            Err(error) => {
+                error!("failed to decompress onmessage callback content: {error:?}");
+                return;
+            }
+        };
+        on_signal_message(message);
+    }) as Box<dyn FnMut(MessageEvent)>);
+    websocket.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
+    closures.keep(onmessage_callback);
+}
+
+/// Handler of the messages received from the signaling server, or from the other peer of the page.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn signal_message_handler(
+    peer_connection: RtcPeerConnection,
+    signaling: Signaling,
+    compression: Rc<Cell<bool>>,
+    session_owner: Rc<Cell<Option<bool>>>,
+    user_id: Rc<Cell<Option<UserId>>>,
+    fingerprint_check: FingerprintCheck,
+    sdp_transform: SdpTransform,
+    pings: Pings,
+    candidates: PendingCandidates,
+    initial_channel: InitialChannel,
+) -> impl FnMut(SignalMessage) + 'static {
+    move |message| {
+        let signaling = signaling.clone();
         let peer_connection_clone = peer_connection.clone();
+        let compression = compression.clone();
+        let session_owner = session_owner.clone();
+        let user_id = user_id.clone();
+        let fingerprint_check = fingerprint_check.clone();
+        let sdp_transform = sdp_transform.clone();
+        let pings = pings.clone();
+        let candidates = candidates.clone();
+        let initial_channel = initial_channel.clone();
         wasm_bindgen_futures::spawn_local(async move {
             websocket_handler::handle_websocket_message(
                 message,
                 peer_connection_clone,
-                websocket_clone,
+                signaling,
+                compression,
+                session_owner,
+                user_id,
+                fingerprint_check,
+                sdp_transform,
+                pings,
+                candidates,
+                initial_channel,
             )
             .await
             .unwrap_or_else(|error| {
                 error!("error handling websocket message: {:?}", error);
             })
         });
-    }) as Box<dyn FnMut(MessageEvent)>);
-    websocket.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
-    onmessage_callback.forget();
+    }
 }
 
-/// once websocket is open, send a request to start or join a session
-pub(crate) fn set_websocket_on_open(websocket: &WebSocket, session_id: SessionId) {
+/// once websocket is open, join the session, see [websocket_handler::join_session]
+pub(crate) fn set_websocket_on_open(
+    websocket: &WebSocket,
+    session_id: SessionId,
+    user_id: Rc<Cell<Option<UserId>>>,
+    join_retry: JoinRetry,
+    closures: &mut Closures,
+) {
     let websocket_clone = websocket.clone();
     let onopen_callback = Closure::wrap(Box::new(move |_| {
-        let signal_message = SignalMessage::SessionJoin(session_id);
-        let signal_message =
-            rmp_serde::to_vec(&signal_message).expect("failed serializing SignalMessage");
-        websocket_clone
-            .send_with_u8_array(&signal_message)
-            .expect("failed sending start-or-join message to the websocket");
+        websocket_handler::join_session(&websocket_clone, session_id, user_id.get(), &join_retry);
     }) as Box<dyn FnMut(JsValue)>);
     websocket.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
-    onopen_callback.forget();
+    closures.keep(onopen_callback);
 }
 
-pub(crate) fn set_peer_connection_on_negotiation_needed(peer_connection: &RtcPeerConnection) {
+pub(crate) fn set_peer_connection_on_negotiation_needed(
+    peer_connection: &RtcPeerConnection,
+    closures: &mut Closures,
+) {
     let on_negotiation_needed = Closure::wrap(Box::new(move || {
         debug!("on negotiation needed event occurred");
     }) as Box<dyn FnMut()>);
     peer_connection.set_onnegotiationneeded(Some(on_negotiation_needed.as_ref().unchecked_ref()));
-    on_negotiation_needed.forget();
+    closures.keep(on_negotiation_needed);
 }
 
 pub(crate) fn set_peer_connection_on_ice_gathering_state_change(
     peer_connection: &RtcPeerConnection,
+    closures: &mut Closures,
 ) {
     let peer_connection_clone = peer_connection.clone();
     let on_ice_gathering_state_change = Closure::wrap(Box::new(move || {
@@ -109,64 +668,237 @@ pub(crate) fn set_peer_connection_on_ice_gathering_state_change(
     peer_connection.set_onicegatheringstatechange(Some(
         on_ice_gathering_state_change.as_ref().unchecked_ref(),
     ));
-    on_ice_gathering_state_change.forget();
+    closures.keep(on_ice_gathering_state_change);
+}
+
+/// Text frames are decoded from JSON, or taken as the raw string if that fails,
+/// so that plain text can be received as a `String`.
+fn decode_text<T: DeserializeOwned>(text: &str) -> Result<T, serde_json::Error> {
+    serde_json::from_str(text).or_else(|error| {
+        T::deserialize(IntoDeserializer::<ValueError>::into_deserializer(text)).map_err(|_| error)
+    })
 }
 
+/// also acknowledges reliable messages, and resolves the pending sends acknowledged by the other peer
 pub(crate) fn set_data_channel_on_message<T: DeserializeOwned>(
     data_channel: &RtcDataChannel,
-    mut on_message_callback: impl FnMut(T) + 'static,
+    acknowledgements: Rc<RefCell<Acknowledgements>>,
+    encryption: Encryption,
+    signaling: Signaling,
+    on_message_callback: impl FnMut(T) + 'static,
+    closures: &mut Closures,
 ) {
-    let datachannel_on_message = Closure::wrap(Box::new(move |ev: MessageEvent| {
-        let message = ev.data().dyn_into::<Uint8Array>().ok();
-        if let Some(message) = message.and_then(|t| rmp_serde::from_slice(&t.to_vec()).ok()) {
+    let on_message = message_handler(
+        data_channel.clone(),
+        acknowledgements,
+        encryption,
+        signaling,
+        on_message_callback,
+    );
+    data_channel.set_on_message(on_message, closures);
+}
+
+/// Decrypts and decodes the messages received on `transport` for `on_message_callback`,
+/// answering reliable messages with their acknowledgement on the same transport.
+/// The other peer asks through it to open `signaling` again, to renegotiate.
+/// Messages sent with redundancy are each delivered once, the stale ones are dropped.
+pub(crate) fn message_handler<T: DeserializeOwned>(
+    transport: impl Transport + 'static,
+    acknowledgements: Rc<RefCell<Acknowledgements>>,
+    encryption: Encryption,
+    signaling: Signaling,
+    mut on_message_callback: impl FnMut(T) + 'static,
+) -> Box<dyn FnMut(Payload)> {
+    let mut redundancy = RedundantReceiver::default();
+    Box::new(move |payload| {
+        let payload = match encryption.open(payload) {
+            Some(payload) => payload,
+            None => return,
+        };
+        let message = match payload {
+            Payload::Text(text) => {
+                match decode_text(&text) {
+                    Ok(message) => {
+                        debug!("text message from datachannel (will call on_message)");
+                        on_message_callback(message);
+                    }
+                    Err(error) => error!(
+                        "failed to decode a {} bytes data channel text message: {error}",
+                        text.len()
+                    ),
+                }
+                return;
+            }
+            Payload::Binary(message) => message,
+        };
+        let payload = match acknowledgement::parse_frame(&message) {
+            Frame::Plain(payload) => payload,
+            Frame::Redundant(sequence, body) => {
+                for payload in redundancy.receive(sequence, body) {
+                    deliver(payload, &mut on_message_callback);
+                }
+                return;
+            }
+            Frame::Reliable(sequence, payload) => {
+                let ack = Payload::Binary(acknowledgement::ack_frame(sequence));
+                if let Err(error) = encryption.seal(ack).and_then(|ack| transport.send(ack)) {
+                    error!("failed to acknowledge reliable message {sequence}: {error:?}");
+                }
+                if !acknowledgements.borrow_mut().receive(sequence) {
+                    debug!("dropping already received reliable message {sequence}");
+                    return;
+                }
+                payload
+            }
+            Frame::Ack(sequence) => {
+                let resolve = acknowledgements.borrow_mut().acknowledge(sequence);
+                if let Some(resolve) = resolve {
+                    let _ = resolve.call0(&JsValue::NULL);
+                }
+                return;
+            }
+            Frame::ReopenSignaling => {
+                if let Err(error) = signaling.reopen() {
+                    error!("failed to reconnect to the signaling server: {error:?}");
+                }
+                return;
+            }
+        };
+        deliver(payload, &mut on_message_callback);
+    })
+}
+
+fn deliver<T: DeserializeOwned>(payload: &[u8], on_message_callback: &mut impl FnMut(T)) {
+    match rmp_serde::from_slice(payload) {
+        Ok(message) => {
             debug!("message from datachannel (will call on_message)");
             on_message_callback(message);
         }
-    }) as Box<dyn FnMut(MessageEvent)>);
-    data_channel.set_onmessage(Some(datachannel_on_message.as_ref().unchecked_ref()));
-    datachannel_on_message.forget();
+        Err(error) => error!(
+            "failed to decode a {} bytes data channel message: {error}",
+            payload.len()
+        ),
+    }
 }
 
-pub(crate) fn set_data_channel_on_error(data_channel: &RtcDataChannel) {
+pub(crate) fn set_data_channel_on_error(
+    data_channel: &RtcDataChannel,
+    open_hook: OpenHook,
+    closures: &mut Closures,
+) {
     let onerror = Closure::wrap(Box::new(move |data_channel_error| {
         error!("data channel error: {:?}", data_channel_error);
+        open_hook.settle(Err(WebRtcError::Channel(format!("{data_channel_error:?}"))));
     }) as Box<dyn FnMut(JsValue)>);
     data_channel.set_onerror(Some(onerror.as_ref().unchecked_ref()));
-    onerror.forget();
+    closures.keep(onerror);
+}
+
+pub(crate) fn set_data_channel_on_close(
+    data_channel: &RtcDataChannel,
+    open_hook: OpenHook,
+    on_disconnect: DisconnectHook,
+    closures: &mut Closures,
+) {
+    let onclose_callback = Closure::wrap(Box::new(move |_| {
+        debug!("data channel was closed, calling on_disconnect!");
+        open_hook.settle(Err(WebRtcError::Closed));
+        on_disconnect.call();
+    }) as Box<dyn FnMut(JsValue)>);
+    data_channel.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
+    closures.keep(onclose_callback);
+}
+
+/// Sends the messages waiting in the outbox once the data channel drained.
+pub(crate) fn set_data_channel_on_buffered_amount_low(
+    data_channel: &RtcDataChannel,
+    outbox: Rc<RefCell<Outbox>>,
+    closures: &mut Closures,
+) {
+    data_channel.set_buffered_amount_low_threshold(LOW_BUFFERED_AMOUNT);
+    let channel = data_channel.clone();
+    let onbufferedamountlow_callback = Closure::wrap(Box::new(move |_| {
+        outbox.borrow_mut().flush(&channel, MAX_BUFFERED_AMOUNT);
+    }) as Box<dyn FnMut(JsValue)>);
+    data_channel
+        .set_onbufferedamountlow(Some(onbufferedamountlow_callback.as_ref().unchecked_ref()));
+    closures.keep(onbufferedamountlow_callback);
 }
 
 pub(crate) fn set_data_channel_on_open(
     data_channel: &RtcDataChannel,
+    open_hook: OpenHook,
     mut on_open_callback: impl FnMut() + 'static,
+    closures: &mut Closures,
 ) {
     let onopen_callback = Closure::wrap(Box::new(move |_| {
         debug!("data channel is now open, calling on_open!");
+        open_hook.settle(Ok(()));
         on_open_callback();
     }) as Box<dyn FnMut(JsValue)>);
     data_channel.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
-    onopen_callback.forget();
+    closures.keep(onopen_callback);
 }
 
 pub(crate) fn set_peer_connection_on_ice_connection_state_change(
     peer_connection: &RtcPeerConnection,
+    on_state_change: StateHook,
+    ice_restart: IceRestart,
+    closures: &mut Closures,
 ) {
     let peer_connection_clone = peer_connection.clone();
     let on_ice_connection_state_change = Closure::wrap(Box::new(move || {
-        debug!(
-            "connection state change: {:?}",
-            peer_connection_clone.ice_connection_state()
-        )
+        let state = peer_connection_clone.ice_connection_state();
+        debug!("connection state change: {:?}", state);
+        let state = PeerState::from_ice_connection_state(state);
+        ice_restart.on_state_change(&peer_connection_clone, state);
+        on_state_change.call(state);
     }) as Box<dyn FnMut()>);
     peer_connection.set_oniceconnectionstatechange(Some(
         on_ice_connection_state_change.as_ref().unchecked_ref(),
     ));
-    on_ice_connection_state_change.forget();
+    closures.keep(on_ice_connection_state_change);
+}
+
+/// Tells `on_network_change` when the browser goes offline and back online,
+/// restarting ICE through `ice_restart` once it is back.
+pub(crate) fn set_window_on_network_change(
+    peer_connection: &RtcPeerConnection,
+    on_network_change: NetworkHook,
+    ice_restart: IceRestart,
+    closures: &mut Closures,
+) {
+    let window = match web_sys::window() {
+        Some(window) => window,
+        None => return,
+    };
+    for (event, online) in [("online", true), ("offline", false)] {
+        let peer_connection = peer_connection.clone();
+        let on_network_change = on_network_change.clone();
+        let ice_restart = ice_restart.clone();
+        let closure = Closure::wrap(Box::new(move || {
+            info!("the browser went {event}");
+            if online {
+                ice_restart.on_network_back(&peer_connection);
+            }
+            on_network_change.call(online);
+        }) as Box<dyn FnMut()>);
+        if let Err(error) =
+            window.add_event_listener_with_callback(event, closure.as_ref().unchecked_ref())
+        {
+            error!("failed to listen to the browser going {event}: {error:?}");
+            continue;
+        }
+        closures.keep_window_listener(WindowListener { event, closure });
+    }
 }
 
 pub(crate) fn set_peer_connection_on_ice_candidate(
     peer_connection: &RtcPeerConnection,
-    websocket: WebSocket,
+    signaling: Signaling,
     session_id: SessionId,
+    compression: Rc<Cell<bool>>,
+    closures: &mut Closures,
 ) {
     let on_ice_candidate = Closure::wrap(Box::new(move |ev: RtcPeerConnectionIceEvent| {
         let candidate = if let Some(candidate) = ev.candidate() {
@@ -174,21 +906,55 @@ pub(crate) fn set_peer_connection_on_ice_candidate(
         } else {
             return;
         };
+        let candidate_line = candidate.candidate();
         let signaled_candidate = IceCandidate {
-            candidate: candidate.candidate(),
+            candidate_type: CandidateType::from_candidate(&candidate_line),
+            candidate: candidate_line,
             sdp_mid: candidate.sdp_mid(),
             sdp_m_line_index: candidate.sdp_m_line_index(),
         };
         debug!("signaled candidate: {:#?}", signaled_candidate);
 
         let signal_message = SignalMessage::IceCandidate(session_id, signaled_candidate);
-        let signal_message =
-            rmp_serde::to_vec(&signal_message).expect("failed to serialize SignalMessage");
-
-        websocket
-            .send_with_u8_array(&signal_message)
+        signaling
+            .send(signal_message, compression.get())
             .unwrap_or_else(|_| error!("failed to send one of the ICE candidates"));
     }) as Box<dyn FnMut(RtcPeerConnectionIceEvent)>);
     peer_connection.set_onicecandidate(Some(on_ice_candidate.as_ref().unchecked_ref()));
-    on_ice_candidate.forget();
+    closures.keep(on_ice_candidate);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_open_hook_keeps_the_first_outcome() {
+        let open_hook = OpenHook::default();
+        let waiting = open_hook.wait(1000);
+        open_hook.settle(Ok(()));
+        open_hook.settle(Err(WebRtcError::Closed));
+        assert_eq!(waiting.await.unwrap(), Ok(()));
+        assert_eq!(open_hook.wait(1000).await.unwrap(), Ok(()));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_unanswered_pings_expire() {
+        let mut state = PingState::default();
+        state.pending.insert(0, (1_000.0, Box::new(|_| {})));
+        state.pending.insert(1, (5_000.0, Box::new(|_| {})));
+        state.expire(1_000.0 + PING_TIMEOUT_MS);
+        assert_eq!(state.pending.keys().collect::<Vec<_>>(), [&1]);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_text_messages_decode_as_json_or_raw_string() {
+        assert_eq!(decode_text::<Vec<u32>>("[1,2]").unwrap(), vec![1, 2]);
+        assert_eq!(decode_text::<String>("\"quoted\"").unwrap(), "quoted");
+        assert_eq!(decode_text::<String>("plain text").unwrap(), "plain text");
+        assert!(decode_text::<Vec<u32>>("plain text").is_err());
+    }
 }
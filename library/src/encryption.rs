@@ -0,0 +1,155 @@
+/*!
+End-to-end encryption of the messages, with a key the peers shared out-of-band.
+
+DTLS already encrypts the data channel, but a malicious signaling server could swap the SDP of the
+peers to sit in the middle. With the `encryption` feature and [crate::ConnectionConfig::encryption_key]
+set, every binary frame is sealed with XChaCha20-Poly1305 before being sent, under a random 192 bits
+nonce sent along with it, which is large enough for random nonces to never repeat.
+
+Without the feature or the key, frames pass through unchanged.
+ */
+
+use crate::transport::Payload;
+use crate::ConnectionConfig;
+#[cfg(feature = "encryption")]
+use chacha20poly1305::aead::{Aead, KeyInit};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+#[cfg(feature = "encryption")]
+use log::error;
+use std::fmt::{Debug, Formatter};
+#[cfg(feature = "encryption")]
+use std::rc::Rc;
+use wasm_bindgen::JsValue;
+
+#[cfg(feature = "encryption")]
+const NONCE_LENGTH: usize = 24;
+
+/// Key shared by both peers, for example derived from a password they both know.
+#[cfg(feature = "encryption")]
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+#[cfg(feature = "encryption")]
+impl From<[u8; 32]> for EncryptionKey {
+    fn from(key: [u8; 32]) -> Self {
+        EncryptionKey(key)
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// Sealing of the frames sent and opening of the frames received by a network manager.
+#[derive(Clone, Default)]
+pub(crate) struct Encryption {
+    #[cfg(feature = "encryption")]
+    cipher: Option<Rc<XChaCha20Poly1305>>,
+}
+
+impl Encryption {
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    pub(crate) fn new(config: &ConnectionConfig) -> Self {
+        Encryption {
+            #[cfg(feature = "encryption")]
+            cipher: config
+                .encryption_key
+                .as_ref()
+                .map(|key| Rc::new(XChaCha20Poly1305::new(Key::from_slice(&key.0)))),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        #[cfg(feature = "encryption")]
+        return self.cipher.is_some();
+        #[cfg(not(feature = "encryption"))]
+        false
+    }
+
+    /// Frame to send for `payload`. Text frames can't be sent once encrypted.
+    pub(crate) fn seal(&self, payload: Payload) -> Result<Payload, JsValue> {
+        match payload {
+            Payload::Binary(message) => self.seal_binary(message).map(Payload::Binary),
+            #[cfg(feature = "encryption")]
+            Payload::Text(_) if self.cipher.is_some() => {
+                Err(JsValue::from_str("text messages can't be encrypted"))
+            }
+            Payload::Text(message) => Ok(Payload::Text(message)),
+        }
+    }
+
+    pub(crate) fn seal_binary(&self, message: Vec<u8>) -> Result<Vec<u8>, JsValue> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            let mut nonce = [0; NONCE_LENGTH];
+            getrandom::getrandom(&mut nonce)
+                .map_err(|error| JsValue::from_str(&error.to_string()))?;
+            let sealed = cipher
+                .encrypt(XNonce::from_slice(&nonce), message.as_slice())
+                .map_err(|_| JsValue::from_str("failed to encrypt a message"))?;
+            let mut frame = nonce.to_vec();
+            frame.extend(sealed);
+            return Ok(frame);
+        }
+        Ok(message)
+    }
+
+    /// Payload of a received `frame`, `None` when it was not sealed with the same key.
+    pub(crate) fn open(&self, frame: Payload) -> Option<Payload> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            let frame = match frame {
+                Payload::Binary(frame) if frame.len() >= NONCE_LENGTH => frame,
+                _ => {
+                    error!("dropping a message which is not encrypted");
+                    return None;
+                }
+            };
+            let (nonce, sealed) = frame.split_at(NONCE_LENGTH);
+            return match cipher.decrypt(XNonce::from_slice(nonce), sealed) {
+                Ok(message) => Some(Payload::Binary(message)),
+                Err(_) => {
+                    error!("dropping a message which failed to decrypt, is the key the same?");
+                    None
+                }
+            };
+        }
+        Some(frame)
+    }
+}
+
+impl Debug for Encryption {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("Encryption");
+        #[cfg(feature = "encryption")]
+        debug.field("enabled", &self.cipher.is_some());
+        debug.finish()
+    }
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sealed_frame_only_opens_with_the_same_key() {
+        let with_key = |key| ConnectionConfig {
+            encryption_key: Some(EncryptionKey::from(key)),
+            ..ConnectionConfig::default()
+        };
+        let encryption = Encryption::new(&with_key([7; 32]));
+        let message = Payload::Binary(b"goal!".to_vec());
+
+        let frame = encryption.seal(message.clone()).unwrap();
+        let other_frame = encryption.seal(message.clone()).unwrap();
+        assert_ne!(frame, other_frame, "nonces must not repeat");
+        assert_eq!(encryption.open(frame.clone()), Some(message));
+
+        let other_key = Encryption::new(&with_key([8; 32]));
+        assert_eq!(other_key.open(frame), None);
+    }
+}
@@ -0,0 +1,54 @@
+/*!
+Errors of the library that callers may want to tell apart, instead of an opaque [JsValue].
+ */
+
+use std::fmt::{Display, Formatter};
+use wasm_bindgen::JsValue;
+
+/// Failure of a connection to the other peer.
+///
+/// Converts into a [JsValue], so that `?` works in functions returning the errors of the browser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WebRtcError {
+    /// The data channel did not open in time.
+    Timeout,
+    /// The data channel reported an error before opening, described by the browser.
+    Channel(String),
+    /// The data channel, or the whole connection, was closed before opening.
+    Closed,
+    /// A message was sent while the data channel is not open, either not yet or no longer.
+    NotConnected,
+    /// A message could not be encrypted, described by the error.
+    Encryption(String),
+    /// The browser refused the SDP offer or answer of the other peer.
+    InvalidSdp {
+        /// Beginning of the refused SDP
+        sdp: String,
+        /// Reason given by the browser
+        error: String,
+    },
+}
+
+impl Display for WebRtcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebRtcError::Timeout => write!(f, "data channel did not open in time"),
+            WebRtcError::Channel(error) => write!(f, "data channel failed: {error}"),
+            WebRtcError::Closed => write!(f, "connection closed before the data channel opened"),
+            WebRtcError::NotConnected => write!(f, "data channel is not open"),
+            WebRtcError::Encryption(error) => write!(f, "failed to encrypt a message: {error}"),
+            WebRtcError::InvalidSdp { sdp, error } => {
+                write!(f, "invalid SDP from the other peer: {error}, in: {sdp}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WebRtcError {}
+
+impl From<WebRtcError> for JsValue {
+    fn from(error: WebRtcError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
@@ -0,0 +1,91 @@
+/*!
+Messages received by a network manager started with [crate::one_to_one::NetworkManager::start_polling],
+waiting for the application to drain them.
+
+When the application drains them slower than the other peer sends them, the oldest are dropped
+past [crate::ChannelConfig::max_received], which fits state sent every tick best: the newest one
+replaces what was dropped.
+ */
+
+use log::error;
+use std::any::Any;
+use std::collections::VecDeque;
+
+#[derive(Debug, Default)]
+pub(crate) struct Inbox {
+    messages: VecDeque<Box<dyn Any>>,
+    /// Messages kept at most, unbounded when `None`
+    capacity: Option<usize>,
+    /// Messages dropped since the network manager started
+    dropped: u64,
+    /// Whether messages were dropped since the last drain, to only report it once
+    overflowing: bool,
+}
+
+impl Inbox {
+    pub(crate) fn new(capacity: Option<usize>) -> Self {
+        Inbox {
+            capacity,
+            ..Inbox::default()
+        }
+    }
+
+    pub(crate) fn push(&mut self, message: Box<dyn Any>) {
+        self.messages.push_back(message);
+        let capacity = match self.capacity {
+            Some(capacity) => capacity,
+            None => return,
+        };
+        while self.messages.len() > capacity {
+            self.messages.pop_front();
+            self.dropped += 1;
+            if !self.overflowing {
+                error!("more than {capacity} messages received since the last drain, dropping the oldest");
+                self.overflowing = true;
+            }
+        }
+    }
+
+    /// Messages received since the last drain, oldest first.
+    pub(crate) fn drain<T: 'static>(&mut self) -> Vec<T> {
+        self.overflowing = false;
+        self.messages
+            .drain(..)
+            .filter_map(|message| match message.downcast() {
+                Ok(message) => Some(*message),
+                Err(_) => {
+                    error!("dropping a message received as another type than drained");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn oldest_messages_are_dropped_past_the_capacity() {
+        let mut inbox = Inbox::new(Some(2));
+        for state in 1..=5_u32 {
+            inbox.push(Box::new(state));
+        }
+        assert_eq!(inbox.len(), 2);
+        assert_eq!(inbox.dropped(), 3);
+        assert_eq!(inbox.drain::<u32>(), [4, 5]);
+        assert_eq!(inbox.len(), 0);
+
+        inbox.push(Box::new("not a state"));
+        assert!(inbox.drain::<u32>().is_empty());
+    }
+}
@@ -16,16 +16,41 @@ Library contains three network topologies, [one-to-one](one_to_one), which creat
 [one-to-many](one_to_many), which specifies a host and arbitrary number of clients
 and [many-to-many] that creates connection for each pair of peers and allows sending messages to any of them.
 
+The types needed to get started are re-exported together by the [prelude], see `use wasm_peers::prelude::*;`.
+
 */
 
+mod acknowledgement;
 mod callbacks;
+mod encryption;
+mod error;
+mod inbox;
+#[cfg(feature = "netsim")]
+mod netsim;
 #[deny(missing_docs)]
 pub mod one_to_one;
+mod outbox;
+mod peer_connection;
+pub mod prelude;
+mod redundancy;
+mod signaling;
+mod stats;
+mod transport;
 mod utils;
 mod websocket_handler;
 
-pub use utils::ConnectionType;
-pub use wasm_peers_protocol::{SessionId, UserId};
+#[cfg(feature = "encryption")]
+pub use encryption::EncryptionKey;
+pub use error::WebRtcError;
+#[cfg(feature = "netsim")]
+pub use netsim::NetworkSimulation;
+pub use outbox::Priority;
+pub use stats::{Candidate, CandidatePair};
+pub use utils::{
+    ChannelConfig, ChannelMode, ConnectionConfig, ConnectionType, DtlsFingerprint, IceServer,
+    PeerState, Reliability,
+};
+pub use wasm_peers_protocol::{text, SessionId, UserId};
 
 /// Returns a new SessionId instance that can be used to identify a session by signaling server.
 pub fn get_random_session_id() -> SessionId {
@@ -0,0 +1,48 @@
+/*!
+Degrade the outgoing side of the data channel on purpose, to exercise
+interpolation and lag compensation without an actually bad network.
+
+Only available with the `netsim` feature, which is meant for development builds.
+ */
+
+use crate::transport::{Payload, Transport};
+use js_sys::Math;
+use log::debug;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::RtcDataChannel;
+
+/// Network conditions simulated by [NetworkManager::simulate_network](crate::one_to_one::NetworkManager::simulate_network).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetworkSimulation {
+    /// Delay added to every outgoing message, in milliseconds.
+    pub latency_ms: u32,
+    /// Random extra delay up to this many milliseconds added on top of `latency_ms`.
+    /// Since the data channel is unordered, messages may arrive out of order.
+    pub jitter_ms: u32,
+    /// Fraction of outgoing messages silently dropped, between `0.0` and `1.0`.
+    pub loss: f64,
+}
+
+pub(crate) fn send_with_simulation(
+    data_channel: &RtcDataChannel,
+    message: Payload,
+    simulation: &NetworkSimulation,
+) {
+    if Math::random() < simulation.loss {
+        debug!("netsim: dropped an outgoing message");
+        return;
+    }
+    let delay = simulation.latency_ms as f64 + Math::random() * simulation.jitter_ms as f64;
+    let data_channel = data_channel.clone();
+    let send = Closure::once_into_js(move || {
+        let _ = data_channel.send(message);
+    });
+    let window = web_sys::window().expect("there was no window global object!");
+    if window
+        .set_timeout_with_callback_and_timeout_and_arguments_0(send.unchecked_ref(), delay as i32)
+        .is_err()
+    {
+        debug!("netsim: failed to schedule an outgoing message");
+    }
+}
@@ -8,73 +8,233 @@ with [NetworkManager::send_message] method.
 
 This example shows two peers sending `ping` and `pong` messages to each other.
 
-```
-use wasm_peers::{ConnectionType, SessionId};
-use wasm_peers::one_to_one::NetworkManager;
+```no_run
+use wasm_peers::prelude::*;
 use web_sys::console;
 
 const SIGNALING_SERVER_URL: &str = "ws://0.0.0.0:9001/one-to-one";
-const STUN_SERVER_URL: &str = "stun:openrelay.metered.ca:80";
 
 let session_id = SessionId::new(12348);
 let mut server = NetworkManager::new(
     SIGNALING_SERVER_URL,
     session_id.clone(),
-    ConnectionType::Stun { urls: STUN_SERVER_URL.to_string() },
+    ConnectionType::Stun,
 )
 .unwrap();
 
 let server_clone = server.clone();
-let server_on_open = move || server_clone.send_message("ping!").unwrap();
+let server_on_open = move || server_clone.send_message(&"ping!").unwrap();
 let server_on_message = {
-    move |message| {
+    move |message: String| {
         console::log_1(&format!("server received message: {}", message).into());
     }
 };
-server.start(server_on_open, server_on_message).unwrap();
+server.start(ChannelConfig::default(), server_on_open, server_on_message).unwrap();
 
 let mut client = NetworkManager::new(
     SIGNALING_SERVER_URL,
     session_id,
-    ConnectionType::Stun { urls: STUN_SERVER_URL.to_string() },
+    ConnectionType::Stun,
 )
 .unwrap();
 let client_on_open = || { /* do nothing */ };
 let client_clone = client.clone();
 let client_on_message = {
-    move |message| {
+    move |message: String| {
         console::log_1(&format!("client received message: {}", message).into());
-        client_clone.send_message("pong!").unwrap();
+        client_clone.send_message(&"pong!").unwrap();
     }
 };
-client.start(client_on_open, client_on_message).unwrap();
+client.start(ChannelConfig::default(), client_on_open, client_on_message).unwrap();
 ```
+
+Both peers above could run in the same page without any signaling server by passing
+[ConnectionType::InPage](crate::ConnectionType::InPage) instead, which is handy for tests and demos.
 */
 
+use crate::acknowledgement::{self, Acknowledgements};
 use crate::callbacks::{
-    set_data_channel_on_error, set_data_channel_on_message, set_data_channel_on_open,
-    set_peer_connection_on_data_channel, set_peer_connection_on_ice_candidate,
-    set_peer_connection_on_ice_connection_state_change,
+    set_data_channel_on_buffered_amount_low, set_data_channel_on_close, set_data_channel_on_error,
+    set_data_channel_on_message, set_data_channel_on_open, set_peer_connection_on_data_channel,
+    set_peer_connection_on_ice_candidate, set_peer_connection_on_ice_connection_state_change,
     set_peer_connection_on_ice_gathering_state_change, set_peer_connection_on_negotiation_needed,
-    set_websocket_on_message, set_websocket_on_open,
+    set_window_on_network_change, signal_message_handler, Closures, DisconnectHook,
+    FingerprintCheck, IceRestart, InitialChannel, NetworkHook, OpenHook, Pings, SdpTransform,
+    SignalHook, StateHook,
+};
+use crate::encryption::Encryption;
+use crate::error::WebRtcError;
+use crate::inbox::Inbox;
+use crate::outbox::{Outbox, Priority, MAX_BUFFERED_AMOUNT};
+use crate::peer_connection::PendingCandidates;
+use crate::redundancy::RedundantSender;
+use crate::signaling::{self, InPagePeer, JoinRetry, ServerLink, SharedLink, Signaling};
+use crate::stats::{self, CandidatePair};
+use crate::transport::Payload;
+use crate::utils::{
+    js_error_message, ChannelConfig, ChannelMode, ConnectionConfig, ConnectionType,
+    DtlsFingerprint, PeerState,
 };
-use crate::utils::ConnectionType;
-use log::debug;
+use crate::websocket_handler::send_sdp_offer;
+use js_sys::Promise;
+use log::{debug, error};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use std::cell::{Ref, RefCell};
+use std::cell::{Cell, Ref, RefCell};
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
 use std::rc::Rc;
 use wasm_bindgen::JsValue;
-use wasm_peers_protocol::{SessionId, WS_PORT};
-use web_sys::RtcPeerConnection;
-use web_sys::{RtcDataChannel, RtcDataChannelInit, WebSocket};
+use wasm_bindgen_futures::JsFuture;
+pub use wasm_peers_protocol::one_to_one::SignalMessage;
+use wasm_peers_protocol::text::clamp_text;
+use wasm_peers_protocol::{SessionId, UserId};
+use web_sys::{RtcDataChannel, RtcDataChannelInit, RtcDataChannelState};
+use web_sys::{RtcPeerConnection, RtcSignalingState, WebSocket};
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub(crate) struct NetworkManagerInner {
     session_id: SessionId,
-    websocket: WebSocket,
+    pub(crate) signaling: Signaling,
     peer_connection: RtcPeerConnection,
     pub(crate) data_channel: Option<RtcDataChannel>,
+    channel_opener: Option<ChannelOpener>,
+    channel_mode: ChannelMode,
+    /// Whether signaling messages are compressed, agreed upon with the signaling server
+    compression: Rc<Cell<bool>>,
+    /// Whether this peer owns the session, told once the other peer joined
+    session_owner: Rc<Cell<Option<bool>>>,
+    /// Id the signaling server gave this peer, told along with [NetworkManagerInner::session_owner]
+    user_id: Rc<Cell<Option<UserId>>>,
+    #[cfg(feature = "netsim")]
+    network_simulation: Option<crate::NetworkSimulation>,
+    pub(crate) acknowledgements: Rc<RefCell<Acknowledgements>>,
+    pub(crate) closures: Closures,
+    /// Handlers of the current data channel, replaced along with it
+    pub(crate) channel_closures: Closures,
+    /// Handler of the messages received, see [NetworkManager::set_on_message]
+    message_handler: Option<MessageHandler>,
+    /// Data channels the messages are received on, with the closures of the handler set on them
+    receiving: Vec<(RtcDataChannel, Closures)>,
+    pub(crate) on_disconnect: DisconnectHook,
+    on_state_change: StateHook,
+    on_network_change: NetworkHook,
+    on_signal_message: SignalHook,
+    pub(crate) open_hook: OpenHook,
+    /// Messages waiting for the data channel to drain
+    pub(crate) outbox: Rc<RefCell<Outbox>>,
+    /// Messages sent with [NetworkManager::send_message_with_redundancy], to repeat in the next ones
+    redundancy: RedundantSender,
+    /// Messages received since the last [NetworkManager::drain_received]
+    received: Rc<RefCell<Inbox>>,
+    pub(crate) encryption: Encryption,
+    fingerprint_check: FingerprintCheck,
+    sdp_transform: SdpTransform,
+    pings: Pings,
+    /// ICE restarts attempted in a row when the connection fails
+    ice_restarts: u8,
+    join_retry: JoinRetry,
+    /// Characters kept of the text sent, all of it when `None`
+    max_text_len: Option<usize>,
+}
+
+/// Time [NetworkManager::connected] waits for the data channel to open.
+const OPEN_TIMEOUT_MS: i32 = 30_000;
+
+impl NetworkManagerInner {
+    fn close(&mut self) {
+        self.peer_connection.set_ondatachannel(None);
+        self.peer_connection.set_onicecandidate(None);
+        self.peer_connection.set_oniceconnectionstatechange(None);
+        self.peer_connection.set_onicegatheringstatechange(None);
+        self.peer_connection.set_onnegotiationneeded(None);
+        if let Some(data_channel) = self.data_channel.take() {
+            close_data_channel(&data_channel);
+        }
+        self.peer_connection.close();
+        self.signaling.close();
+        self.open_hook.settle(Err(WebRtcError::Closed));
+        self.closures = Closures::default();
+        self.channel_closures = Closures::default();
+        self.receiving.clear();
+    }
+
+    /// Handle the messages received on `data_channel` with the current handler,
+    /// along with those of the other data channels still open.
+    pub(crate) fn receive_on(&mut self, data_channel: &RtcDataChannel) {
+        let message_handler = match &self.message_handler {
+            Some(message_handler) => message_handler.clone(),
+            None => return,
+        };
+        self.receiving
+            .retain(|(data_channel, _)| data_channel.ready_state() != RtcDataChannelState::Closed);
+        let closures = message_handler.attach(data_channel);
+        self.receiving.push((data_channel.clone(), closures));
+    }
+}
+
+/// Close `data_channel` without running its handlers.
+pub(crate) fn close_data_channel(data_channel: &RtcDataChannel) {
+    data_channel.set_onopen(None);
+    data_channel.set_onclose(None);
+    data_channel.set_onerror(None);
+    data_channel.set_onmessage(None);
+    data_channel.set_onbufferedamountlow(None);
+    data_channel.close();
+}
+
+/// Creates the data channel of [NetworkManager::start] again, along with its handlers.
+#[derive(Clone)]
+pub(crate) struct ChannelOpener(Rc<dyn Fn(&mut Closures) -> RtcDataChannel>);
+
+impl Debug for ChannelOpener {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ChannelOpener")
+    }
+}
+
+/// Sets the `on_message_callback` of [NetworkManager::start] or [NetworkManager::set_on_message]
+/// on a data channel, returning the closures to keep while it is in use.
+#[derive(Clone)]
+struct MessageHandler(Rc<dyn Fn(&RtcDataChannel) -> Closures>);
+
+impl MessageHandler {
+    fn new<T: DeserializeOwned>(
+        inner: &NetworkManagerInner,
+        on_message_callback: impl FnMut(T) + Clone + 'static,
+    ) -> Self {
+        let acknowledgements = inner.acknowledgements.clone();
+        let encryption = inner.encryption.clone();
+        let signaling = inner.signaling.clone();
+        MessageHandler(Rc::new(move |data_channel| {
+            let mut closures = Closures::default();
+            set_data_channel_on_message(
+                data_channel,
+                acknowledgements.clone(),
+                encryption.clone(),
+                signaling.clone(),
+                on_message_callback.clone(),
+                &mut closures,
+            );
+            closures
+        }))
+    }
+
+    fn attach(&self, data_channel: &RtcDataChannel) -> Closures {
+        (self.0)(data_channel)
+    }
+}
+
+impl Debug for MessageHandler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MessageHandler")
+    }
+}
+
+impl Drop for NetworkManagerInner {
+    fn drop(&mut self) {
+        self.close();
+    }
 }
 
 /// Abstraction over WebRTC peer-to-peer connection.
@@ -92,6 +252,17 @@ pub(crate) struct NetworkManagerInner {
 /// to allow possibility of referring to network manger itself from the callbacks.
 ///
 /// This class is a cloneable pointer to the underlying resource and can be cloned freely.
+/// The connection is closed once the last clone is dropped, or explicitly with [NetworkManager::close].
+/// Callbacks holding a clone of the manager keep it alive, in that case [NetworkManager::close] must be called.
+///
+/// # Threading
+///
+/// `NetworkManager` is neither `Send` nor `Sync`, and swapping its `Rc<RefCell<_>>` for an
+/// `Arc<Mutex<_>>` would not change that: the `WebSocket`, `RtcPeerConnection` and
+/// `RtcDataChannel` handles it wraps are `JsValue`s, which only exist on the JS thread that
+/// created them, even when building with wasm threads support.
+/// Code running on another thread should forward what it wants to send to the thread
+/// owning the manager, for example through a channel polled from the game loop.
 #[derive(Debug, Clone)]
 pub struct NetworkManager {
     pub(crate) inner: Rc<RefCell<NetworkManagerInner>>,
@@ -106,18 +277,123 @@ impl NetworkManager {
         session_id: SessionId,
         connection_type: ConnectionType,
     ) -> Result<Self, JsValue> {
-        let peer_connection = connection_type.create_peer_connection(hostname)?;
+        Self::with_config(
+            hostname,
+            session_id,
+            connection_type,
+            ConnectionConfig::default(),
+        )
+    }
+
+    /// Same as [NetworkManager::new], with additional options of the connection.
+    pub fn with_config(
+        hostname: &str,
+        session_id: SessionId,
+        connection_type: ConnectionType,
+        config: ConnectionConfig,
+    ) -> Result<Self, JsValue> {
+        Self::with_websocket(hostname, session_id, connection_type, config, None)
+    }
+
+    /// Same as [NetworkManager::with_config], in a new session the signaling server creates
+    /// under an id it picks, see [NetworkManager::session_id], for the other peer to join it with.
+    ///
+    /// Unlike an id chosen by the peers, the id of the session can't be guessed or taken by
+    /// another session. It is the only way to open a session on a signaling server
+    /// assigning the ids of the sessions. This peer owns the session, see [NetworkManager::is_session_owner].
+    /// Fails with [ConnectionType::InPage] and with [ConnectionConfig::share_websocket],
+    /// which don't have a websocket to the signaling server of their own.
+    pub fn create_session(
+        hostname: &str,
+        connection_type: ConnectionType,
+        config: ConnectionConfig,
+    ) -> impl Future<Output = Result<Self, JsValue>> {
+        let hostname = hostname.to_string();
+        async move {
+            if matches!(connection_type, ConnectionType::InPage) || config.share_websocket {
+                return Err(JsValue::from_str(
+                    "only a network manager with its own websocket to the signaling server can create a session",
+                ));
+            }
+            let url = config.signaling_url(&hostname, "one-to-one");
+            let (websocket, session_id) = signaling::create_session(&url)?.await?;
+            Self::with_websocket(
+                &hostname,
+                session_id,
+                connection_type,
+                config,
+                Some(websocket),
+            )
+        }
+    }
+
+    /// Same as [NetworkManager::with_config], on `websocket` when it was already opened to create the session.
+    fn with_websocket(
+        hostname: &str,
+        session_id: SessionId,
+        connection_type: ConnectionType,
+        config: ConnectionConfig,
+        websocket: Option<WebSocket>,
+    ) -> Result<Self, JsValue> {
+        let peer_connection = connection_type.create_peer_connection(hostname, &config)?;
+        let encryption = Encryption::new(&config);
+        let ice_restarts = config.ice_restarts;
 
-        let url = format!("ws://{hostname}:{WS_PORT}/one-to-one");
-        let websocket = WebSocket::new(&url)?;
-        websocket.set_binary_type(web_sys::BinaryType::Arraybuffer);
+        let user_id = Rc::new(Cell::new(None));
+        let join_retry = JoinRetry::new(config.join_retries);
+        let signaling = if let ConnectionType::InPage = connection_type {
+            Signaling::InPage(InPagePeer::new(config.namespace.clone(), session_id))
+        } else if config.share_websocket {
+            let url = config.signaling_url(hostname, "one-to-one");
+            Signaling::Shared(SharedLink::new(&url, session_id, join_retry.clone())?)
+        } else {
+            let url = config.signaling_url(hostname, "one-to-one");
+            let keep_open = config.keep_signaling_open;
+            Signaling::Server(match websocket {
+                Some(websocket) => ServerLink::with_websocket(
+                    url,
+                    websocket,
+                    keep_open,
+                    user_id.clone(),
+                    join_retry.clone(),
+                ),
+                None => ServerLink::new(url, keep_open, user_id.clone(), join_retry.clone())?,
+            })
+        };
 
         Ok(NetworkManager {
             inner: Rc::new(RefCell::new(NetworkManagerInner {
                 session_id,
-                websocket,
+                signaling,
                 peer_connection,
                 data_channel: None,
+                channel_opener: None,
+                channel_mode: ChannelMode::default(),
+                compression: Rc::new(Cell::new(false)),
+                session_owner: Rc::default(),
+                user_id,
+                #[cfg(feature = "netsim")]
+                network_simulation: None,
+                acknowledgements: Rc::default(),
+                closures: Closures::default(),
+                channel_closures: Closures::default(),
+                message_handler: None,
+                receiving: Vec::new(),
+                on_disconnect: DisconnectHook::default(),
+                on_state_change: StateHook::default(),
+                on_network_change: NetworkHook::default(),
+                on_signal_message: SignalHook::default(),
+                open_hook: OpenHook::default(),
+                outbox: Rc::default(),
+                redundancy: RedundantSender::default(),
+                received: Rc::default(),
+                encryption,
+                fingerprint_check: FingerprintCheck::default(),
+                sdp_transform: SdpTransform::default(),
+                pings: Pings::default(),
+                ice_restarts,
+                join_retry,
+                max_text_len: config.max_text_len,
             })),
         })
     }
@@ -125,70 +401,793 @@ impl NetworkManager {
     /// Second part of the setup that begins the actual connection.
     /// Requires specifying a callbacks that are guaranteed to run
     /// when the connection opens and on each message received.
+    ///
+    /// Once both peers joined the session, the session owner, see [NetworkManager::is_session_owner],
+    /// creates the data channel and offers the connection, the other peer only answering it.
+    ///
+    /// Messages are received whatever the mode of the other peer's channel,
+    /// `config.mode` only chooses how this end sends them, always in binary when encrypting.
     pub fn start<T: DeserializeOwned>(
         &mut self,
-        max_retransmits: u16,
+        config: ChannelConfig,
         on_open_callback: impl FnMut() + Clone + 'static,
         on_message_callback: impl FnMut(T) + Clone + 'static,
     ) -> Result<(), JsValue> {
-        let NetworkManagerInner {
-            websocket,
-            peer_connection,
-            session_id,
-            ..
-        } = self.inner.borrow().clone();
+        let (signaling, peer_connection, session_id, compression, on_disconnect) = {
+            let inner = self.inner.borrow();
+            (
+                inner.signaling.clone(),
+                inner.peer_connection.clone(),
+                inner.session_id,
+                inner.compression.clone(),
+                inner.on_disconnect.clone(),
+            )
+        };
+        let fingerprint_check = self.inner.borrow().fingerprint_check.clone();
+        let sdp_transform = self.inner.borrow().sdp_transform.clone();
+        let pings = self.inner.borrow().pings.clone();
+        let on_state_change = self.inner.borrow().on_state_change.clone();
+        let on_network_change = self.inner.borrow().on_network_change.clone();
+        let on_signal_message = self.inner.borrow().on_signal_message.clone();
+        let ice_restarts = self.inner.borrow().ice_restarts;
+        let session_owner = self.inner.borrow().session_owner.clone();
+        let user_id = self.inner.borrow().user_id.clone();
+        let open_hook = self.inner.borrow().open_hook.clone();
+        let outbox = self.inner.borrow().outbox.clone();
+        let encrypted = self.inner.borrow().encryption.is_enabled();
+        let mut closures = Closures::default();
 
+        let label = config.label(session_id)?;
         let mut init = RtcDataChannelInit::new();
-        init.max_retransmits(max_retransmits);
-        init.ordered(false);
-
-        let data_channel = peer_connection
-            .create_data_channel_with_data_channel_dict(&session_id.to_string(), &init);
-        debug!(
-            "data_channel created with label: {:?}",
-            data_channel.label()
-        );
+        config.reliability.configure(&mut init);
+
+        let opener = {
+            let peer_connection = peer_connection.clone();
+            let on_open_callback = on_open_callback.clone();
+            move |closures: &mut Closures| {
+                let data_channel =
+                    peer_connection.create_data_channel_with_data_channel_dict(&label, &init);
+                debug!(
+                    "data_channel created with label: {:?}",
+                    data_channel.label()
+                );
 
-        set_data_channel_on_open(&data_channel, on_open_callback.clone());
-        set_data_channel_on_error(&data_channel);
-        set_data_channel_on_message(&data_channel, on_message_callback.clone());
+                set_data_channel_on_open(
+                    &data_channel,
+                    open_hook.clone(),
+                    on_open_callback.clone(),
+                    closures,
+                );
+                set_data_channel_on_error(&data_channel, open_hook.clone(), closures);
+                set_data_channel_on_close(
+                    &data_channel,
+                    open_hook.clone(),
+                    on_disconnect.clone(),
+                    closures,
+                );
+                set_data_channel_on_buffered_amount_low(&data_channel, outbox.clone(), closures);
+                data_channel
+            }
+        };
 
-        self.inner.borrow_mut().data_channel = Some(data_channel);
+        let message_handler = MessageHandler::new(&self.inner.borrow(), on_message_callback);
+        self.inner.borrow_mut().message_handler = Some(message_handler);
+        self.inner.borrow_mut().channel_opener = Some(ChannelOpener(Rc::new(opener)));
+        let initial_channel = {
+            let inner = Rc::downgrade(&self.inner);
+            InitialChannel::new(move || {
+                let inner = match inner.upgrade() {
+                    Some(inner) => inner,
+                    None => return,
+                };
+                let opener = match &*inner.borrow() {
+                    NetworkManagerInner {
+                        data_channel: None,
+                        channel_opener: Some(opener),
+                        ..
+                    } => opener.clone(),
+                    _ => return,
+                };
+                let mut closures = Closures::default();
+                let data_channel = (opener.0)(&mut closures);
+                let mut inner = inner.borrow_mut();
+                inner.receive_on(&data_channel);
+                inner.data_channel = Some(data_channel);
+                inner.channel_closures = closures;
+            })
+        };
+        // only binary frames can be encrypted
+        let mode = if encrypted {
+            ChannelMode::Binary
+        } else {
+            config.mode
+        };
+        self.inner.borrow_mut().channel_mode = mode;
         set_peer_connection_on_data_channel(
             &peer_connection,
-            self.clone(),
+            Rc::downgrade(&self.inner),
             on_open_callback,
-            on_message_callback,
+            &mut closures,
+        );
+
+        set_peer_connection_on_ice_candidate(
+            &peer_connection,
+            signaling.clone(),
+            session_id,
+            compression.clone(),
+            &mut closures,
         );
+        let ice_restart = IceRestart {
+            attempts: ice_restarts,
+            remaining: Rc::new(Cell::new(ice_restarts)),
+            restarting: Rc::default(),
+            signaling: signaling.clone(),
+            session_id,
+            compression: compression.clone(),
+            session_owner: session_owner.clone(),
+            sdp_transform: sdp_transform.clone(),
+        };
+        set_window_on_network_change(
+            &peer_connection,
+            on_network_change,
+            ice_restart.clone(),
+            &mut closures,
+        );
+        set_peer_connection_on_ice_connection_state_change(
+            &peer_connection,
+            on_state_change,
+            ice_restart,
+            &mut closures,
+        );
+        set_peer_connection_on_ice_gathering_state_change(&peer_connection, &mut closures);
+        set_peer_connection_on_negotiation_needed(&peer_connection, &mut closures);
+        let mut handle_signal_message = signal_message_handler(
+            peer_connection,
+            signaling.clone(),
+            compression,
+            session_owner.clone(),
+            user_id,
+            fingerprint_check,
+            sdp_transform,
+            pings,
+            PendingCandidates::default(),
+            initial_channel,
+        );
+        let on_signal_message = move |message| {
+            on_signal_message.call(&message);
+            handle_signal_message(message)
+        };
+        match &signaling {
+            Signaling::Server(link) => link.connect(session_id, on_signal_message),
+            Signaling::Shared(link) => link.connect(on_signal_message),
+            Signaling::InPage(peer) => {
+                peer.set_on_message(on_signal_message);
+                if peer.join() {
+                    session_owner.set(Some(false));
+                }
+            }
+        }
+
+        self.inner.borrow_mut().closures.append(closures);
+        Ok(())
+    }
+
+    /// Alternative to [NetworkManager::start] for game loops, which take the messages received
+    /// with [NetworkManager::drain_received] at a fixed point of their tick, instead of handling
+    /// them in a callback run whenever they arrive.
+    pub fn start_polling<T: DeserializeOwned + 'static>(
+        &mut self,
+        config: ChannelConfig,
+        on_open_callback: impl FnMut() + Clone + 'static,
+    ) -> Result<(), JsValue> {
+        let received = Rc::new(RefCell::new(Inbox::new(config.max_received)));
+        self.inner.borrow_mut().received = received.clone();
+        self.start(config, on_open_callback, move |message: T| {
+            received.borrow_mut().push(Box::new(message));
+        })
+    }
+
+    /// Messages received since the last call, oldest first,
+    /// once started with [NetworkManager::start_polling] for the same `T`.
+    pub fn drain_received<T: 'static>(&self) -> Vec<T> {
+        self.inner.borrow().received.borrow_mut().drain()
+    }
+
+    /// Number of messages waiting for [NetworkManager::drain_received].
+    pub fn received_len(&self) -> usize {
+        self.inner.borrow().received.borrow().len()
+    }
+
+    /// Number of messages dropped because more than [ChannelConfig::max_received] waited
+    /// for [NetworkManager::drain_received]. Growing, it tells the application is falling
+    /// behind the rate the other peer sends at.
+    pub fn dropped_received(&self) -> u64 {
+        self.inner.borrow().received.borrow().dropped()
+    }
+
+    /// Wait for the data channel to open, as an alternative to the `on_open_callback` of
+    /// [NetworkManager::start] for setup code written in sequence:
+    /// `manager.connected().await?` before the first [NetworkManager::send_message].
+    ///
+    /// Resolves right away if the channel already opened. Fails if the channel errors or closes
+    /// before opening, or if it did not open within 30 seconds.
+    pub fn connected(&self) -> impl Future<Output = Result<(), WebRtcError>> {
+        let outcome = self.inner.borrow().open_hook.wait(OPEN_TIMEOUT_MS);
+        // the sender is only dropped unsent along with the network manager
+        async move { outcome.await.unwrap_or(Err(WebRtcError::Closed)) }
+    }
+
+    /// Replace the `on_message_callback` given to [NetworkManager::start], to handle the messages
+    /// differently from now on, for example once a game leaves its lobby.
+    ///
+    /// The messages may then be of another type. With [NetworkManager::start_polling],
+    /// they go to the callback instead of [NetworkManager::drain_received].
+    /// Fails before [NetworkManager::start].
+    pub fn set_on_message<T: DeserializeOwned>(
+        &self,
+        on_message_callback: impl FnMut(T) + Clone + 'static,
+    ) -> Result<(), JsValue> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.message_handler.is_none() {
+            return Err(JsValue::from_str(
+                "cannot set the message handler of a channel never started",
+            ));
+        }
+        let message_handler = MessageHandler::new(&inner, on_message_callback);
+        let receiving: Vec<_> = inner
+            .receiving
+            .iter()
+            .filter(|(data_channel, _)| data_channel.ready_state() != RtcDataChannelState::Closed)
+            .map(|(data_channel, _)| (data_channel.clone(), message_handler.attach(data_channel)))
+            .collect();
+        // drops the closures of the previous handler, detached from the channels above
+        inner.receiving = receiving;
+        inner.message_handler = Some(message_handler);
+        Ok(())
+    }
+
+    /// Set a callback run once the data channel is closed by the other peer,
+    /// or when the connection between them drops.
+    /// It is not run when closing the connection with [NetworkManager::close].
+    pub fn on_disconnect(&self, callback: impl FnMut() + 'static) {
+        self.inner.borrow().on_disconnect.set(callback);
+    }
+
+    /// Set a callback run once the signaling server still did not answer that the session is ready
+    /// after [ConnectionConfig::join_retries] retries of the request to join it,
+    /// either because the server is unreachable or because the other peer never joined.
+    /// Never run without retries.
+    pub fn on_join_timeout(&self, callback: impl FnOnce() + 'static) {
+        self.inner.borrow().join_retry.set_on_timeout(callback);
+    }
 
-        set_peer_connection_on_ice_candidate(&peer_connection, websocket.clone(), session_id);
-        set_peer_connection_on_ice_connection_state_change(&peer_connection);
-        set_peer_connection_on_ice_gathering_state_change(&peer_connection);
-        set_peer_connection_on_negotiation_needed(&peer_connection);
-        set_websocket_on_open(&websocket, session_id);
-        set_websocket_on_message(&websocket, peer_connection);
+    /// Current state of the connection with the other peer.
+    pub fn peer_connection_state(&self) -> PeerState {
+        PeerState::from_ice_connection_state(
+            self.inner.borrow().peer_connection.ice_connection_state(),
+        )
+    }
+
+    /// Set a callback run each time the state of the connection with the other peer changes,
+    /// see [NetworkManager::peer_connection_state].
+    /// Must be called before [NetworkManager::start] to be told of the first changes.
+    pub fn on_state_change(&self, callback: impl FnMut(PeerState) + 'static) {
+        self.inner.borrow().on_state_change.set(callback);
+    }
+
+    /// Set a callback run when the browser goes offline, with `false`, and back online, with `true`,
+    /// to tell the player the game is reconnecting for example.
+    ///
+    /// Once back online, ICE is restarted right away if the connection dropped meanwhile,
+    /// as when it fails, see [ConnectionConfig::ice_restarts].
+    pub fn on_network_change(&self, callback: impl FnMut(bool) + 'static) {
+        self.inner.borrow().on_network_change.set(callback);
+    }
+
+    /// Set a callback run on each message received from the signaling server, before this peer
+    /// handles it, to follow the session being ready for example.
+    /// Also run on the messages of the other peer of the page with [ConnectionType::InPage].
+    /// Must be called before [NetworkManager::start] to be told of the first messages.
+    ///
+    /// The [SignalMessage::Custom] ones sent by the other peer with [NetworkManager::send_signal]
+    /// are only handed to this callback.
+    pub fn on_signal_message(&self, callback: impl FnMut(&SignalMessage) + 'static) {
+        self.inner.borrow().on_signal_message.set(callback);
+    }
+
+    /// Send `payload` to the other peer through the signaling server, as a [SignalMessage::Custom]
+    /// given to its [NetworkManager::on_signal_message] callback.
+    ///
+    /// This works as soon as both peers joined the session, before the data channel opens,
+    /// to exchange the metadata of a game lobby for example. The signaling server answers
+    /// with a [SignalMessage::Error] when the other peer is not in the session yet.
+    pub fn send_signal(&self, payload: Vec<u8>) -> Result<(), JsValue> {
+        let inner = self.inner.borrow();
+        let message = SignalMessage::Custom(inner.session_id, payload);
+        inner.signaling.send(message, inner.compression.get())
+    }
+
+    /// Whether this peer owns the session, having created it or joined it first,
+    /// which makes it the one deciding how the session is configured.
+    /// `None` until both peers joined the session.
+    pub fn is_session_owner(&self) -> Option<bool> {
+        self.inner.borrow().session_owner.get()
+    }
+
+    /// Id the signaling server gave this peer in the session, `None` until both peers joined it.
+    /// Sent back to the server when reconnecting, to take the same slot in the session again.
+    pub fn user_id(&self) -> Option<UserId> {
+        self.inner.borrow().user_id.get()
+    }
+
+    /// Set a check of the DTLS fingerprint announced in the SDP of the other peer,
+    /// run before its offer or answer is applied.
+    ///
+    /// Returning `false`, or the SDP lacking a fingerprint, closes the connection.
+    /// Comparing the fingerprint with one received out-of-band, see [NetworkManager::local_fingerprint],
+    /// protects against a malicious signaling server swapping the SDP of the peers.
+    /// Must be called before [NetworkManager::start] to apply to the initial connection.
+    pub fn verify_fingerprint(&self, callback: impl FnMut(&DtlsFingerprint) -> bool + 'static) {
+        self.inner.borrow().fingerprint_check.set(callback);
+    }
+
+    /// Set an edit of the SDP of this peer, applied to each offer and answer before it is set
+    /// as the local description and sent to the other peer, to cap the bandwidth for example.
+    ///
+    /// The browser refuses some edits, failing the negotiation.
+    /// Must be called before [NetworkManager::start] to apply to the initial connection.
+    pub fn transform_sdp(&self, transform: impl Fn(String) -> String + 'static) {
+        self.inner.borrow().sdp_transform.set(transform);
+    }
+
+    /// DTLS fingerprint of this peer, to share with the other one out-of-band.
+    /// `None` until the local description is set during the connection setup.
+    pub fn local_fingerprint(&self) -> Option<DtlsFingerprint> {
+        DtlsFingerprint::from_sdp(&self.local_sdp()?)
+    }
+
+    /// SDP of this peer as negotiated, to dump when debugging the connection setup.
+    /// `None` until the local description is set.
+    pub fn local_sdp(&self) -> Option<String> {
+        let description = self.inner.borrow().peer_connection.local_description()?;
+        Some(description.sdp())
+    }
 
+    /// SDP of the other peer as negotiated, `None` until it is received.
+    pub fn remote_sdp(&self) -> Option<String> {
+        let description = self.inner.borrow().peer_connection.remote_description()?;
+        Some(description.sdp())
+    }
+
+    /// Local and remote candidates the connection goes through, telling for example
+    /// whether it is relayed by a TURN server. `None` until the connection is established.
+    pub fn selected_candidate_pair(
+        &self,
+    ) -> impl Future<Output = Result<Option<CandidatePair>, JsValue>> {
+        let peer_connection = self.inner.borrow().peer_connection.clone();
+        async move { stats::selected_candidate_pair(&peer_connection).await }
+    }
+
+    /// Replace the data channel with a new one, with the configuration and callbacks given to
+    /// [NetworkManager::start], for when the channel closed but the connection is still up.
+    ///
+    /// This recovers much faster than a new connection, as the ICE and DTLS setup are kept.
+    /// The other peer receives the new channel in place of its own, and `on_open_callback`
+    /// runs again on both ends once it opens. Fails before [NetworkManager::start] or once the
+    /// connection is closed.
+    pub fn reopen_channel(&self) -> Result<(), JsValue> {
+        let (opener, peer_connection) = {
+            let inner = self.inner.borrow();
+            (inner.channel_opener.clone(), inner.peer_connection.clone())
+        };
+        let opener =
+            opener.ok_or_else(|| JsValue::from_str("cannot reopen a channel never started"))?;
+        if peer_connection.signaling_state() == RtcSignalingState::Closed {
+            return Err(JsValue::from_str(
+                "cannot reopen a channel, the connection is closed",
+            ));
+        }
+        let mut closures = Closures::default();
+        let data_channel = (opener.0)(&mut closures);
+
+        let mut inner = self.inner.borrow_mut();
+        inner.receive_on(&data_channel);
+        if let Some(data_channel) = inner.data_channel.replace(data_channel) {
+            close_data_channel(&data_channel);
+        }
+        inner.on_disconnect.rearm();
+        // the handlers of the previous channel were unset when closing it
+        inner.channel_closures = closures;
+        Ok(())
+    }
+
+    /// Re-run the SDP offer/answer exchange through the signaling server,
+    /// for example after adding a data channel to the connection.
+    ///
+    /// The answer is applied once it arrives, the same way as during the initial connection.
+    /// Only one of the peers should renegotiate at a time, this fails if a negotiation
+    /// is already in progress on this end.
+    ///
+    /// When the connection to the signaling server was released, see
+    /// [ConnectionConfig::keep_signaling_open], both peers connect to it again first,
+    /// the other one being told so through the data channel, and offer once the server sees them both.
+    pub fn renegotiate(&self) -> Result<(), JsValue> {
+        let (signaling, peer_connection, session_id, compression, sdp_transform) = {
+            let inner = self.inner.borrow();
+            (
+                inner.signaling.clone(),
+                inner.peer_connection.clone(),
+                inner.session_id,
+                inner.compression.clone(),
+                inner.sdp_transform.clone(),
+            )
+        };
+        if peer_connection.signaling_state() != RtcSignalingState::Stable {
+            return Err(JsValue::from_str(
+                "cannot renegotiate, a negotiation is already in progress",
+            ));
+        }
+        if signaling.is_released() {
+            let channel = self.open_datachannel()?;
+            signaling.reopen()?;
+            let frame = acknowledgement::reopen_signaling_frame();
+            let frame = self.inner.borrow().encryption.seal_binary(frame)?;
+            channel.send_with_u8_array(&frame)?;
+            return Ok(());
+        }
+        wasm_bindgen_futures::spawn_local(async move {
+            send_sdp_offer(
+                &peer_connection,
+                &signaling,
+                session_id,
+                compression.get(),
+                &sdp_transform,
+                false,
+            )
+            .await
+            .unwrap_or_else(|error| error!("failed to renegotiate: {error:?}"));
+        });
         Ok(())
     }
 
+    /// Measure the round trip time to the other peer through the signaling server, in milliseconds,
+    /// given to `on_rtt` once the other peer answered. It is never called if the answer
+    /// takes more than 10 seconds, and is likely lost.
+    ///
+    /// Unlike messages, this works as soon as both peers joined the session, before the data
+    /// channel opens, giving an early estimate of the quality of the connection.
+    pub fn measure_signaling_rtt(&self, on_rtt: impl FnOnce(f64) + 'static) -> Result<(), JsValue> {
+        let inner = self.inner.borrow();
+        let token = inner.pings.start(on_rtt);
+        let ping = SignalMessage::Ping(inner.session_id, token);
+        inner
+            .signaling
+            .send(ping, inner.compression.get())
+            .inspect_err(|_| inner.pings.cancel(token))
+    }
+
+    /// Round trip time to the other peer in milliseconds, measured by the ICE agent on the
+    /// connection itself rather than through the signaling server, see [NetworkManager::measure_signaling_rtt].
+    /// `None` until the connection is established, or when the browser doesn't report it.
+    pub fn round_trip_time(&self) -> impl Future<Output = Result<Option<f64>, JsValue>> {
+        let peer_connection = self.inner.borrow().peer_connection.clone();
+        async move { stats::round_trip_time(&peer_connection).await }
+    }
+
+    /// Session id this network manager was created with.
+    pub fn session_id(&self) -> SessionId {
+        self.inner.borrow().session_id
+    }
+
     fn datachannel(&self) -> Ref<'_, Option<RtcDataChannel>> {
         let data_channel = &*self.inner;
         let borrowed = data_channel.borrow();
         Ref::map(borrowed, |t| &t.data_channel)
     }
 
+    /// Data channel to send messages on, [WebRtcError::NotConnected] unless it is open.
+    fn open_datachannel(&self) -> Result<RtcDataChannel, WebRtcError> {
+        match &*self.datachannel() {
+            Some(channel) if channel.ready_state() == RtcDataChannelState::Open => {
+                Ok(channel.clone())
+            }
+            _ => Err(WebRtcError::NotConnected),
+        }
+    }
+
     /// Send message to the other end of the connection.
-    /// It might fail if the connection is not yet set up
-    /// and thus should only be called after `on_open_callback` triggers.
-    /// Otherwise it will result in an error.
-    pub fn send_message<T: Serialize>(&self, message: &T) {
+    /// It fails with [WebRtcError::NotConnected] until `on_open_callback` triggers,
+    /// and once the data channel closes.
+    pub fn send_message<T: Serialize>(&self, message: &T) -> Result<(), WebRtcError> {
+        self.send_message_with_priority(message, Priority::Normal)
+    }
+
+    /// Same as [NetworkManager::send_message], with [Priority::High] messages sent before
+    /// the [Priority::Normal] ones still waiting for the data channel to drain.
+    ///
+    /// Messages of the same priority are sent in order. They only wait once the data channel
+    /// buffers 64 KiB, until it is down to 16 KiB.
+    pub fn send_message_with_priority<T: Serialize>(
+        &self,
+        message: &T,
+        priority: Priority,
+    ) -> Result<(), WebRtcError> {
         debug!("server will try to send a message");
+        let channel = self.open_datachannel()?;
+        let outbox = {
+            let inner = self.inner.borrow();
+            #[cfg(feature = "netsim")]
+            let simulated = inner.network_simulation.is_some();
+            #[cfg(not(feature = "netsim"))]
+            let simulated = false;
+            let plain_binary =
+                inner.channel_mode == ChannelMode::Binary && !inner.encryption.is_enabled();
+            (plain_binary && !simulated).then(|| inner.outbox.clone())
+        };
+        // most messages, sent without copying them out of the buffer they are serialized in
+        if let Some(outbox) = outbox {
+            let mut outbox = outbox.borrow_mut();
+            outbox.send_serialized(&channel, message, priority, MAX_BUFFERED_AMOUNT);
+            return Ok(());
+        }
+        let payload = encode_message(message, self.inner.borrow().channel_mode);
+        let payload = self.seal(payload)?;
+        self.enqueue(&channel, payload, priority);
+        Ok(())
+    }
+
+    /// Same as [NetworkManager::send_message], repeating in the same frame up to `previous` of
+    /// the messages sent before this way, so that the other peer recovers as many lost messages
+    /// in a row from the next one it receives, without waiting for a retransmission.
+    ///
+    /// Meant for messages superseding the previous ones on an unreliable channel, like the state
+    /// of a game sent every tick: the other peer drops those arriving after a newer one.
+    /// At most 8 previous messages are repeated, each one growing the frame by its size.
+    /// The messages are sent in binary whatever the [ChannelConfig::mode].
+    pub fn send_message_with_redundancy<T: Serialize>(
+        &self,
+        message: &T,
+        previous: usize,
+    ) -> Result<(), WebRtcError> {
+        let channel = self.open_datachannel()?;
+        let message = rmp_serde::to_vec(message).unwrap();
+        let frame = self.inner.borrow_mut().redundancy.frame(message, previous);
+        let payload = self.seal(Payload::Binary(frame))?;
+        self.enqueue(&channel, payload, Priority::Normal);
+        Ok(())
+    }
+
+    /// Send `payload` once the data channel drained, or right away.
+    fn enqueue(&self, channel: &RtcDataChannel, payload: Payload, priority: Priority) {
+        #[cfg(feature = "netsim")]
+        if let Some(simulation) = &self.inner.borrow().network_simulation {
+            crate::netsim::send_with_simulation(channel, payload, simulation);
+            return;
+        }
+        let outbox = self.inner.borrow().outbox.clone();
+        let mut outbox = outbox.borrow_mut();
+        outbox.push(payload, priority);
+        outbox.flush(channel, MAX_BUFFERED_AMOUNT);
+    }
+
+    fn seal(&self, payload: Payload) -> Result<Payload, WebRtcError> {
+        self.inner
+            .borrow()
+            .encryption
+            .seal(payload)
+            .map_err(|error| WebRtcError::Encryption(js_error_message(&error)))
+    }
+
+    /// Send a text frame to the other end of the connection, as is.
+    ///
+    /// A Rust peer receives it as the `String` itself, or decoded as JSON into its message type.
+    /// The text is cut to [ConnectionConfig::max_text_len] characters.
+    /// Fails with [WebRtcError::Encryption] when encrypting messages, as text frames can't be encrypted.
+    pub fn send_text(&self, message: &str) -> Result<(), WebRtcError> {
+        let channel = self.open_datachannel()?;
+        let message = match self.inner.borrow().max_text_len {
+            Some(max_len) => clamp_text(message, max_len),
+            None => message,
+        };
+        let payload = self.seal(Payload::Text(message.to_string()))?;
+        self.enqueue(&channel, payload, Priority::Normal);
+        Ok(())
+    }
+
+    /// Send message to the other end of the connection, and wait for the other peer
+    /// to acknowledge it.
+    ///
+    /// The message is sent again every 250ms until acknowledged, at most `max_retries` times,
+    /// after which the returned future resolves to an error. The other peer receives it
+    /// through its `on_message_callback` exactly once, like any other message.
+    /// Messages sent this way are not ordered relative to each other nor to [NetworkManager::send_message].
+    pub fn send_reliable_with_ack<T: Serialize>(
+        &self,
+        message: &T,
+        max_retries: u32,
+    ) -> impl Future<Output = Result<(), JsValue>> {
+        let promise = self.send_reliable(message, max_retries);
+        async move { JsFuture::from(promise?).await.map(drop) }
+    }
+
+    fn send_reliable<T: Serialize>(
+        &self,
+        message: &T,
+        max_retries: u32,
+    ) -> Result<Promise, JsValue> {
+        let channel = self.open_datachannel()?;
+        let acknowledgements = self.inner.borrow().acknowledgements.clone();
+        let message = rmp_serde::to_vec(message).unwrap();
+        let sequence = acknowledgements.borrow_mut().next_sequence();
+        let frame = acknowledgement::reliable_frame(sequence, &message);
+        let frame = self.inner.borrow().encryption.seal_binary(frame)?;
+        channel.send_with_u8_array(&frame)?;
+
+        Ok(Promise::new(&mut |resolve, reject| {
+            acknowledgements.borrow_mut().wait_for(sequence, resolve);
+            acknowledgement::schedule_retry(
+                channel.clone(),
+                acknowledgements.clone(),
+                sequence,
+                frame.clone(),
+                max_retries,
+                reject,
+            );
+        }))
+    }
+
+    /// Close the connection and the signaling websocket, and free the callbacks given to
+    /// [NetworkManager::start]. Messages sent afterwards are dropped.
+    pub fn close(&self) {
+        self.inner.borrow_mut().close();
+    }
+
+    /// Delay and drop outgoing messages according to `simulation`, text and binary alike,
+    /// or go back to sending them immediately with `None`.
+    #[cfg(feature = "netsim")]
+    pub fn simulate_network(&self, simulation: Option<crate::NetworkSimulation>) {
+        self.inner.borrow_mut().network_simulation = simulation;
+    }
+}
+
+/// Payload sent for `message` by [NetworkManager::send_message] on a channel in `mode`.
+pub(crate) fn encode_message<T: Serialize>(message: &T, mode: ChannelMode) -> Payload {
+    match mode {
+        ChannelMode::Text => Payload::Text(serde_json::to_string(message).unwrap()),
         // FIXME(tkarwowski): this is an ugly fix to the fact, that if you send empty string as message
         //  webrtc fails with a cryptic "The operation failed for an operation-specific reason"
         //  message
-        let message = rmp_serde::to_vec(message).unwrap();
-        if let Some(channel) = &*self.datachannel() {
-            let _ = channel.send_with_u8_array(&message);
-        }
+        ChannelMode::Binary => Payload::Binary(rmp_serde::to_vec(message).unwrap()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    /// Resolves once the tasks spawned so far ran, timers only running after them.
+    async fn settle() {
+        let promise = Promise::new(&mut |resolve, _| {
+            web_sys::window()
+                .unwrap()
+                .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, 0)
+                .unwrap();
+        });
+        JsFuture::from(promise).await.unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    fn test_sending_before_the_channel_opens_fails() {
+        let network_manager =
+            NetworkManager::new("", SessionId::new(670), ConnectionType::InPage).unwrap();
+        assert_eq!(
+            network_manager.send_message(&"too early"),
+            Err(WebRtcError::NotConnected)
+        );
+        assert_eq!(
+            network_manager.send_text("too early"),
+            Err(WebRtcError::NotConnected)
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_sessions_are_only_created_by_a_signaling_server() {
+        let created =
+            NetworkManager::create_session("", ConnectionType::InPage, ConnectionConfig::default())
+                .await;
+        assert!(created.is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_only_the_session_owner_creates_the_data_channel() {
+        let session_id = SessionId::new(692);
+        let mut owner = NetworkManager::new("", session_id, ConnectionType::InPage).unwrap();
+        let mut guest = NetworkManager::new("", session_id, ConnectionType::InPage).unwrap();
+        owner
+            .start(ChannelConfig::default(), || {}, |_: String| {})
+            .unwrap();
+        assert!(owner.datachannel().is_none());
+        guest
+            .start(ChannelConfig::default(), || {}, |_: String| {})
+            .unwrap();
+        assert_eq!(guest.is_session_owner(), Some(false));
+        assert!(guest.datachannel().is_none());
+
+        // the owner handles the session being ready on the next tick
+        JsFuture::from(Promise::resolve(&JsValue::NULL))
+            .await
+            .unwrap();
+        assert_eq!(owner.is_session_owner(), Some(true));
+        assert!(owner.datachannel().is_some());
+        owner.close();
+        guest.close();
+    }
+
+    #[wasm_bindgen_test]
+    fn test_a_shared_websocket_joins_each_session_once() {
+        let config = || ConnectionConfig {
+            share_websocket: true,
+            ..ConnectionConfig::default()
+        };
+        let hostname = "localhost:9001";
+        let first = NetworkManager::with_config(
+            hostname,
+            SessionId::new(698),
+            ConnectionType::Local,
+            config(),
+        )
+        .unwrap();
+        assert!(NetworkManager::with_config(
+            hostname,
+            SessionId::new(698),
+            ConnectionType::Local,
+            config(),
+        )
+        .is_err());
+        let other = NetworkManager::with_config(
+            hostname,
+            SessionId::new(699),
+            ConnectionType::Local,
+            config(),
+        )
+        .unwrap();
+        first.close();
+        other.close();
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_signal_messages_are_observed() {
+        let session_id = SessionId::new(696);
+        let mut owner = NetworkManager::new("", session_id, ConnectionType::InPage).unwrap();
+        let mut guest = NetworkManager::new("", session_id, ConnectionType::InPage).unwrap();
+        let observed = Rc::new(RefCell::new(Vec::new()));
+        owner.on_signal_message({
+            let observed = observed.clone();
+            move |message| observed.borrow_mut().push(message.clone())
+        });
+        owner
+            .start(ChannelConfig::default(), || {}, |_: String| {})
+            .unwrap();
+        guest
+            .start(ChannelConfig::default(), || {}, |_: String| {})
+            .unwrap();
+
+        settle().await;
+        assert!(matches!(
+            observed.borrow().first(),
+            Some(SignalMessage::SessionReady(id, true, _)) if *id == session_id
+        ));
+
+        guest.send_signal(b"lobby".to_vec()).unwrap();
+        settle().await;
+        assert!(observed.borrow().iter().any(|message| matches!(
+            message,
+            SignalMessage::Custom(id, payload) if *id == session_id && payload == b"lobby"
+        )));
+        owner.close();
+        guest.close();
     }
 }
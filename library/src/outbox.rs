@@ -0,0 +1,146 @@
+/*!
+Messages waiting for the data channel to drain before being sent.
+
+The browser buffers whatever is sent on a data channel, in order. Once too much is buffered,
+messages are held here instead, so that [Priority::High] ones can go ahead of those already waiting.
+ */
+
+use crate::transport::{Payload, Transport};
+use log::error;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// Bytes buffered by the data channel above which messages wait in the outbox.
+pub(crate) const MAX_BUFFERED_AMOUNT: u32 = 64 * 1024;
+/// Bytes buffered by the data channel below which the outbox is sent again.
+pub(crate) const LOW_BUFFERED_AMOUNT: u32 = 16 * 1024;
+
+/// Priority of a message sent with [crate::one_to_one::NetworkManager::send_message_with_priority].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Priority {
+    /// Sent before every waiting [Priority::Normal] message, for rare events that must not lag.
+    High,
+    /// Sent in order with other messages, for frequent updates.
+    #[default]
+    Normal,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Outbox {
+    high: VecDeque<Payload>,
+    normal: VecDeque<Payload>,
+    /// Messages sent right away are serialized here, kept across messages to not allocate each time
+    buffer: Vec<u8>,
+}
+
+impl Outbox {
+    /// Sends `message` serialized in binary, right away when no message is waiting and the
+    /// `transport` buffers less than `max_buffered` bytes, only then copying it to wait otherwise.
+    pub(crate) fn send_serialized<T: Serialize>(
+        &mut self,
+        transport: &impl Transport,
+        message: &T,
+        priority: Priority,
+        max_buffered: u32,
+    ) {
+        self.buffer.clear();
+        rmp_serde::encode::write(&mut self.buffer, message).unwrap();
+        let waiting = !self.high.is_empty() || !self.normal.is_empty();
+        if waiting || transport.buffered_amount() >= max_buffered {
+            self.push(Payload::Binary(self.buffer.clone()), priority);
+            self.flush(transport, max_buffered);
+        } else if let Err(error) = transport.send_binary(&self.buffer) {
+            error!("failed to send a message: {error:?}");
+        }
+    }
+
+    pub(crate) fn push(&mut self, payload: Payload, priority: Priority) {
+        match priority {
+            Priority::High => self.high.push_back(payload),
+            Priority::Normal => self.normal.push_back(payload),
+        }
+    }
+
+    /// Sends waiting messages, high priority ones first, until `max_buffered` bytes are buffered
+    /// by the `transport`.
+    pub(crate) fn flush(&mut self, transport: &impl Transport, max_buffered: u32) {
+        while transport.buffered_amount() < max_buffered {
+            let payload = match self.high.pop_front().or_else(|| self.normal.pop_front()) {
+                Some(payload) => payload,
+                None => return,
+            };
+            if let Err(error) = transport.send(payload) {
+                error!("failed to send a message: {error:?}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::Loopback;
+
+    #[test]
+    fn high_priority_message_jumps_the_queue() {
+        let (sender, receiver) = Loopback::pair();
+        let received = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let on_message = {
+            let received = received.clone();
+            Box::new(move |payload| received.borrow_mut().push(payload))
+        };
+        receiver.set_on_message(on_message, &mut Default::default());
+
+        let mut outbox = Outbox::default();
+        for state in ["state 1", "state 2", "state 3", "state 4"] {
+            outbox.push(Payload::Text(state.to_string()), Priority::Normal);
+            outbox.flush(&sender, 10);
+        }
+        outbox.push(Payload::Text("goal".to_string()), Priority::High);
+        outbox.flush(&sender, 10);
+        // the first two states fill the buffer of the channel, the others wait
+        assert_eq!(receiver.deliver(), 2);
+        outbox.flush(&sender, 10);
+        receiver.deliver();
+        outbox.flush(&sender, 10);
+        receiver.deliver();
+
+        let received: Vec<_> = received
+            .borrow()
+            .iter()
+            .map(|payload| match payload {
+                Payload::Text(text) => text.clone(),
+                Payload::Binary(_) => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            received,
+            ["state 1", "state 2", "goal", "state 3", "state 4"]
+        );
+    }
+
+    #[test]
+    fn serialized_messages_wait_behind_the_queue() {
+        let (sender, receiver) = Loopback::pair();
+        let received = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let on_message = {
+            let received = received.clone();
+            Box::new(move |payload| received.borrow_mut().push(payload))
+        };
+        receiver.set_on_message(on_message, &mut Default::default());
+
+        let mut outbox = Outbox::default();
+        outbox.send_serialized(&sender, &1u8, Priority::Normal, 2);
+        let capacity = outbox.buffer.capacity();
+        // the channel is full, the next ones wait
+        outbox.send_serialized(&sender, &2u8, Priority::Normal, 1);
+        outbox.send_serialized(&sender, &3u8, Priority::Normal, 2);
+        receiver.deliver();
+        outbox.flush(&sender, 2);
+        receiver.deliver();
+
+        let encoded = |value: u8| Payload::Binary(rmp_serde::to_vec(&value).unwrap());
+        assert_eq!(*received.borrow(), [encoded(1), encoded(2), encoded(3)]);
+        assert_eq!(outbox.buffer.capacity(), capacity);
+    }
+}
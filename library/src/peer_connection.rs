@@ -0,0 +1,160 @@
+/*!
+Operations on the WebRTC peer connection done while answering the other peer's signaling messages.
+
+In the browser this is the `RtcPeerConnection` itself. Tests use a recording stand-in instead,
+so that the handling of offers, answers and ICE candidates can be exercised without a browser.
+ */
+
+use crate::callbacks::SdpTransform;
+use crate::error::WebRtcError;
+use crate::utils::{create_local_answer, set_remote_description};
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use wasm_peers_protocol::one_to_one::IceCandidate;
+use web_sys::{RtcIceCandidate, RtcIceCandidateInit, RtcPeerConnection, RtcSdpType};
+
+pub(crate) trait PeerConnection {
+    /// Apply the SDP of the other peer, rolling the connection back if it is refused.
+    async fn set_remote_description(
+        &self,
+        sdp_type: RtcSdpType,
+        sdp: &str,
+    ) -> Result<(), WebRtcError>;
+
+    /// Answer the offer set as the remote description, and set the answer as the local one.
+    async fn create_answer(&self, sdp_transform: &SdpTransform) -> Result<String, JsValue>;
+
+    async fn add_ice_candidate(&self, candidate: &IceCandidate) -> Result<(), JsValue>;
+
+    /// Whether an offer or answer of the other peer was applied, which ICE candidates need first.
+    fn has_remote_description(&self) -> bool;
+
+    fn close(&self);
+}
+
+impl PeerConnection for RtcPeerConnection {
+    async fn set_remote_description(
+        &self,
+        sdp_type: RtcSdpType,
+        sdp: &str,
+    ) -> Result<(), WebRtcError> {
+        set_remote_description(self, sdp_type, sdp).await
+    }
+
+    async fn create_answer(&self, sdp_transform: &SdpTransform) -> Result<String, JsValue> {
+        create_local_answer(self, sdp_transform).await
+    }
+
+    async fn add_ice_candidate(&self, candidate: &IceCandidate) -> Result<(), JsValue> {
+        let rtc_candidate = RtcIceCandidateInit::new("");
+        rtc_candidate.set_candidate(&candidate.candidate);
+        rtc_candidate.set_sdp_m_line_index(candidate.sdp_m_line_index);
+        rtc_candidate.set_sdp_mid(candidate.sdp_mid.as_deref());
+        let rtc_candidate = RtcIceCandidate::new(&rtc_candidate)?;
+        JsFuture::from(self.add_ice_candidate_with_opt_rtc_ice_candidate(Some(&rtc_candidate)))
+            .await?;
+        Ok(())
+    }
+
+    fn has_remote_description(&self) -> bool {
+        self.remote_description().is_some()
+    }
+
+    fn close(&self) {
+        RtcPeerConnection::close(self);
+    }
+}
+
+/// ICE candidates of the other peer received before its offer or answer was applied,
+/// shared by the handlers of the signaling messages, which may run interleaved.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PendingCandidates(Rc<RefCell<Vec<IceCandidate>>>);
+
+impl PendingCandidates {
+    /// Add `candidate` to `peer`, or keep it until the remote description is set.
+    pub(crate) async fn add<P: PeerConnection>(
+        &self,
+        peer: &P,
+        candidate: IceCandidate,
+    ) -> Result<(), JsValue> {
+        if !peer.has_remote_description() {
+            self.0.borrow_mut().push(candidate);
+            return Ok(());
+        }
+        peer.add_ice_candidate(&candidate).await
+    }
+
+    /// Add the candidates kept so far, once the remote description is set.
+    pub(crate) async fn flush<P: PeerConnection>(&self, peer: &P) -> Result<(), JsValue> {
+        let pending = std::mem::take(&mut *self.0.borrow_mut());
+        for candidate in &pending {
+            peer.add_ice_candidate(candidate).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) use recorder::{Call, Recorder};
+
+#[cfg(test)]
+mod recorder {
+    use super::PeerConnection;
+    use crate::callbacks::SdpTransform;
+    use crate::error::WebRtcError;
+    use std::cell::{Cell, RefCell};
+    use wasm_bindgen::JsValue;
+    use wasm_peers_protocol::one_to_one::IceCandidate;
+    use web_sys::RtcSdpType;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) enum Call {
+        SetRemoteDescription(RtcSdpType, String),
+        CreateAnswer,
+        AddIceCandidate(String),
+        Close,
+    }
+
+    /// Peer connection recording the calls made to it, which all succeed.
+    #[derive(Default)]
+    pub(crate) struct Recorder {
+        pub(crate) calls: RefCell<Vec<Call>>,
+        remote_description: Cell<bool>,
+    }
+
+    impl PeerConnection for Recorder {
+        async fn set_remote_description(
+            &self,
+            sdp_type: RtcSdpType,
+            sdp: &str,
+        ) -> Result<(), WebRtcError> {
+            self.calls
+                .borrow_mut()
+                .push(Call::SetRemoteDescription(sdp_type, sdp.to_string()));
+            self.remote_description.set(true);
+            Ok(())
+        }
+
+        async fn create_answer(&self, sdp_transform: &SdpTransform) -> Result<String, JsValue> {
+            self.calls.borrow_mut().push(Call::CreateAnswer);
+            Ok(sdp_transform.apply("answer".to_string()))
+        }
+
+        async fn add_ice_candidate(&self, candidate: &IceCandidate) -> Result<(), JsValue> {
+            self.calls
+                .borrow_mut()
+                .push(Call::AddIceCandidate(candidate.candidate.clone()));
+            Ok(())
+        }
+
+        fn has_remote_description(&self) -> bool {
+            self.remote_description.get()
+        }
+
+        fn close(&self) {
+            self.calls.borrow_mut().push(Call::Close);
+        }
+    }
+}
@@ -0,0 +1,22 @@
+/*!
+The types needed to set up a connection and exchange messages, to import them all at once.
+
+```no_run
+use wasm_peers::prelude::*;
+
+let session_id = get_random_session_id();
+let network_manager = NetworkManager::new(
+    "ws://0.0.0.0:9001/one-to-one",
+    session_id,
+    ConnectionType::Local,
+);
+```
+ */
+
+pub use crate::one_to_one::NetworkManager;
+#[cfg(feature = "encryption")]
+pub use crate::EncryptionKey;
+pub use crate::{
+    get_random_session_id, ChannelConfig, ChannelMode, ConnectionConfig, ConnectionType, PeerState,
+    Priority, Reliability, SessionId, UserId, WebRtcError,
+};
@@ -0,0 +1,116 @@
+/*!
+Redundant copies of messages sent over an unreliable data channel, so that a lost message is
+recovered from the next one to arrive rather than retransmitted.
+
+A redundant frame starts like a reliable one, see [crate::acknowledgement], with its own extension
+type holding the sequence number of its newest message. It is followed by that message and by the
+ones sent before it, newest first, each prefixed with its length as 4 big endian bytes.
+ */
+
+use crate::acknowledgement;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+
+/// Most previous messages repeated in a redundant frame.
+pub(crate) const MAX_REDUNDANCY: usize = 8;
+
+/// Messages sent with redundancy, kept to be repeated in the next frames.
+#[derive(Debug, Default)]
+pub(crate) struct RedundantSender {
+    next_sequence: u32,
+    /// Newest first
+    history: VecDeque<Vec<u8>>,
+}
+
+impl RedundantSender {
+    /// Frame of `message` repeating up to `previous` of the messages sent before it.
+    pub(crate) fn frame(&mut self, message: Vec<u8>, previous: usize) -> Vec<u8> {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        let mut body = Vec::new();
+        let copies = std::iter::once(&message).chain(self.history.iter().take(previous));
+        for copy in copies {
+            body.extend_from_slice(&(copy.len() as u32).to_be_bytes());
+            body.extend_from_slice(copy);
+        }
+        self.history.push_front(message);
+        self.history.truncate(MAX_REDUNDANCY);
+        acknowledgement::redundant_frame(sequence, &body)
+    }
+}
+
+/// Sequence number of the newest message received on a data channel with redundancy.
+#[derive(Debug, Default)]
+pub(crate) struct RedundantReceiver {
+    last: Option<u32>,
+}
+
+impl RedundantReceiver {
+    /// Messages of the frame whose newest message is `sequence` that were not received yet,
+    /// oldest first. Nothing when a newer message was already received, as the frame is stale.
+    pub(crate) fn receive<'a>(&mut self, sequence: u32, body: &'a [u8]) -> Vec<&'a [u8]> {
+        let copies = split_copies(body);
+        let missed = match self.last {
+            // the messages before the first one received may be meant for a previous handler
+            None => 1,
+            Some(last) => {
+                let ahead = sequence.wrapping_sub(last);
+                if ahead == 0 || ahead > u32::MAX / 2 {
+                    return Vec::new();
+                }
+                ahead as usize
+            }
+        };
+        self.last = Some(sequence);
+        copies.into_iter().take(missed).rev().collect()
+    }
+}
+
+/// Messages of a redundant frame body, newest first, up to the first malformed one.
+fn split_copies(mut body: &[u8]) -> Vec<&[u8]> {
+    let mut copies = Vec::new();
+    while body.len() >= 4 {
+        let len = u32::from_be_bytes(body[..4].try_into().unwrap()) as usize;
+        let rest = &body[4..];
+        if rest.len() < len {
+            break;
+        }
+        copies.push(&rest[..len]);
+        body = &rest[len..];
+    }
+    copies
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::acknowledgement::{parse_frame, Frame};
+
+    fn receive(receiver: &mut RedundantReceiver, frame: &[u8]) -> Vec<Vec<u8>> {
+        match parse_frame(frame) {
+            Frame::Redundant(sequence, body) => receiver
+                .receive(sequence, body)
+                .into_iter()
+                .map(<[u8]>::to_vec)
+                .collect(),
+            _ => panic!("not a redundant frame"),
+        }
+    }
+
+    #[test]
+    fn lost_messages_are_recovered_from_the_next_frame() {
+        let mut sender = RedundantSender::default();
+        let mut receiver = RedundantReceiver::default();
+        let frames: Vec<_> = (0..5u8).map(|i| sender.frame(vec![i], 2)).collect();
+
+        assert_eq!(receive(&mut receiver, &frames[0]), vec![vec![0]]);
+        // frames 1 and 2 are lost
+        assert_eq!(
+            receive(&mut receiver, &frames[3]),
+            vec![vec![1], vec![2], vec![3]]
+        );
+        // arriving late, after a newer one
+        assert!(receive(&mut receiver, &frames[2]).is_empty());
+        assert_eq!(receive(&mut receiver, &frames[4]), vec![vec![4]]);
+    }
+}
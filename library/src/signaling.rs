@@ -0,0 +1,876 @@
+/*!
+Channel to the signaling server, or to the other peer of the same page for [crate::ConnectionType::InPage].
+
+Unless [crate::ConnectionConfig::keep_signaling_open], the websocket to the server is released once
+the peers are connected, and opened again to renegotiate or restart ICE.
+
+With [crate::ConnectionConfig::share_websocket], the network managers of the page connecting to the
+same server share a single websocket, the messages of the server going to the manager of their session.
+
+In-page peers exchange the same [SignalMessage]s as through the server, relayed in memory
+by a registry of the sessions of the page.
+ */
+
+use crate::callbacks::{set_websocket_on_message, set_websocket_on_open, Closures};
+use crate::websocket_handler;
+use futures_channel::oneshot;
+use log::{debug, error, info, warn};
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::rc::Rc;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::spawn_local;
+use wasm_peers_protocol::one_to_one::SignalMessage;
+use wasm_peers_protocol::text::{clamp_text, MAX_ERROR_LEN};
+use wasm_peers_protocol::{SessionId, UserId};
+use web_sys::{RtcIceConnectionState, RtcPeerConnection, RtcSignalingState, WebSocket};
+
+#[derive(Debug, Clone)]
+pub(crate) enum Signaling {
+    Server(ServerLink),
+    Shared(SharedLink),
+    InPage(InPagePeer),
+}
+
+impl Signaling {
+    /// Send a message to the signaling server, or straight to the other peer of the page.
+    pub(crate) fn send(&self, message: SignalMessage, compression: bool) -> Result<(), JsValue> {
+        match self {
+            Signaling::Server(link) => link.send(message, compression),
+            Signaling::Shared(link) => link.send(message, compression),
+            Signaling::InPage(peer) => {
+                peer.send(message);
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether the websocket to the server was released, a shared one or the in-page channel never is.
+    pub(crate) fn is_released(&self) -> bool {
+        match self {
+            Signaling::Server(link) => link.is_released(),
+            Signaling::Shared(_) | Signaling::InPage(_) => false,
+        }
+    }
+
+    /// Release the websocket to the server if the peers are connected and done negotiating.
+    pub(crate) fn release_if_settled(&self, peer_connection: &RtcPeerConnection) {
+        if let Signaling::Server(link) = self {
+            link.release_if_settled(peer_connection);
+        }
+    }
+
+    /// Open the websocket to the server again and join the session, if it was released.
+    pub(crate) fn reopen(&self) -> Result<(), JsValue> {
+        match self {
+            Signaling::Server(link) => link.reopen(),
+            Signaling::Shared(_) | Signaling::InPage(_) => Ok(()),
+        }
+    }
+
+    pub(crate) fn close(&self) {
+        match self {
+            Signaling::Server(link) => link.close(),
+            Signaling::Shared(link) => link.close(),
+            Signaling::InPage(peer) => peer.leave(),
+        }
+    }
+}
+
+type Handler = Box<dyn FnMut(SignalMessage)>;
+
+/// Websocket to the signaling server, which can be released and opened again,
+/// joining the session each time it opens.
+#[derive(Clone)]
+pub(crate) struct ServerLink(Rc<RefCell<Link>>);
+
+struct Link {
+    url: String,
+    keep_open: bool,
+    websocket: Option<WebSocket>,
+    /// Session joined once connected, and handler of the messages of the server, set on start
+    session: Option<(SessionId, Rc<RefCell<Handler>>)>,
+    /// Id the server gave this peer in the session, sent back to get the same slot when reopening
+    user_id: Rc<Cell<Option<UserId>>>,
+    join_retry: JoinRetry,
+    /// Messages received before the session handler is set, on a websocket already in the session
+    early: Rc<RefCell<Vec<SignalMessage>>>,
+    /// Event handlers of the current websocket
+    closures: Closures,
+}
+
+impl ServerLink {
+    pub(crate) fn new(
+        url: String,
+        keep_open: bool,
+        user_id: Rc<Cell<Option<UserId>>>,
+        join_retry: JoinRetry,
+    ) -> Result<Self, JsValue> {
+        let websocket = open_websocket(&url)?;
+        Ok(Self::with_websocket(
+            url, websocket, keep_open, user_id, join_retry,
+        ))
+    }
+
+    /// Same as [ServerLink::new] on `websocket`, which may already be open, see [create_session].
+    pub(crate) fn with_websocket(
+        url: String,
+        websocket: WebSocket,
+        keep_open: bool,
+        user_id: Rc<Cell<Option<UserId>>>,
+        join_retry: JoinRetry,
+    ) -> Self {
+        let early = Rc::<RefCell<Vec<_>>>::default();
+        let mut closures = Closures::default();
+        let on_message = {
+            let early = early.clone();
+            move |message| early.borrow_mut().push(message)
+        };
+        set_websocket_on_message(&websocket, on_message, &mut closures);
+        ServerLink(Rc::new(RefCell::new(Link {
+            url,
+            keep_open,
+            websocket: Some(websocket),
+            session: None,
+            user_id,
+            join_retry,
+            early,
+            closures,
+        })))
+    }
+
+    /// Join `session_id` once connected, and hand the messages of the server to `on_message`.
+    pub(crate) fn connect(
+        &self,
+        session_id: SessionId,
+        on_message: impl FnMut(SignalMessage) + 'static,
+    ) {
+        let mut link = self.0.borrow_mut();
+        link.session = Some((session_id, Rc::new(RefCell::new(Box::new(on_message)))));
+        link.set_handlers();
+    }
+
+    fn send(&self, message: SignalMessage, compression: bool) -> Result<(), JsValue> {
+        match &self.0.borrow().websocket {
+            Some(websocket) => {
+                websocket_handler::send_signal_message(websocket, message, compression)
+            }
+            None => Err(JsValue::from_str(
+                "the connection to the signaling server was released",
+            )),
+        }
+    }
+
+    fn is_released(&self) -> bool {
+        self.0.borrow().websocket.is_none()
+    }
+
+    fn release_if_settled(&self, peer_connection: &RtcPeerConnection) {
+        let connected = matches!(
+            peer_connection.ice_connection_state(),
+            RtcIceConnectionState::Connected | RtcIceConnectionState::Completed
+        );
+        let settled = connected && peer_connection.signaling_state() == RtcSignalingState::Stable;
+        let mut link = self.0.borrow_mut();
+        if settled && !link.keep_open && link.release() {
+            info!("peers connected, released the connection to the signaling server");
+        }
+    }
+
+    fn reopen(&self) -> Result<(), JsValue> {
+        let mut link = self.0.borrow_mut();
+        if link.websocket.is_some() {
+            return Ok(());
+        }
+        info!("opening the connection to the signaling server again");
+        link.websocket = Some(open_websocket(&link.url)?);
+        link.set_handlers();
+        Ok(())
+    }
+
+    fn close(&self) {
+        let mut link = self.0.borrow_mut();
+        if let (Some(websocket), Some((session_id, _))) = (&link.websocket, &link.session) {
+            if websocket.ready_state() == WebSocket::OPEN {
+                let leave = SignalMessage::SessionLeave(*session_id);
+                if let Err(error) = websocket_handler::send_signal_message(websocket, leave, false)
+                {
+                    error!("failed to leave the session: {error:?}");
+                }
+            }
+        }
+        link.release();
+        // the handler holds onto the network manager
+        link.session = None;
+    }
+}
+
+impl Link {
+    fn set_handlers(&mut self) {
+        let (websocket, (session_id, handler)) = match (&self.websocket, &self.session) {
+            (Some(websocket), Some(session)) => (websocket, session),
+            _ => return,
+        };
+        let mut closures = Closures::default();
+        set_websocket_on_open(
+            websocket,
+            *session_id,
+            self.user_id.clone(),
+            self.join_retry.clone(),
+            &mut closures,
+        );
+        let on_message = handler.clone();
+        let join_retry = self.join_retry.clone();
+        set_websocket_on_message(
+            websocket,
+            move |message| {
+                if let SignalMessage::SessionReady(..) = message {
+                    join_retry.cancel();
+                }
+                (on_message.borrow_mut())(message)
+            },
+            &mut closures,
+        );
+        self.closures = closures;
+        if websocket.ready_state() == WebSocket::OPEN {
+            // the websocket which created the session, the server answers it is already in it
+            websocket_handler::join_session(
+                websocket,
+                *session_id,
+                self.user_id.get(),
+                &self.join_retry,
+            );
+            // the other peer may have joined meanwhile, handled once the link is not borrowed
+            let early = std::mem::take(&mut *self.early.borrow_mut());
+            let handler = handler.clone();
+            let join_retry = self.join_retry.clone();
+            spawn_local(async move {
+                for message in early {
+                    if let SignalMessage::SessionReady(..) = message {
+                        join_retry.cancel();
+                    }
+                    (handler.borrow_mut())(message);
+                }
+            });
+        }
+    }
+
+    /// Returns whether there was a websocket to close.
+    fn release(&mut self) -> bool {
+        match self.websocket.take() {
+            Some(websocket) => {
+                self.join_retry.cancel();
+                websocket.set_onopen(None);
+                websocket.set_onmessage(None);
+                let _ = websocket.close();
+                self.closures = Closures::default();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Time waited for the session to be ready before the first retry of the join.
+const JOIN_TIMEOUT_MS: i32 = 2_000;
+/// Longest time waited between two retries of the join, however many there are.
+const MAX_JOIN_TIMEOUT_MS: i32 = 60_000;
+
+/// Time waited before the retry following `retry`, doubling each time up to [MAX_JOIN_TIMEOUT_MS].
+fn join_timeout_ms(retry: u8) -> i32 {
+    JOIN_TIMEOUT_MS
+        .checked_mul(1 << retry.min(30))
+        .map_or(MAX_JOIN_TIMEOUT_MS, |timeout| {
+            timeout.min(MAX_JOIN_TIMEOUT_MS)
+        })
+}
+
+type TimeoutCallback = Box<dyn FnOnce()>;
+
+/// Sends the request to join the session again while the server doesn't answer it,
+/// waiting twice as long each time, and calls the timeout callback once the retries run out.
+#[derive(Clone, Default)]
+pub(crate) struct JoinRetry(Rc<JoinState>);
+
+#[derive(Default)]
+struct JoinState {
+    retries: u8,
+    /// Incremented on each join and once answered, so that the timers of older attempts do nothing
+    attempt: Cell<u32>,
+    on_timeout: RefCell<Option<TimeoutCallback>>,
+}
+
+impl JoinRetry {
+    pub(crate) fn new(retries: u8) -> Self {
+        JoinRetry(Rc::new(JoinState {
+            retries,
+            ..JoinState::default()
+        }))
+    }
+
+    pub(crate) fn set_on_timeout(&self, callback: impl FnOnce() + 'static) {
+        *self.0.on_timeout.borrow_mut() = Some(Box::new(callback));
+    }
+
+    /// Send `join` again on `websocket` until [JoinRetry::cancel] is called.
+    pub(crate) fn start(&self, websocket: WebSocket, join: SignalMessage) {
+        let attempt = self.0.attempt.get().wrapping_add(1);
+        self.0.attempt.set(attempt);
+        if self.0.retries > 0 {
+            self.schedule(websocket, join, attempt, 0);
+        }
+    }
+
+    /// The session is ready, or the websocket was released.
+    pub(crate) fn cancel(&self) {
+        self.0.attempt.set(self.0.attempt.get().wrapping_add(1));
+    }
+
+    fn schedule(&self, websocket: WebSocket, join: SignalMessage, attempt: u32, retry: u8) {
+        let join_retry = self.clone();
+        let on_timeout = Closure::once_into_js(move || {
+            if join_retry.0.attempt.get() != attempt {
+                return;
+            }
+            if retry == join_retry.0.retries {
+                warn!("the session was not ready after {retry} retries of the join");
+                join_retry.cancel();
+                let callback = join_retry.0.on_timeout.borrow_mut().take();
+                if let Some(callback) = callback {
+                    callback();
+                }
+                return;
+            }
+            debug!("the session is not ready yet, sending the join again");
+            if let Err(error) =
+                websocket_handler::send_signal_message(&websocket, join.clone(), false)
+            {
+                error!("failed to send the join again: {error:?}");
+            }
+            join_retry.schedule(websocket, join, attempt, retry + 1);
+        });
+        let window = web_sys::window().expect("there was no window global object!");
+        let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+            on_timeout.unchecked_ref(),
+            join_timeout_ms(retry),
+        );
+    }
+}
+
+impl Debug for JoinRetry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JoinRetry")
+            .field("retries", &self.0.retries)
+            .finish()
+    }
+}
+
+/// Websocket asking the signaling server for a session, see [create_session].
+/// Its handlers are removed once dropped, and it is closed unless it was taken.
+struct Creation {
+    websocket: WebSocket,
+    taken: bool,
+    _closures: Closures,
+}
+
+impl Creation {
+    fn take(mut self) -> WebSocket {
+        self.taken = true;
+        self.websocket.clone()
+    }
+}
+
+impl Drop for Creation {
+    fn drop(&mut self) {
+        self.websocket.set_onopen(None);
+        self.websocket.set_onmessage(None);
+        self.websocket.set_onclose(None);
+        if !self.taken {
+            let _ = self.websocket.close();
+        }
+    }
+}
+
+type Created = Result<SessionId, JsValue>;
+
+/// Open a websocket to the signaling server at `url` and ask it for a session under an id it picks,
+/// with a [SignalMessage::SessionCreate]. Resolves to the websocket, already in the session,
+/// and the id of the session.
+pub(crate) fn create_session(
+    url: &str,
+) -> Result<impl Future<Output = Result<(WebSocket, SessionId), JsValue>>, JsValue> {
+    let websocket = open_websocket(url)?;
+    let (sender, receiver) = oneshot::channel::<Created>();
+    let sender = Rc::new(RefCell::new(Some(sender)));
+    let settle = move |outcome: Created| {
+        if let Some(sender) = sender.borrow_mut().take() {
+            let _ = sender.send(outcome);
+        }
+    };
+
+    let mut closures = Closures::default();
+    let onopen = {
+        let websocket = websocket.clone();
+        let settle = settle.clone();
+        Closure::wrap(Box::new(move |_| {
+            let create = SignalMessage::SessionCreate;
+            if let Err(error) = websocket_handler::send_signal_message(&websocket, create, false) {
+                settle(Err(error));
+            }
+        }) as Box<dyn FnMut(JsValue)>)
+    };
+    websocket.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+    closures.keep(onopen);
+    let on_message = {
+        let settle = settle.clone();
+        move |message| match message {
+            SignalMessage::SessionCreated(session_id) => settle(Ok(session_id)),
+            SignalMessage::Error(_, error) => settle(Err(JsValue::from_str(&format!(
+                "the signaling server did not create a session: {}",
+                clamp_text(&error, MAX_ERROR_LEN)
+            )))),
+            message => debug!("ignoring {message:?} while creating a session"),
+        }
+    };
+    set_websocket_on_message(&websocket, on_message, &mut closures);
+    let onclose = Closure::wrap(Box::new(move |_| {
+        settle(Err(JsValue::from_str(
+            "the connection to the signaling server closed before the session was created",
+        )));
+    }) as Box<dyn FnMut(JsValue)>);
+    websocket.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+    closures.keep(onclose);
+
+    let creation = Creation {
+        websocket,
+        taken: false,
+        _closures: closures,
+    };
+    Ok(async move {
+        // the handlers live until the answer, the sender is only dropped unsent along with them
+        let session_id = receiver
+            .await
+            .unwrap_or_else(|_| Err(JsValue::from_str("the session creation was abandoned")))?;
+        Ok((creation.take(), session_id))
+    })
+}
+
+fn open_websocket(url: &str) -> Result<WebSocket, JsValue> {
+    let websocket = WebSocket::new(url)?;
+    websocket.set_binary_type(web_sys::BinaryType::Arraybuffer);
+    Ok(websocket)
+}
+
+impl Debug for ServerLink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let link = self.0.borrow();
+        f.debug_struct("ServerLink")
+            .field("url", &link.url)
+            .field("keep_open", &link.keep_open)
+            .field("released", &link.websocket.is_none())
+            .finish()
+    }
+}
+
+thread_local! {
+    /// Websockets shared by the network managers of the page, by url of the signaling server.
+    static SHARED_SOCKETS: RefCell<HashMap<String, SharedSocket>> = RefCell::new(HashMap::new());
+}
+
+/// Websocket to the signaling server through which several sessions are joined.
+/// Closed once the last of them is left, and no longer shared once it closes or fails.
+#[derive(Clone)]
+struct SharedSocket(Rc<RefCell<Socket>>);
+
+struct Socket {
+    url: String,
+    websocket: WebSocket,
+    /// Sessions joined through the websocket, set once their network manager starts
+    sessions: HashMap<SessionId, Option<SharedSession>>,
+    /// Capabilities of the server, handed to the sessions joining after it sent them
+    hello: Option<SignalMessage>,
+    /// Event handlers of the websocket
+    closures: Closures,
+}
+
+#[derive(Clone)]
+struct SharedSession {
+    /// Handler of the messages of the server about the session
+    handler: Rc<RefCell<Handler>>,
+    join_retry: JoinRetry,
+}
+
+impl SharedSocket {
+    /// The websocket to `url`, opened unless another network manager of the page did already.
+    fn get_or_open(url: &str) -> Result<Self, JsValue> {
+        if let Some(socket) = SHARED_SOCKETS.with(|sockets| sockets.borrow().get(url).cloned()) {
+            return Ok(socket);
+        }
+        let socket = SharedSocket(Rc::new(RefCell::new(Socket {
+            url: url.to_string(),
+            websocket: open_websocket(url)?,
+            sessions: HashMap::new(),
+            hello: None,
+            closures: Closures::default(),
+        })));
+        socket.set_handlers();
+        SHARED_SOCKETS.with(|sockets| sockets.borrow_mut().insert(url.to_string(), socket.clone()));
+        Ok(socket)
+    }
+
+    fn set_handlers(&self) {
+        let mut closures = Closures::default();
+        let websocket = self.0.borrow().websocket.clone();
+        // the socket owns the closures, which must not keep it alive
+        let socket = Rc::downgrade(&self.0);
+        let on_open = Closure::wrap(Box::new(move |_| {
+            let socket = match socket.upgrade() {
+                Some(socket) => SharedSocket(socket),
+                None => return,
+            };
+            let websocket = socket.0.borrow().websocket.clone();
+            if let Err(error) = websocket_handler::send_hello(&websocket) {
+                error!("failed to greet the signaling server: {error:?}");
+            }
+            let started: Vec<_> = socket
+                .0
+                .borrow()
+                .sessions
+                .iter()
+                .filter_map(|(session_id, session)| Some((*session_id, session.clone()?)))
+                .collect();
+            for (session_id, session) in started {
+                socket.join(session_id, &session.join_retry);
+            }
+        }) as Box<dyn FnMut(JsValue)>);
+        websocket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        closures.keep(on_open);
+
+        let socket = Rc::downgrade(&self.0);
+        set_websocket_on_message(
+            &websocket,
+            move |message| {
+                if let Some(socket) = socket.upgrade() {
+                    SharedSocket(socket).dispatch(message);
+                }
+            },
+            &mut closures,
+        );
+
+        let socket = Rc::downgrade(&self.0);
+        let on_close = Closure::wrap(Box::new(move |_| {
+            if let Some(socket) = socket.upgrade() {
+                let socket = SharedSocket(socket);
+                warn!(
+                    "the shared websocket to {} closed, the sessions joined through it are not signaled anymore",
+                    socket.0.borrow().url
+                );
+                // the next network managers open another one
+                socket.unregister();
+            }
+        }) as Box<dyn FnMut(JsValue)>);
+        // a failing websocket is closed right after
+        websocket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        websocket.set_onerror(Some(on_close.as_ref().unchecked_ref()));
+        closures.keep(on_close);
+        self.0.borrow_mut().closures = closures;
+    }
+
+    /// Stop sharing the websocket with the network managers created from now on.
+    fn unregister(&self) {
+        let url = self.0.borrow().url.clone();
+        SHARED_SOCKETS.with(|sockets| {
+            let mut sockets = sockets.borrow_mut();
+            if matches!(sockets.get(&url), Some(socket) if Rc::ptr_eq(&socket.0, &self.0)) {
+                sockets.remove(&url);
+            }
+        });
+    }
+
+    /// Send the request to join `session_id`, again while the server doesn't answer it.
+    fn join(&self, session_id: SessionId, join_retry: &JoinRetry) {
+        let websocket = self.0.borrow().websocket.clone();
+        let join = SignalMessage::SessionJoin(session_id);
+        if let Err(error) = websocket_handler::send_signal_message(&websocket, join.clone(), false)
+        {
+            error!("failed to join the session {session_id:?}: {error:?}");
+        }
+        join_retry.start(websocket, join);
+    }
+
+    /// Hand `message` to the handler of its session, or to all of them when it is about the
+    /// websocket itself, like the capabilities of the server.
+    fn dispatch(&self, message: SignalMessage) {
+        if let SignalMessage::Hello(_) = message {
+            self.0.borrow_mut().hello = Some(message.clone());
+        }
+        let handlers: Vec<_> = {
+            let socket = self.0.borrow();
+            match message.session_id() {
+                Some(session_id) => match socket.sessions.get(&session_id) {
+                    Some(Some(session)) => vec![session.handler.clone()],
+                    _ => {
+                        debug!("ignoring a message for session {session_id:?}, not joined here");
+                        return;
+                    }
+                },
+                None => socket
+                    .sessions
+                    .values()
+                    .flatten()
+                    .map(|session| session.handler.clone())
+                    .collect(),
+            }
+        };
+        for handler in handlers {
+            (handler.borrow_mut())(message.clone());
+        }
+    }
+
+    /// Forget `session_id`, closing the websocket if it was the last session joined through it.
+    fn remove(&self, session_id: SessionId) {
+        {
+            let mut socket = self.0.borrow_mut();
+            socket.sessions.remove(&session_id);
+            if !socket.sessions.is_empty() {
+                return;
+            }
+            socket.websocket.set_onopen(None);
+            socket.websocket.set_onmessage(None);
+            socket.websocket.set_onclose(None);
+            socket.websocket.set_onerror(None);
+            let _ = socket.websocket.close();
+            socket.closures = Closures::default();
+        }
+        self.unregister();
+    }
+}
+
+/// Session joined through a websocket shared with other network managers of the page.
+#[derive(Clone)]
+pub(crate) struct SharedLink {
+    socket: SharedSocket,
+    session_id: SessionId,
+    join_retry: JoinRetry,
+}
+
+impl SharedLink {
+    /// Fails if another network manager of the page joins `session_id` through the same websocket,
+    /// as the server would take them for the same peer.
+    pub(crate) fn new(
+        url: &str,
+        session_id: SessionId,
+        join_retry: JoinRetry,
+    ) -> Result<Self, JsValue> {
+        let socket = SharedSocket::get_or_open(url)?;
+        match socket.0.borrow_mut().sessions.entry(session_id) {
+            Entry::Occupied(_) => {
+                return Err(JsValue::from_str(
+                    "the session is already joined through the shared websocket",
+                ))
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(None);
+            }
+        }
+        Ok(SharedLink {
+            socket,
+            session_id,
+            join_retry,
+        })
+    }
+
+    /// Join the session, once connected, and hand the messages of the server about it to `on_message`.
+    pub(crate) fn connect(&self, mut on_message: impl FnMut(SignalMessage) + 'static) {
+        let join_retry = self.join_retry.clone();
+        let handler: Handler = Box::new(move |message| {
+            if let SignalMessage::SessionReady(..) = message {
+                join_retry.cancel();
+            }
+            on_message(message)
+        });
+        let session = SharedSession {
+            handler: Rc::new(RefCell::new(handler)),
+            join_retry: self.join_retry.clone(),
+        };
+        let handler = session.handler.clone();
+        let (open, hello) = {
+            let mut socket = self.socket.0.borrow_mut();
+            socket.sessions.insert(self.session_id, Some(session));
+            let open = socket.websocket.ready_state() == WebSocket::OPEN;
+            (open, socket.hello.clone())
+        };
+        // the server greeted the websocket before this session joined it
+        if let Some(hello) = hello {
+            (handler.borrow_mut())(hello);
+        }
+        // otherwise joined along with the other sessions once it opens
+        if open {
+            self.socket.join(self.session_id, &self.join_retry);
+        }
+    }
+
+    fn send(&self, message: SignalMessage, compression: bool) -> Result<(), JsValue> {
+        let websocket = self.socket.0.borrow().websocket.clone();
+        websocket_handler::send_signal_message(&websocket, message, compression)
+    }
+
+    fn close(&self) {
+        self.join_retry.cancel();
+        let websocket = self.socket.0.borrow().websocket.clone();
+        if websocket.ready_state() == WebSocket::OPEN {
+            let leave = SignalMessage::SessionLeave(self.session_id);
+            if let Err(error) = websocket_handler::send_signal_message(&websocket, leave, false) {
+                error!("failed to leave the session: {error:?}");
+            }
+        }
+        // the handler holds onto the network manager
+        self.socket.remove(self.session_id);
+    }
+}
+
+impl Debug for SharedLink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedLink")
+            .field("url", &self.socket.0.borrow().url)
+            .field("session_id", &self.session_id)
+            .finish()
+    }
+}
+
+type SessionKey = (String, SessionId);
+
+thread_local! {
+    /// Peers of each in-page session, the one which joined first coming first.
+    static SESSIONS: RefCell<HashMap<SessionKey, Vec<InPagePeer>>> = RefCell::new(HashMap::new());
+}
+
+/// Peer of an in-page session, playing the part of both its websocket and the signaling server.
+#[derive(Clone)]
+pub(crate) struct InPagePeer {
+    key: SessionKey,
+    on_message: Rc<RefCell<Option<Handler>>>,
+}
+
+impl InPagePeer {
+    pub(crate) fn new(namespace: String, session_id: SessionId) -> Self {
+        InPagePeer {
+            key: (namespace, session_id),
+            on_message: Rc::default(),
+        }
+    }
+
+    pub(crate) fn set_on_message(&self, on_message: impl FnMut(SignalMessage) + 'static) {
+        *self.on_message.borrow_mut() = Some(Box::new(on_message));
+    }
+
+    /// Join the session, the first peer is told to start the connection once the second one joins.
+    /// Returns whether the other peer was already there, which then owns the session.
+    pub(crate) fn join(&self) -> bool {
+        let first = SESSIONS.with(|sessions| {
+            let mut sessions = sessions.borrow_mut();
+            let peers = sessions.entry(self.key.clone()).or_default();
+            if peers.iter().any(|peer| peer.is(self)) {
+                return None;
+            }
+            peers.push(self.clone());
+            match peers.as_slice() {
+                [first, _] => Some(first.clone()),
+                _ => None,
+            }
+        });
+        match first {
+            Some(first) => {
+                first.deliver(SignalMessage::SessionReady(
+                    self.key.1,
+                    true,
+                    UserId::new(1),
+                ));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn send(&self, message: SignalMessage) {
+        match message {
+            SignalMessage::SessionJoin(_) | SignalMessage::SessionReconnect(..) => {
+                self.join();
+            }
+            message @ (SignalMessage::SdpOffer(..)
+            | SignalMessage::SdpAnswer(..)
+            | SignalMessage::IceCandidate(..)
+            | SignalMessage::Ping(..)
+            | SignalMessage::Pong(..)
+            | SignalMessage::Custom(..)) => match self.other_peer() {
+                Some(peer) => peer.deliver(message),
+                None => error!("no other peer in the page for session {:?}", self.key.1),
+            },
+            message => debug!("not relaying {message:?} to the other peer of the page"),
+        }
+    }
+
+    /// Hand `message` over to the handler later, as if it came from the network.
+    fn deliver(&self, message: SignalMessage) {
+        let on_message = self.on_message.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Some(on_message) = on_message.borrow_mut().as_mut() {
+                on_message(message);
+            }
+        });
+    }
+
+    fn other_peer(&self) -> Option<InPagePeer> {
+        SESSIONS.with(|sessions| {
+            sessions
+                .borrow()
+                .get(&self.key)?
+                .iter()
+                .find(|peer| !peer.is(self))
+                .cloned()
+        })
+    }
+
+    fn is(&self, other: &InPagePeer) -> bool {
+        Rc::ptr_eq(&self.on_message, &other.on_message)
+    }
+
+    /// Leave the session, and drop the handler which holds onto the network manager.
+    pub(crate) fn leave(&self) {
+        SESSIONS.with(|sessions| {
+            let mut sessions = sessions.borrow_mut();
+            if let Some(peers) = sessions.get_mut(&self.key) {
+                peers.retain(|peer| !peer.is(self));
+                if peers.is_empty() {
+                    sessions.remove(&self.key);
+                }
+            }
+        });
+        self.on_message.borrow_mut().take();
+    }
+}
+
+impl Debug for InPagePeer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InPagePeer")
+            .field("session_id", &self.key.1)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn join_timeout_doubles_up_to_its_maximum() {
+        assert_eq!(join_timeout_ms(0), JOIN_TIMEOUT_MS);
+        assert_eq!(join_timeout_ms(1), 2 * JOIN_TIMEOUT_MS);
+        assert_eq!(join_timeout_ms(5), MAX_JOIN_TIMEOUT_MS);
+        assert_eq!(join_timeout_ms(u8::MAX), MAX_JOIN_TIMEOUT_MS);
+    }
+}
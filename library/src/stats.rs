@@ -0,0 +1,186 @@
+/*!
+Candidate pair the ICE agent selected for the connection, and its round trip time,
+read from the WebRTC statistics.
+ */
+
+use js_sys::{Map, Reflect};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::RtcPeerConnection;
+
+/// One end of the [CandidatePair] the connection goes through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// `host` on the local network, `srflx` or `prflx` through a NAT, `relay` through a TURN server
+    pub candidate_type: String,
+    /// IP address or hostname, hidden by some browsers for host candidates
+    pub address: Option<String>,
+    /// Port number, along with the address
+    pub port: Option<u16>,
+    /// `udp` or `tcp`
+    pub protocol: Option<String>,
+}
+
+/// Local and remote candidates of the connection, as selected by the ICE agent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidatePair {
+    /// Candidate of this peer
+    pub local: Candidate,
+    /// Candidate of the other peer
+    pub remote: Candidate,
+}
+
+impl CandidatePair {
+    /// Whether the traffic goes through a TURN server, which adds latency.
+    pub fn is_relayed(&self) -> bool {
+        self.local.candidate_type == "relay" || self.remote.candidate_type == "relay"
+    }
+}
+
+fn get(object: &JsValue, key: &str) -> Option<JsValue> {
+    Reflect::get(object, &key.into())
+        .ok()
+        .filter(|value| !value.is_undefined() && !value.is_null())
+}
+
+fn get_string(object: &JsValue, key: &str) -> Option<String> {
+    get(object, key)?.as_string()
+}
+
+impl Candidate {
+    fn from_stats(stats: &JsValue) -> Option<Self> {
+        Some(Candidate {
+            candidate_type: get_string(stats, "candidateType")?,
+            // `ip` is the older name of `address`
+            address: get_string(stats, "address").or_else(|| get_string(stats, "ip")),
+            port: get(stats, "port")
+                .and_then(|port| port.as_f64())
+                .map(|port| port as u16),
+            protocol: get_string(stats, "protocol"),
+        })
+    }
+}
+
+/// Stats of the selected pair in a stats report, given as a map from stats ids to stats objects.
+fn selected_pair_stats(report: &Map) -> Option<JsValue> {
+    let by_type = |kind: &str| {
+        let mut found = Vec::new();
+        report.for_each(&mut |stats, _id| {
+            if get_string(&stats, "type").as_deref() == Some(kind) {
+                found.push(stats);
+            }
+        });
+        found
+    };
+    // standard way, through the transport
+    by_type("transport")
+        .iter()
+        .filter_map(|transport| get(transport, "selectedCandidatePairId"))
+        .map(|id| report.get(&id))
+        .find(|pair| !pair.is_undefined())
+        // Firefox flags the pair itself instead
+        .or_else(|| {
+            by_type("candidate-pair").into_iter().find(|pair| {
+                get(pair, "selected").and_then(|s| s.as_bool()) == Some(true)
+                    || (get_string(pair, "state").as_deref() == Some("succeeded")
+                        && get(pair, "nominated").and_then(|n| n.as_bool()) == Some(true))
+            })
+        })
+}
+
+/// Finds the selected pair in a stats report, given as a map from stats ids to stats objects.
+pub(crate) fn selected_pair(report: &Map) -> Option<CandidatePair> {
+    let pair = selected_pair_stats(report)?;
+    let candidate = |key: &str| {
+        let id = get(&pair, key)?;
+        Candidate::from_stats(&report.get(&id))
+    };
+    Some(CandidatePair {
+        local: candidate("localCandidateId")?,
+        remote: candidate("remoteCandidateId")?,
+    })
+}
+
+/// Latest round trip time of the selected pair in milliseconds, measured by the ICE agent.
+pub(crate) fn selected_pair_round_trip_time(report: &Map) -> Option<f64> {
+    let seconds = get(&selected_pair_stats(report)?, "currentRoundTripTime")?.as_f64()?;
+    Some(seconds * 1000.0)
+}
+
+pub(crate) async fn round_trip_time(
+    peer_connection: &RtcPeerConnection,
+) -> Result<Option<f64>, JsValue> {
+    let report = JsFuture::from(peer_connection.get_stats()).await?;
+    Ok(selected_pair_round_trip_time(report.unchecked_ref::<Map>()))
+}
+
+pub(crate) async fn selected_candidate_pair(
+    peer_connection: &RtcPeerConnection,
+) -> Result<Option<CandidatePair>, JsValue> {
+    let report = JsFuture::from(peer_connection.get_stats()).await?;
+    // RTCStatsReport is a read-only map, with the same methods
+    Ok(selected_pair(report.unchecked_ref::<Map>()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use js_sys::Object;
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn stats(entries: &[(&str, JsValue)]) -> JsValue {
+        let object = Object::new();
+        for (key, value) in entries {
+            Reflect::set(&object, &(*key).into(), value).unwrap();
+        }
+        object.into()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_selected_pair_is_found_through_the_transport() {
+        let report = Map::new();
+        let insert = |id: &str, entries: &[(&str, JsValue)]| {
+            let mut entries = entries.to_vec();
+            entries.push(("id", id.into()));
+            report.set(&id.into(), &stats(&entries));
+        };
+        insert(
+            "T1",
+            &[
+                ("type", "transport".into()),
+                ("selectedCandidatePairId", "CP2".into()),
+            ],
+        );
+        for (pair, local, rtt) in [("CP1", "L1", 0.3), ("CP2", "L2", 0.042)] {
+            insert(
+                pair,
+                &[
+                    ("type", "candidate-pair".into()),
+                    ("localCandidateId", local.into()),
+                    ("remoteCandidateId", "R1".into()),
+                    ("currentRoundTripTime", rtt.into()),
+                ],
+            );
+        }
+        insert("L1", &[("candidateType", "host".into())]);
+        insert(
+            "L2",
+            &[
+                ("candidateType", "relay".into()),
+                ("address", "203.0.113.7".into()),
+                ("port", 3478.into()),
+                ("protocol", "udp".into()),
+            ],
+        );
+        insert("R1", &[("candidateType", "srflx".into())]);
+
+        let pair = selected_pair(&report).expect("no selected pair");
+        assert_eq!(pair.local.address.as_deref(), Some("203.0.113.7"));
+        assert_eq!(pair.local.port, Some(3478));
+        assert_eq!(pair.remote.candidate_type, "srflx");
+        assert!(pair.is_relayed());
+        assert_eq!(selected_pair_round_trip_time(&report), Some(42.0));
+    }
+}
@@ -0,0 +1,225 @@
+/*!
+Channel carrying the messages of a network manager once the connection is established.
+
+In the browser this is the WebRTC data channel itself. Tests use an in-memory loopback instead,
+so that encoding, acknowledgements and decoding of messages can be exercised without a browser.
+ */
+
+use crate::callbacks::Closures;
+use js_sys::{ArrayBuffer, JsString, Uint8Array};
+use log::error;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{MessageEvent, RtcDataChannel, RtcDataChannelType};
+
+/// Single message as sent on the channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Payload {
+    Binary(Vec<u8>),
+    Text(String),
+}
+
+pub(crate) trait Transport {
+    fn send(&self, payload: Payload) -> Result<(), JsValue>;
+
+    /// Same as sending a [Payload::Binary], without giving up the buffer holding `message`.
+    fn send_binary(&self, message: &[u8]) -> Result<(), JsValue>;
+
+    /// Bytes sent but not yet passed on to the network.
+    fn buffered_amount(&self) -> u32;
+
+    /// Replaces the handler of messages received from the other end.
+    fn set_on_message(&self, on_message: Box<dyn FnMut(Payload)>, closures: &mut Closures);
+}
+
+impl Transport for RtcDataChannel {
+    fn send(&self, payload: Payload) -> Result<(), JsValue> {
+        match payload {
+            Payload::Binary(message) => self.send_binary(&message),
+            Payload::Text(message) => self.send_with_str(&message),
+        }
+    }
+
+    fn send_binary(&self, message: &[u8]) -> Result<(), JsValue> {
+        self.send_with_u8_array(message)
+    }
+
+    fn buffered_amount(&self) -> u32 {
+        RtcDataChannel::buffered_amount(self)
+    }
+
+    fn set_on_message(&self, mut on_message: Box<dyn FnMut(Payload)>, closures: &mut Closures) {
+        self.set_binary_type(RtcDataChannelType::Arraybuffer);
+        let datachannel_on_message = Closure::wrap(Box::new(move |ev: MessageEvent| {
+            let data = ev.data();
+            if let Some(text) = data.dyn_ref::<JsString>() {
+                on_message(Payload::Text(String::from(text)));
+                return;
+            }
+            match data.dyn_into::<ArrayBuffer>() {
+                Ok(message) => on_message(Payload::Binary(Uint8Array::new(&message).to_vec())),
+                Err(data) => {
+                    error!(
+                        "received a data channel message that is neither text nor binary: {data:?}"
+                    );
+                }
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+        self.set_onmessage(Some(datachannel_on_message.as_ref().unchecked_ref()));
+        closures.keep(datachannel_on_message);
+    }
+}
+
+#[cfg(test)]
+pub(crate) use loopback::Loopback;
+
+#[cfg(test)]
+mod loopback {
+    use super::{Payload, Transport};
+    use crate::callbacks::Closures;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use wasm_bindgen::JsValue;
+
+    type Handler = Box<dyn FnMut(Payload)>;
+
+    /// One end of an in-memory channel, created in pairs with [Loopback::pair].
+    ///
+    /// Sent messages wait in the inbox of the other end until it calls [Loopback::deliver],
+    /// so that handlers answering a message don't run within each other.
+    #[derive(Clone, Default)]
+    pub(crate) struct Loopback {
+        inbox: Rc<RefCell<VecDeque<Payload>>>,
+        outbox: Rc<RefCell<VecDeque<Payload>>>,
+        on_message: Rc<RefCell<Option<Handler>>>,
+    }
+
+    impl Loopback {
+        pub(crate) fn pair() -> (Loopback, Loopback) {
+            let first = Loopback::default();
+            let second = Loopback {
+                inbox: first.outbox.clone(),
+                outbox: first.inbox.clone(),
+                on_message: Rc::default(),
+            };
+            (first, second)
+        }
+
+        /// Runs the message handler on every message received so far, returns how many there were.
+        pub(crate) fn deliver(&self) -> usize {
+            let mut delivered = 0;
+            loop {
+                let payload = self.inbox.borrow_mut().pop_front();
+                let payload = match payload {
+                    Some(payload) => payload,
+                    None => return delivered,
+                };
+                if let Some(on_message) = self.on_message.borrow_mut().as_mut() {
+                    on_message(payload);
+                }
+                delivered += 1;
+            }
+        }
+    }
+
+    impl Transport for Loopback {
+        fn send(&self, payload: Payload) -> Result<(), JsValue> {
+            self.outbox.borrow_mut().push_back(payload);
+            Ok(())
+        }
+
+        fn send_binary(&self, message: &[u8]) -> Result<(), JsValue> {
+            self.send(Payload::Binary(message.to_vec()))
+        }
+
+        /// Bytes of the messages the other end did not receive yet.
+        fn buffered_amount(&self) -> u32 {
+            let length = |payload: &Payload| match payload {
+                Payload::Binary(message) => message.len(),
+                Payload::Text(message) => message.len(),
+            };
+            self.outbox.borrow().iter().map(length).sum::<usize>() as u32
+        }
+
+        fn set_on_message(&self, on_message: Handler, _closures: &mut Closures) {
+            *self.on_message.borrow_mut() = Some(on_message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::acknowledgement::{self, Acknowledgements};
+    use crate::callbacks::message_handler;
+    use crate::encryption::Encryption;
+    use crate::one_to_one::encode_message;
+    use crate::signaling::{InPagePeer, Signaling};
+    use crate::ChannelMode;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use wasm_peers_protocol::SessionId;
+
+    fn connect<T: serde::de::DeserializeOwned + 'static>(
+        transport: &Loopback,
+    ) -> Rc<RefCell<Vec<T>>> {
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let on_message = {
+            let received = received.clone();
+            move |message| received.borrow_mut().push(message)
+        };
+        let handler = message_handler(
+            transport.clone(),
+            Rc::default(),
+            Encryption::default(),
+            Signaling::InPage(InPagePeer::new(String::new(), SessionId::new(0))),
+            on_message,
+        );
+        transport.set_on_message(handler, &mut Closures::default());
+        received
+    }
+
+    #[test]
+    fn single_message_passes_both_ways() {
+        let (server, client) = Loopback::pair();
+        let server_received = connect::<String>(&server);
+        let client_received = connect::<String>(&client);
+
+        server
+            .send(encode_message(&"ping!", ChannelMode::Binary))
+            .unwrap();
+        client.deliver();
+        client
+            .send(encode_message(&"pong!", ChannelMode::Text))
+            .unwrap();
+        server.deliver();
+
+        assert_eq!(*client_received.borrow(), vec!["ping!".to_string()]);
+        assert_eq!(*server_received.borrow(), vec!["pong!".to_string()]);
+    }
+
+    #[test]
+    fn retried_reliable_message_is_received_once_and_acknowledged() {
+        let (sender, receiver) = Loopback::pair();
+        let received = connect::<u32>(&receiver);
+
+        let mut acknowledgements = Acknowledgements::default();
+        let sequence = acknowledgements.next_sequence();
+        let frame = acknowledgement::reliable_frame(sequence, &rmp_serde::to_vec(&7u32).unwrap());
+        sender.send(Payload::Binary(frame.clone())).unwrap();
+        sender.send(Payload::Binary(frame)).unwrap();
+        assert_eq!(receiver.deliver(), 2);
+
+        assert_eq!(*received.borrow(), vec![7]);
+        let acks = Rc::new(RefCell::new(Vec::new()));
+        let on_ack = {
+            let acks = acks.clone();
+            Box::new(move |payload| acks.borrow_mut().push(payload))
+        };
+        sender.set_on_message(on_ack, &mut Closures::default());
+        sender.deliver();
+        let ack = Payload::Binary(acknowledgement::ack_frame(sequence));
+        assert_eq!(*acks.borrow(), vec![ack.clone(), ack]);
+    }
+}
@@ -1,8 +1,13 @@
+use crate::callbacks::SdpTransform;
+use crate::error::WebRtcError;
 use js_sys::{Array, Object, Reflect};
 use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::JsFuture;
-use wasm_peers_protocol::{STUN_PORT, TURN_PORT};
-use web_sys::{RtcConfiguration, RtcPeerConnection};
+use wasm_peers_protocol::{SessionId, STUN_PORT, TURN_PORT, WS_PORT};
+use web_sys::RtcIceConnectionState;
+use web_sys::{
+    RtcConfiguration, RtcDataChannelInit, RtcOfferOptions, RtcPeerConnection, RtcSignalingState,
+};
 use web_sys::{RtcSdpType, RtcSessionDescriptionInit};
 
 /// Specifies what kind of peer connection to create
@@ -14,18 +19,247 @@ pub enum ConnectionType {
     Stun,
     /// Setup with STUN and TURN hosts and fallback to TURN if needed, most stable connection
     StunAndTurn {
+        /// Addresses of the STUN servers, the one of the signaling server host when empty
+        stun_urls: Vec<String>,
+        /// Addresses of the TURN servers, the one of the signaling server host when empty
+        turn_urls: Vec<String>,
+        /// Required by the TURN servers
         username: String,
+        /// Required by the TURN servers
         credential: String,
     },
+    /// Setup with external STUN and TURN servers, when the signaling server runs without its own relay
+    External(Vec<IceServer>),
+    /// Both peers live in the same page, and exchange the signaling messages in memory
+    /// without any signaling server, the hostname given to the network manager being ignored.
+    /// Only for tests and demos, peers in different pages, tabs or origins never find each other.
+    InPage,
 }
+
+/// STUN or TURN server used by [ConnectionType::External].
+#[derive(Debug, Clone)]
+pub struct IceServer {
+    /// Addresses of the server, for example `stun:stun.example.com:3478`
+    pub urls: Vec<String>,
+    /// Required by TURN servers
+    pub username: Option<String>,
+    /// Required by TURN servers
+    pub credential: Option<String>,
+}
+
+impl IceServer {
+    fn to_js(&self) -> Result<Object, JsValue> {
+        let server_entry = Object::new();
+        let urls: Array = self.urls.iter().map(JsValue::from).collect();
+        Reflect::set(&server_entry, &"urls".into(), &urls)?;
+        if let Some(username) = &self.username {
+            Reflect::set(&server_entry, &"username".into(), &username.into())?;
+        }
+        if let Some(credential) = &self.credential {
+            Reflect::set(&server_entry, &"credential".into(), &credential.into())?;
+        }
+        Ok(server_entry)
+    }
+}
+/// Additional options of the connection, the defaults fit a signaling server
+/// reachable at the root of its host.
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    /// Path under which the signaling server is mounted, when it sits behind
+    /// a reverse proxy, for example `/game/signaling`.
+    pub signaling_path: String,
+    /// Name of the application, isolating its sessions from the ones of other applications
+    /// sharing the same signaling server. Sessions join the default namespace when empty.
+    pub namespace: String,
+    /// Number of ICE candidates gathered ahead of the SDP offer, which shortens the connection setup.
+    /// Left to the browser when `None`.
+    pub ice_candidate_pool_size: Option<u8>,
+    /// Key encrypting the messages end-to-end, which must be the same for both peers.
+    /// Messages are sent as MessagePack whatever the [ChannelMode], and [crate::one_to_one::NetworkManager::send_text]
+    /// can't be used.
+    #[cfg(feature = "encryption")]
+    pub encryption_key: Option<crate::EncryptionKey>,
+    /// Number of ICE restarts attempted in a row when the connection fails, for example
+    /// after a player switched from wifi to cellular. Only the session owner restarts,
+    /// so that both peers don't send an offer at once. Disabled with 0, the default.
+    pub ice_restarts: u8,
+    /// Whether to stay connected to the signaling server for the whole session, the default.
+    /// Otherwise the connection is released once the peers are connected, freeing a slot of the server,
+    /// and opened again to renegotiate or restart ICE, which then takes longer.
+    /// With a server assigning session ids, the session is kept for an hour once both peers
+    /// released their connection, after which they can't reconnect into it.
+    pub keep_signaling_open: bool,
+    /// Number of times the request to join the session is sent again while the signaling server
+    /// doesn't answer that the session is ready, waiting twice as long before each attempt, up to a minute.
+    /// The answer also waits for the other peer, so this bounds the wait for both.
+    /// Once they run out, [crate::one_to_one::NetworkManager::on_join_timeout] is called.
+    /// Sent only once with 0, the default.
+    pub join_retries: u8,
+    /// Whether to join the session through a websocket shared with the other network managers
+    /// of the page connecting to the same signaling server with this option, one per session,
+    /// rather than opening one each, to observe many sessions at once for example.
+    /// A shared websocket stays open, whatever [ConnectionConfig::keep_signaling_open],
+    /// until all of its sessions are closed. Once it closes or fails, the network managers
+    /// created afterwards open another one. Disabled by default.
+    pub share_websocket: bool,
+    /// Characters kept of the text sent by [crate::one_to_one::NetworkManager::send_text],
+    /// the rest being cut. Unlimited when `None`, the default.
+    pub max_text_len: Option<usize>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            signaling_path: String::new(),
+            namespace: String::new(),
+            ice_candidate_pool_size: None,
+            #[cfg(feature = "encryption")]
+            encryption_key: None,
+            ice_restarts: 0,
+            keep_signaling_open: true,
+            join_retries: 0,
+            share_websocket: false,
+            max_text_len: None,
+        }
+    }
+}
+
+impl ConnectionConfig {
+    pub(crate) fn signaling_url(&self, hostname: &str, endpoint: &str) -> String {
+        let path = self.signaling_path.trim_matches('/');
+        let url = if path.is_empty() {
+            format!("ws://{hostname}:{WS_PORT}/{endpoint}")
+        } else {
+            format!("ws://{hostname}:{WS_PORT}/{path}/{endpoint}")
+        };
+        if self.namespace.is_empty() {
+            url
+        } else {
+            format!("{url}/{}", self.namespace)
+        }
+    }
+
+    fn configure(&self, rtc_configuration: &RtcConfiguration) -> Result<(), JsValue> {
+        if let Some(pool_size) = self.ice_candidate_pool_size {
+            Reflect::set(
+                rtc_configuration,
+                &"iceCandidatePoolSize".into(),
+                &pool_size.into(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// How messages are encoded on the data channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelMode {
+    /// MessagePack in binary frames.
+    #[default]
+    Binary,
+    /// JSON in text frames, for peers that do not speak MessagePack, like plain JS clients.
+    Text,
+}
+
+/// Delivery guarantees of a data channel.
+///
+/// Only [Reliability::FullyReliable] delivers messages in the order they were sent,
+/// with the other presets a message can arrive after one sent later.
+/// The browser only allows limiting either the retransmits or the lifetime of a message,
+/// hence a single preset per channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reliability {
+    /// Every message is sent once, and may be lost.
+    /// Fits state sent every tick, where a newer message replaces a lost one.
+    Unreliable,
+    /// A lost message is sent again at most `max_retransmits` times.
+    SemiReliable {
+        /// Retransmits before giving up on a message
+        max_retransmits: u16,
+    },
+    /// A lost message is sent again until `max_packet_life_time_ms` elapsed since it was first sent.
+    TimeLimited {
+        /// Time in milliseconds after which a message is given up on
+        max_packet_life_time_ms: u16,
+    },
+    /// Every message arrives, in order, like over TCP.
+    /// Fits events which must not be missed, at the cost of a lost message delaying the next ones.
+    FullyReliable,
+}
+
+impl Default for Reliability {
+    fn default() -> Self {
+        Reliability::SemiReliable {
+            max_retransmits: 10,
+        }
+    }
+}
+
+impl Reliability {
+    pub(crate) fn configure(&self, init: &mut RtcDataChannelInit) {
+        init.set_ordered(*self == Reliability::FullyReliable);
+        match *self {
+            Reliability::Unreliable => {
+                init.set_max_retransmits(0);
+            }
+            Reliability::SemiReliable { max_retransmits } => {
+                init.set_max_retransmits(max_retransmits);
+            }
+            Reliability::TimeLimited {
+                max_packet_life_time_ms,
+            } => {
+                init.set_max_packet_life_time(max_packet_life_time_ms);
+            }
+            Reliability::FullyReliable => {}
+        }
+    }
+}
+
+/// Options of the data channel opened by [crate::one_to_one::NetworkManager::start].
+#[derive(Debug, Clone, Default)]
+pub struct ChannelConfig {
+    /// Whether lost messages are sent again, and whether messages arrive in order.
+    pub reliability: Reliability,
+    /// Encoding of the messages sent with [crate::one_to_one::NetworkManager::send_message].
+    pub mode: ChannelMode,
+    /// Label of the data channel, the session id if `None`.
+    /// Browsers allow up to 65535 bytes, but some WebRTC stacks misbehave well below that,
+    /// so labels longer than 1024 bytes are refused.
+    pub label: Option<String>,
+    /// Messages kept for [crate::one_to_one::NetworkManager::drain_received] at most,
+    /// the oldest being dropped past it. Unbounded if `None`.
+    /// Only used by [crate::one_to_one::NetworkManager::start_polling].
+    pub max_received: Option<usize>,
+}
+
+/// Longest label accepted for a data channel, in bytes of UTF-8.
+const MAX_LABEL_LENGTH: usize = 1024;
+
+impl ChannelConfig {
+    pub(crate) fn label(&self, session_id: SessionId) -> Result<String, JsValue> {
+        let label = match &self.label {
+            Some(label) => label.clone(),
+            None => session_id.to_string(),
+        };
+        if label.len() > MAX_LABEL_LENGTH {
+            return Err(JsValue::from_str(&format!(
+                "data channel label is {} bytes long, at most {MAX_LABEL_LENGTH} are allowed",
+                label.len()
+            )));
+        }
+        Ok(label)
+    }
+}
+
 impl ConnectionType {
     pub(crate) fn create_peer_connection(
         &self,
         hostname: &str,
+        config: &ConnectionConfig,
     ) -> Result<RtcPeerConnection, JsValue> {
-        use ConnectionType::{Local, Stun, StunAndTurn};
-        match self {
-            Local => RtcPeerConnection::new(),
+        use ConnectionType::{External, InPage, Local, Stun, StunAndTurn};
+        let rtc_configuration = match self {
+            Local | InPage => RtcConfiguration::new(),
             Stun => {
                 let ice_servers = Array::new();
                 let server_entry = Object::new();
@@ -36,45 +270,135 @@ impl ConnectionType {
 
                 ice_servers.push(&*server_entry);
 
-                let mut rtc_configuration = RtcConfiguration::new();
-                rtc_configuration.ice_servers(&ice_servers);
-
-                RtcPeerConnection::new_with_configuration(&rtc_configuration)
+                let rtc_configuration = RtcConfiguration::new();
+                rtc_configuration.set_ice_servers(&ice_servers);
+                rtc_configuration
             }
             StunAndTurn {
+                stun_urls,
+                turn_urls,
                 username,
                 credential,
             } => {
+                let or_signaling_host = |urls: &Vec<String>, default: String| {
+                    if urls.is_empty() {
+                        vec![default]
+                    } else {
+                        urls.clone()
+                    }
+                };
+                let servers = [
+                    IceServer {
+                        urls: or_signaling_host(stun_urls, format!("stun:{hostname}:{STUN_PORT}")),
+                        username: None,
+                        credential: None,
+                    },
+                    IceServer {
+                        urls: or_signaling_host(turn_urls, format!("turn:{hostname}:{TURN_PORT}")),
+                        username: Some(username.clone()),
+                        credential: Some(credential.clone()),
+                    },
+                ];
                 let ice_servers = Array::new();
-                let stun_server_entry = Object::new();
+                for server in &servers {
+                    ice_servers.push(&*server.to_js()?);
+                }
 
-                // NOTE: it's plural, but also accepts unique string
-                let url = format!("stun:{hostname}:{STUN_PORT}");
-                Reflect::set(&stun_server_entry, &"urls".into(), &url.into())?;
+                let rtc_configuration = RtcConfiguration::new();
+                rtc_configuration.set_ice_servers(&ice_servers);
+                rtc_configuration
+            }
+            External(servers) => {
+                let ice_servers = Array::new();
+                for server in servers {
+                    ice_servers.push(&*server.to_js()?);
+                }
 
-                ice_servers.push(&*stun_server_entry);
-                let turn_server_entry = Object::new();
+                let rtc_configuration = RtcConfiguration::new();
+                rtc_configuration.set_ice_servers(&ice_servers);
+                rtc_configuration
+            }
+        };
+        config.configure(&rtc_configuration)?;
+        RtcPeerConnection::new_with_configuration(&rtc_configuration)
+    }
+}
 
-                let url = format!("turn:{hostname}:{TURN_PORT}");
-                Reflect::set(&turn_server_entry, &"urls".into(), &url.into())?;
-                Reflect::set(&turn_server_entry, &"username".into(), &username.into())?;
-                Reflect::set(&turn_server_entry, &"credential".into(), &credential.into())?;
+/// Fingerprint of the DTLS certificate of a peer, as announced in its SDP
+/// by an `a=fingerprint:<algorithm> <value>` line.
+///
+/// Comparing it to a fingerprint exchanged out-of-band, see [crate::one_to_one::NetworkManager::verify_fingerprint],
+/// detects a signaling server swapping the descriptions of the peers to stand in the middle.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DtlsFingerprint {
+    /// Hash function, lowercase, for example `sha-256`
+    pub algorithm: String,
+    /// Uppercase hexadecimal bytes separated by colons
+    pub value: String,
+}
 
-                ice_servers.push(&*turn_server_entry);
+impl DtlsFingerprint {
+    /// Fingerprint of the first `a=fingerprint` line of `sdp`, `None` if there is none.
+    /// All media of a connection share one certificate, so the first line is enough.
+    pub fn from_sdp(sdp: &str) -> Option<Self> {
+        sdp.lines().find_map(|line| {
+            let attribute = line.trim().strip_prefix("a=fingerprint:")?;
+            let (algorithm, value) = attribute.trim().split_once(' ')?;
+            Some(DtlsFingerprint {
+                algorithm: algorithm.to_ascii_lowercase(),
+                value: value.trim().to_ascii_uppercase(),
+            })
+        })
+    }
+}
 
-                let mut rtc_configuration = RtcConfiguration::new();
-                rtc_configuration.ice_servers(&ice_servers);
+impl std::fmt::Display for DtlsFingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.algorithm, self.value)
+    }
+}
 
-                RtcPeerConnection::new_with_configuration(&rtc_configuration)
+/// State of the connection with the other peer, see [crate::one_to_one::NetworkManager::peer_connection_state].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerState {
+    /// Not connecting yet, waiting for candidates of the other peer
+    New,
+    /// Checking candidates for a working pair
+    Connecting,
+    /// A working pair of candidates was found, the connection is up
+    Connected,
+    /// The connection dropped, it may come back on its own
+    Disconnected,
+    /// No pair of candidates works, the connection won't come up without restarting ICE
+    Failed,
+    /// The connection was closed by this peer
+    Closed,
+}
+
+impl PeerState {
+    pub(crate) fn from_ice_connection_state(state: RtcIceConnectionState) -> Self {
+        match state {
+            RtcIceConnectionState::Checking => PeerState::Connecting,
+            RtcIceConnectionState::Connected | RtcIceConnectionState::Completed => {
+                PeerState::Connected
             }
+            RtcIceConnectionState::Disconnected => PeerState::Disconnected,
+            RtcIceConnectionState::Failed => PeerState::Failed,
+            RtcIceConnectionState::Closed => PeerState::Closed,
+            _ => PeerState::New,
         }
     }
 }
 
+/// With `ice_restart`, the offer carries new ICE credentials, gathering candidates again.
 pub(crate) async fn create_sdp_offer(
     peer_connection: &RtcPeerConnection,
+    sdp_transform: &SdpTransform,
+    ice_restart: bool,
 ) -> Result<String, JsValue> {
-    let offer = JsFuture::from(peer_connection.create_offer())
+    let options = RtcOfferOptions::new();
+    options.set_ice_restart(ice_restart);
+    let offer = JsFuture::from(peer_connection.create_offer_with_rtc_offer_options(&options))
         .await
         .map_err(|error| {
             JsValue::from_str(&format!(
@@ -85,8 +409,9 @@ pub(crate) async fn create_sdp_offer(
     let offer = Reflect::get(&offer, &JsValue::from_str("sdp"))?
         .as_string()
         .expect("failed to create JS object for SDP offer");
-    let mut local_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
-    local_session_description.sdp(&offer);
+    let offer = sdp_transform.apply(offer);
+    let local_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
+    local_session_description.set_sdp(&offer);
     JsFuture::from(peer_connection.set_local_description(&local_session_description))
         .await
         .map_err(|error| {
@@ -99,61 +424,328 @@ pub(crate) async fn create_sdp_offer(
     Ok(offer)
 }
 
+#[cfg(test)]
 pub(crate) async fn create_sdp_answer(
     peer_connection: &RtcPeerConnection,
     offer: String,
+    sdp_transform: &SdpTransform,
 ) -> Result<String, JsValue> {
-    let mut remote_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
-    remote_session_description.sdp(&offer);
-    JsFuture::from(peer_connection.set_remote_description(&remote_session_description)).await?;
+    set_remote_description(peer_connection, RtcSdpType::Offer, &offer).await?;
+    create_local_answer(peer_connection, sdp_transform).await
+}
 
-    let answer = JsFuture::from(peer_connection.create_answer()).await?;
-    let answer = Reflect::get(&answer, &JsValue::from_str("sdp"))?
-        .as_string()
-        .expect("failed to create JS object for SPD answer");
+/// Answer the offer already set as the remote description, rolling it back on failure.
+pub(crate) async fn create_local_answer(
+    peer_connection: &RtcPeerConnection,
+    sdp_transform: &SdpTransform,
+) -> Result<String, JsValue> {
+    let answer = async {
+        let answer = JsFuture::from(peer_connection.create_answer()).await?;
+        let answer = Reflect::get(&answer, &JsValue::from_str("sdp"))?
+            .as_string()
+            .expect("failed to create JS object for SPD answer");
+        let answer = sdp_transform.apply(answer);
+        let local_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
+        local_session_description.set_sdp(&answer);
+        JsFuture::from(peer_connection.set_local_description(&local_session_description)).await?;
+        Ok(answer)
+    };
+    match answer.await {
+        Ok(answer) => Ok(answer),
+        Err(error) => {
+            rollback(peer_connection).await;
+            Err(error)
+        }
+    }
+}
+
+/// Characters of a refused SDP kept in [WebRtcError::InvalidSdp].
+const SDP_SNIPPET_LENGTH: usize = 200;
 
-    let mut local_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
-    local_session_description.sdp(&answer);
-    JsFuture::from(peer_connection.set_local_description(&local_session_description)).await?;
+/// Apply the SDP of the other peer. If the browser refuses it, the connection is rolled back
+/// to its last stable state, so that the next offer can be applied.
+pub(crate) async fn set_remote_description(
+    peer_connection: &RtcPeerConnection,
+    sdp_type: RtcSdpType,
+    sdp: &str,
+) -> Result<(), WebRtcError> {
+    let remote_session_description = RtcSessionDescriptionInit::new(sdp_type);
+    remote_session_description.set_sdp(sdp);
+    let result =
+        JsFuture::from(peer_connection.set_remote_description(&remote_session_description)).await;
+    let error = match result {
+        Ok(_) => return Ok(()),
+        Err(error) => error,
+    };
+    rollback(peer_connection).await;
+    let mut snippet: String = sdp.chars().take(SDP_SNIPPET_LENGTH).collect();
+    if snippet.len() < sdp.len() {
+        snippet.push_str("...");
+    }
+    Err(WebRtcError::InvalidSdp {
+        sdp: snippet,
+        error: js_error_message(&error),
+    })
+}
+
+/// Drop a half applied offer or answer, the browser refusing new ones until then.
+async fn rollback(peer_connection: &RtcPeerConnection) {
+    if peer_connection.signaling_state() == RtcSignalingState::Stable {
+        return;
+    }
+    let rollback = RtcSessionDescriptionInit::new(RtcSdpType::Rollback);
+    if let Err(error) = JsFuture::from(peer_connection.set_local_description(&rollback)).await {
+        log::error!("failed to roll back the session description: {error:?}");
+    }
+}
 
-    Ok(answer)
+/// Message of a JS exception, which is usually a `DOMException` rather than a string.
+pub(crate) fn js_error_message(error: &JsValue) -> String {
+    error
+        .as_string()
+        .or_else(|| Reflect::get(error, &"message".into()).ok()?.as_string())
+        .unwrap_or_else(|| format!("{error:?}"))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
-    use web_sys::{RtcIceConnectionState, RtcIceGatheringState};
+    use web_sys::RtcIceGatheringState;
 
     wasm_bindgen_test_configure!(run_in_browser);
 
+    #[wasm_bindgen_test]
+    fn test_reliability_presets_set_exclusive_options() {
+        let get = |init: &RtcDataChannelInit, key: &str| Reflect::get(init, &key.into()).unwrap();
+        let mut init = RtcDataChannelInit::new();
+        Reliability::TimeLimited {
+            max_packet_life_time_ms: 500,
+        }
+        .configure(&mut init);
+        assert_eq!(get(&init, "ordered"), JsValue::FALSE);
+        assert_eq!(get(&init, "maxPacketLifeTime").as_f64(), Some(500.0));
+        assert!(get(&init, "maxRetransmits").is_undefined());
+
+        let mut init = RtcDataChannelInit::new();
+        Reliability::FullyReliable.configure(&mut init);
+        assert_eq!(get(&init, "ordered"), JsValue::TRUE);
+        assert!(get(&init, "maxRetransmits").is_undefined());
+        assert!(get(&init, "maxPacketLifeTime").is_undefined());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_invalid_sdp_is_refused_and_rolled_back() {
+        let peer_connection = RtcPeerConnection::new().unwrap();
+        let garbage = "x".repeat(SDP_SNIPPET_LENGTH * 2);
+        let error = set_remote_description(&peer_connection, RtcSdpType::Offer, &garbage)
+            .await
+            .unwrap_err();
+        match error {
+            WebRtcError::InvalidSdp { sdp, .. } => {
+                assert_eq!(sdp.len(), SDP_SNIPPET_LENGTH + "...".len())
+            }
+            other => panic!("expected an invalid SDP error, got {other:?}"),
+        }
+        assert_eq!(peer_connection.signaling_state(), RtcSignalingState::Stable);
+
+        let offer = create_sdp_offer(
+            &RtcPeerConnection::new().unwrap(),
+            &SdpTransform::default(),
+            false,
+        )
+        .await
+        .unwrap();
+        set_remote_description(&peer_connection, RtcSdpType::Offer, &offer)
+            .await
+            .unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    fn test_long_channel_label_is_refused() {
+        let session_id = SessionId::new(u128::MAX);
+        let config = ChannelConfig::default();
+        assert_eq!(config.label(session_id).unwrap(), session_id.to_string());
+
+        let config = ChannelConfig {
+            label: Some("x".repeat(MAX_LABEL_LENGTH + 1)),
+            ..ChannelConfig::default()
+        };
+        assert!(config.label(session_id).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fingerprint_is_read_from_sdp() {
+        let sdp = "v=0\r\n\
+                   o=- 4611731400430051336 2 IN IP4 127.0.0.1\r\n\
+                   m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n\
+                   a=fingerprint:SHA-256 ab:cd:01\r\n\
+                   a=setup:actpass\r\n";
+        assert_eq!(
+            DtlsFingerprint::from_sdp(sdp),
+            Some(DtlsFingerprint {
+                algorithm: "sha-256".to_string(),
+                value: "AB:CD:01".to_string(),
+            })
+        );
+        assert_eq!(
+            DtlsFingerprint::from_sdp("v=0\r\na=setup:actpass\r\n"),
+            None
+        );
+    }
+
     #[wasm_bindgen_test]
     fn test_create_stun_peer_connection_is_successful() {
         let peer_connection = ConnectionType::Local
-            .create_peer_connection("localhost:9090")
+            .create_peer_connection("localhost:9090", &ConnectionConfig::default())
             .expect("creating peer connection failed!");
         assert_eq!(
             peer_connection.ice_connection_state(),
             RtcIceConnectionState::New
         );
+        assert_eq!(
+            PeerState::from_ice_connection_state(peer_connection.ice_connection_state()),
+            PeerState::New
+        );
         assert_eq!(
             peer_connection.ice_gathering_state(),
             RtcIceGatheringState::New
         );
     }
 
+    #[wasm_bindgen_test]
+    fn test_create_external_peer_connection_is_successful() {
+        let servers = vec![
+            IceServer {
+                urls: vec!["stun:stun.example.com:3478".to_string()],
+                username: None,
+                credential: None,
+            },
+            IceServer {
+                urls: vec!["turn:turn.example.com:3478".to_string()],
+                username: Some("user".to_string()),
+                credential: Some("secret".to_string()),
+            },
+        ];
+        ConnectionType::External(servers)
+            .create_peer_connection("localhost:9090", &ConnectionConfig::default())
+            .expect("creating peer connection failed!");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_create_stun_and_turn_peer_connection_with_separate_hosts() {
+        let connection_type = ConnectionType::StunAndTurn {
+            stun_urls: vec!["stun:stun.example.com:3478".to_string()],
+            turn_urls: vec![
+                "turn:turn.example.com:3478".to_string(),
+                "turns:turn.example.com:5349".to_string(),
+            ],
+            username: "user".to_string(),
+            credential: "secret".to_string(),
+        };
+        connection_type
+            .create_peer_connection("localhost:9090", &ConnectionConfig::default())
+            .expect("creating peer connection failed!");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_create_peer_connection_with_ice_candidate_pool() {
+        let config = ConnectionConfig {
+            ice_candidate_pool_size: Some(4),
+            ..ConnectionConfig::default()
+        };
+        ConnectionType::Local
+            .create_peer_connection("localhost:9090", &config)
+            .expect("creating peer connection failed!");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_signaling_url_includes_signaling_path() {
+        let config = ConnectionConfig {
+            signaling_path: "/game/signaling/".to_string(),
+            ..ConnectionConfig::default()
+        };
+        assert_eq!(
+            config.signaling_url("localhost", "one-to-one"),
+            format!("ws://localhost:{WS_PORT}/game/signaling/one-to-one")
+        );
+        assert_eq!(
+            ConnectionConfig::default().signaling_url("localhost", "one-to-one"),
+            format!("ws://localhost:{WS_PORT}/one-to-one")
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_signaling_url_ends_with_namespace() {
+        let config = ConnectionConfig {
+            signaling_path: "signaling".to_string(),
+            namespace: "footballers".to_string(),
+            ..ConnectionConfig::default()
+        };
+        assert_eq!(
+            config.signaling_url("localhost", "one-to-one"),
+            format!("ws://localhost:{WS_PORT}/signaling/one-to-one/footballers")
+        );
+    }
+
     #[wasm_bindgen_test]
     async fn test_create_sdp_offer_is_successful() {
         let peer_connection = RtcPeerConnection::new().expect("failed to create peer connection");
-        let _offer = create_sdp_offer(&peer_connection).await.unwrap();
+        let _offer = create_sdp_offer(&peer_connection, &SdpTransform::default(), false)
+            .await
+            .unwrap();
         assert!(peer_connection.local_description().is_some());
     }
 
+    #[wasm_bindgen_test]
+    async fn test_sdp_transform_edits_the_local_description() {
+        let peer_connection = RtcPeerConnection::new().expect("failed to create peer connection");
+        peer_connection.create_data_channel("transformed");
+        let sdp_transform = SdpTransform::default();
+        // caps the bandwidth of the data channel
+        sdp_transform.set(|sdp| sdp.replacen("\r\nc=", "\r\nb=AS:30\r\nc=", 1));
+        let offer = create_sdp_offer(&peer_connection, &sdp_transform, false)
+            .await
+            .unwrap();
+        assert!(offer.contains("b=AS:30"));
+        assert_eq!(peer_connection.local_description().unwrap().sdp(), offer);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_ice_restart_offer_changes_the_ice_credentials() {
+        fn ice_ufrag(sdp: &str) -> &str {
+            sdp.lines()
+                .find(|line| line.starts_with("a=ice-ufrag:"))
+                .unwrap()
+        }
+        let peer_connection = RtcPeerConnection::new().expect("failed to create peer connection");
+        peer_connection.create_data_channel("restarted");
+        let other_peer = RtcPeerConnection::new().expect("failed to create peer connection");
+        let offer = create_sdp_offer(&peer_connection, &SdpTransform::default(), false)
+            .await
+            .unwrap();
+        let answer = create_sdp_answer(&other_peer, offer.clone(), &SdpTransform::default())
+            .await
+            .unwrap();
+        set_remote_description(&peer_connection, RtcSdpType::Answer, &answer)
+            .await
+            .unwrap();
+
+        let restart = create_sdp_offer(&peer_connection, &SdpTransform::default(), true)
+            .await
+            .unwrap();
+        assert_ne!(ice_ufrag(&restart), ice_ufrag(&offer));
+    }
+
     #[wasm_bindgen_test]
     async fn test_create_sdp_answer_is_successful() {
         let peer_connection = RtcPeerConnection::new().expect("failed to create peer connection");
-        let offer = create_sdp_offer(&peer_connection).await.unwrap();
-        let _answer = create_sdp_answer(&peer_connection, offer).await.unwrap();
+        let offer = create_sdp_offer(&peer_connection, &SdpTransform::default(), false)
+            .await
+            .unwrap();
+        let _answer = create_sdp_answer(&peer_connection, offer, &SdpTransform::default())
+            .await
+            .unwrap();
         assert!(peer_connection.local_description().is_some());
         assert!(peer_connection.remote_description().is_some());
     }
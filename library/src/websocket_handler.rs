@@ -1,116 +1,288 @@
-use crate::utils::{create_sdp_answer, create_sdp_offer};
+use crate::callbacks::{FingerprintCheck, InitialChannel, Pings, SdpTransform};
+use crate::peer_connection::{PeerConnection, PendingCandidates};
+use crate::signaling::{JoinRetry, Signaling};
+use crate::utils::create_sdp_offer;
 use ::log::{debug, error, info};
+use std::cell::Cell;
+use std::rc::Rc;
 use wasm_bindgen::JsValue;
-use wasm_bindgen_futures::JsFuture;
-use wasm_peers_protocol::one_to_one::SignalMessage;
-use web_sys::{
-    RtcIceCandidate, RtcIceCandidateInit, RtcPeerConnection, RtcSdpType, RtcSessionDescriptionInit,
-    WebSocket,
-};
+use wasm_peers_protocol::one_to_one::{Capabilities, SignalMessage};
+use wasm_peers_protocol::text::{clamp_text, MAX_ERROR_LEN};
+use wasm_peers_protocol::{SessionId, UserId};
+use web_sys::{RtcIceConnectionState, RtcPeerConnection, RtcSdpType, WebSocket};
+
+/// Serialize and send a message to the signaling server,
+/// compressing it if the server agreed to it in its [SignalMessage::Hello].
+pub(crate) fn send_signal_message(
+    websocket: &WebSocket,
+    message: SignalMessage,
+    compression: bool,
+) -> Result<(), JsValue> {
+    #[cfg(feature = "compression")]
+    let message = if compression {
+        message.compress()
+    } else {
+        message
+    };
+    #[cfg(not(feature = "compression"))]
+    let _ = compression;
+    let message = rmp_serde::to_vec(&message).expect("failed to serialize SignalMessage");
+    websocket.send_with_u8_array(&message)
+}
+
+/// Announce the optional protocol features this peer supports, first thing once the websocket opens.
+pub(crate) fn send_hello(websocket: &WebSocket) -> Result<(), JsValue> {
+    let capabilities = Capabilities {
+        compression: cfg!(feature = "compression"),
+    };
+    send_signal_message(websocket, SignalMessage::Hello(capabilities), false)
+}
+
+/// Announce supported capabilities and send a request to start or join a session,
+/// or to get back the slot of `user_id` in it when connecting again.
+pub(crate) fn join_session(
+    websocket: &WebSocket,
+    session_id: SessionId,
+    user_id: Option<UserId>,
+    join_retry: &JoinRetry,
+) {
+    send_hello(websocket).expect("failed sending hello message to the websocket");
+    let signal_message = match user_id {
+        Some(user_id) => SignalMessage::SessionReconnect(session_id, user_id),
+        None => SignalMessage::SessionJoin(session_id),
+    };
+    send_signal_message(websocket, signal_message.clone(), false)
+        .expect("failed sending start-or-join message to the websocket");
+    join_retry.start(websocket.clone(), signal_message);
+}
+
+/// Create a new SDP offer and send it to the other peer through the signaling server,
+/// the answer is then handled by [handle_websocket_message].
+pub(crate) async fn send_sdp_offer(
+    peer_connection: &RtcPeerConnection,
+    signaling: &Signaling,
+    session_id: SessionId,
+    compression: bool,
+    sdp_transform: &SdpTransform,
+    ice_restart: bool,
+) -> Result<(), JsValue> {
+    let offer = create_sdp_offer(peer_connection, sdp_transform, ice_restart).await?;
+    let signal_message = SignalMessage::SdpOffer(session_id, offer);
+    signaling.send(signal_message, compression)?;
+    debug!("sent an offer successfully");
+    Ok(())
+}
 
 /// Basically a state automata spread across host, client and signaling server,
 /// handling each step in session and then WebRTC setup.
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn handle_websocket_message(
     message: SignalMessage,
     peer_connection: RtcPeerConnection,
-    websocket: WebSocket,
+    signaling: Signaling,
+    compression: Rc<Cell<bool>>,
+    session_owner: Rc<Cell<Option<bool>>>,
+    user_id: Rc<Cell<Option<UserId>>>,
+    fingerprint_check: FingerprintCheck,
+    sdp_transform: SdpTransform,
+    pings: Pings,
+    candidates: PendingCandidates,
+    initial_channel: InitialChannel,
 ) -> Result<(), JsValue> {
     match message {
-        SignalMessage::SessionJoin(_session_id) => {
+        SignalMessage::Hello(capabilities) => {
+            debug!("signaling server capabilities: {capabilities:?}");
+            compression.set(capabilities.compression && cfg!(feature = "compression"));
+        }
+        SignalMessage::SessionJoin(_session_id)
+        | SignalMessage::SessionReconnect(_session_id, _) => {
             error!("error, SessionStartOrJoin should only be sent by peers to signaling server");
         }
-        SignalMessage::SessionReady(session_id) => {
-            info!("peer received info that session is ready {:?}", session_id);
-            let offer = create_sdp_offer(&peer_connection).await?;
-            let signal_message = SignalMessage::SdpOffer(session_id, offer);
-            let signal_message =
-                rmp_serde::to_vec(&signal_message).expect("failed to serialize SignalMessage");
-            websocket.send_with_u8_array(&signal_message)?;
-            debug!("sent an offer successfully");
+        SignalMessage::SessionReady(session_id, owner, id) => {
+            info!("peer received info that session is ready {session_id:?}, owning it: {owner}");
+            session_owner.set(Some(owner));
+            user_id.set(Some(id));
+            // the owner offers, the other peer waits for its offer rather than racing it
+            if !owner {
+                return Ok(());
+            }
+            initial_channel.open();
+            // back on the server after releasing the connection, to restart ICE
+            let ice_restart =
+                peer_connection.ice_connection_state() == RtcIceConnectionState::Failed;
+            send_sdp_offer(
+                &peer_connection,
+                &signaling,
+                session_id,
+                compression.get(),
+                &sdp_transform,
+                ice_restart,
+            )
+            .await?;
         }
+        message @ (SignalMessage::SdpOffer(..)
+        | SignalMessage::SdpAnswer(..)
+        | SignalMessage::IceCandidate(..)) => {
+            let settles = !matches!(message, SignalMessage::IceCandidate(..));
+            handle_negotiation_message(
+                message,
+                &peer_connection,
+                |message| signaling.send(message, compression.get()),
+                &fingerprint_check,
+                &sdp_transform,
+                &candidates,
+            )
+            .await?;
+            if settles {
+                signaling.release_if_settled(&peer_connection);
+            }
+        }
+        SignalMessage::Error(session_id, error) => {
+            // the server may not be ours, its explanation is cut like the ones we send
+            error!(
+                "signaling server returned error: session id: {:?}, error:{}",
+                session_id,
+                clamp_text(&error, MAX_ERROR_LEN)
+            );
+        }
+        SignalMessage::Compressed(_) => {
+            error!("error, compressed messages should be decompressed before handling");
+        }
+        SignalMessage::Ping(session_id, token) => {
+            signaling.send(SignalMessage::Pong(session_id, token), compression.get())?;
+        }
+        SignalMessage::Pong(_session_id, token) => pings.pong(token),
+        // for the application, see [crate::one_to_one::NetworkManager::on_signal_message]
+        SignalMessage::Custom(..) => {}
+        unknown => {
+            error!("ignoring signal message unknown to this peer: {unknown:?}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Offer, answer and ICE candidates of the other peer, applied to `peer`,
+/// the answer to an offer being handed to `send`.
+/// Candidates arriving before the offer or answer are kept until it is applied.
+pub(crate) async fn handle_negotiation_message<P: PeerConnection>(
+    message: SignalMessage,
+    peer: &P,
+    send: impl FnOnce(SignalMessage) -> Result<(), JsValue>,
+    fingerprint_check: &FingerprintCheck,
+    sdp_transform: &SdpTransform,
+    candidates: &PendingCandidates,
+) -> Result<(), JsValue> {
+    match message {
         SignalMessage::SdpOffer(session_id, offer) => {
-            let answer = create_sdp_answer(&peer_connection, offer)
-                .await
-                .expect("failed to create SDP answer");
+            reject_unverified(peer, fingerprint_check, &offer)?;
+            peer.set_remote_description(RtcSdpType::Offer, &offer)
+                .await?;
+            candidates.flush(peer).await?;
+            let answer = peer.create_answer(sdp_transform).await?;
             debug!("received an offer and created an answer: {}", answer);
-            let signal_message = SignalMessage::SdpAnswer(session_id, answer);
-            let signal_message =
-                rmp_serde::to_vec(&signal_message).expect("failed to serialize SignalMessage");
-            websocket
-                .send_with_u8_array(&signal_message)
-                .expect("failed to send SPD answer to signaling server");
+            send(SignalMessage::SdpAnswer(session_id, answer))?;
         }
         SignalMessage::SdpAnswer(session_id, answer) => {
-            let mut remote_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
-            remote_session_description.sdp(&answer);
-            JsFuture::from(peer_connection.set_remote_description(&remote_session_description))
-                .await
-                .expect("failed to set remote descripiton");
+            reject_unverified(peer, fingerprint_check, &answer)?;
+            peer.set_remote_description(RtcSdpType::Answer, &answer)
+                .await?;
             debug!(
                 "received answer from peer and set remote description: {}, {:?}",
                 answer, session_id
             );
+            candidates.flush(peer).await?;
         }
         SignalMessage::IceCandidate(_session_id, ice_candidate) => {
             debug!("peer received ice candidate: {ice_candidate:?}");
-            let mut rtc_candidate = RtcIceCandidateInit::new("");
-            rtc_candidate.candidate(&ice_candidate.candidate);
-            rtc_candidate.sdp_m_line_index(ice_candidate.sdp_m_line_index);
-            rtc_candidate.sdp_mid(ice_candidate.sdp_mid.as_deref());
+            candidates.add(peer, ice_candidate).await?;
+        }
+        other => error!("not a negotiation message: {other:?}"),
+    }
+    Ok(())
+}
 
-            let rtc_candidate =
-                RtcIceCandidate::new(&rtc_candidate).expect("failed to create new RtcIceCandidate");
-            JsFuture::from(
-                peer_connection.add_ice_candidate_with_opt_rtc_ice_candidate(Some(&rtc_candidate)),
-            )
-            .await
-            .expect("failed to add ICE candidate");
-            debug!("added ice candidate {:?}", ice_candidate);
+/// Closes the connection if the DTLS fingerprint of the other peer's SDP doesn't pass the check.
+fn reject_unverified<P: PeerConnection>(
+    peer: &P,
+    fingerprint_check: &FingerprintCheck,
+    sdp: &str,
+) -> Result<(), JsValue> {
+    if fingerprint_check.accepts(sdp) {
+        return Ok(());
+    }
+    peer.close();
+    Err(JsValue::from_str(
+        "DTLS fingerprint of the other peer was rejected, closing the connection",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::peer_connection::{Call, Recorder};
+    use std::cell::RefCell;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+    use wasm_peers_protocol::one_to_one::IceCandidate;
+
+    /// Run `future` to completion, which it reaches at once as the recorder never waits.
+    fn run<T>(future: impl Future<Output = T>) -> T {
+        match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("the recorder never waits"),
         }
-        SignalMessage::Error(session_id, error) => {
-            error!(
-                "signaling server returned error: session id: {:?}, error:{}",
-                session_id, error
-            );
+    }
+
+    fn candidate(line: &str) -> IceCandidate {
+        IceCandidate {
+            candidate: line.to_string(),
+            sdp_mid: Some("0".to_string()),
+            sdp_m_line_index: Some(0),
+            candidate_type: None,
         }
     }
 
-    Ok(())
-}
+    #[test]
+    fn offer_is_answered_once_after_the_early_candidates() {
+        let session_id = SessionId::new(1);
+        let peer = Recorder::default();
+        let candidates = PendingCandidates::default();
+        let sent = RefCell::new(Vec::new());
+        let receive = |message| {
+            run(handle_negotiation_message(
+                message,
+                &peer,
+                |message| {
+                    sent.borrow_mut().push(message);
+                    Ok(())
+                },
+                &FingerprintCheck::default(),
+                &SdpTransform::default(),
+                &candidates,
+            ))
+        };
 
-// // TODO(tkarwowski): uncomment once mocks work
-// #[cfg(test)]
-// mod test {
-//     use super::*;
-//     use mockall::mock;
-//     use wasm_bindgen_test::wasm_bindgen_test_configure;
-//
-//     use wasm_peers_protocol::SessionId;
-//
-//     wasm_bindgen_test_configure!(run_in_browser);
-//
-//     mock! {
-//         WebSocket {}
-//     }
-//
-//     #[wasm_bindgen_test]
-//     async fn test_handle_session_ready_signal_is_successful() {
-//         let message =
-//             SignalMessage::SessionReady(SessionId::new("dummy-session-rmp_serde::to_vec()), true);
-//         let peer_connection = RtcPeerConnection::new().unwrap();
-//
-//         // TODO(tkarwowski): this should be mocked, but how do you pass a mock to a function expecting different type?
-//         //  I could introduce a trait, implement it for web_sys::WebSocket and MockWebSocket as well,
-//         //  but that's a lot of work...
-//         //  This is a integration test for now.
-//         let websocket = WebSocket::new("ws://0.0.0.0:9001/ws")
-//             .expect("local signaling server instance was not found");
-//         websocket.set_binary_type(web_sys::BinaryType::Arraybuffer);
-//
-//         // FIXME(tkarwowski): this fails because peer_connection state gets modified in other tests
-//         handle_websocket_message(message, peer_connection.clone(), websocket)
-//             .await
-//             .unwrap();
-//         assert!(peer_connection.local_description().is_some());
-//     }
-// }
+        receive(SignalMessage::IceCandidate(session_id, candidate("early"))).unwrap();
+        assert!(peer.calls.borrow().is_empty());
+        receive(SignalMessage::SdpOffer(session_id, "offer".to_string())).unwrap();
+        receive(SignalMessage::IceCandidate(session_id, candidate("late"))).unwrap();
+
+        assert_eq!(
+            *peer.calls.borrow(),
+            [
+                Call::SetRemoteDescription(RtcSdpType::Offer, "offer".to_string()),
+                Call::AddIceCandidate("early".to_string()),
+                Call::CreateAnswer,
+                Call::AddIceCandidate("late".to_string()),
+            ]
+        );
+        let sent = sent.borrow();
+        assert_eq!(sent.len(), 1);
+        assert!(matches!(
+            &sent[0],
+            SignalMessage::SdpAnswer(id, answer) if *id == session_id && answer == "answer"
+        ));
+    }
+}
@@ -2,11 +2,13 @@
 
 #![cfg(target_arch = "wasm32")]
 
+use js_sys::Promise;
 use std::cell::RefCell;
 use std::rc::Rc;
+use wasm_bindgen_futures::JsFuture;
 use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
 use wasm_peers::one_to_one::NetworkManager;
-use wasm_peers::{ConnectionType, SessionId};
+use wasm_peers::{ChannelConfig, ConnectionType, SessionId};
 use web_sys::console;
 
 const SIGNALING_SERVER_URL: &str = "ws://0.0.0.0:9001/one-to-one";
@@ -15,6 +17,23 @@ wasm_bindgen_test_configure!(run_in_browser);
 
 const DUMMY_SESSION_ID: u128 = 1234;
 
+/// Waits for `condition` to hold, for 10 seconds at most, returning whether it did.
+async fn wait_for(condition: impl Fn() -> bool) -> bool {
+    for _ in 0..100 {
+        if condition() {
+            return true;
+        }
+        let promise = Promise::new(&mut |resolve, _| {
+            web_sys::window()
+                .unwrap()
+                .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, 100)
+                .unwrap();
+        });
+        JsFuture::from(promise).await.unwrap();
+    }
+    condition()
+}
+
 #[wasm_bindgen_test]
 fn network_manager_starts_successfully() {
     let mut server = NetworkManager::new(
@@ -23,50 +42,52 @@ fn network_manager_starts_successfully() {
         ConnectionType::Local,
     )
     .unwrap();
-    server.start(|| {}, |_| {}).unwrap();
+    server
+        .start(ChannelConfig::default(), || {}, |_: String| {})
+        .unwrap();
 }
 
+/// Both network managers are in this page, connected without a signaling server.
 #[wasm_bindgen_test]
-fn single_message_passes_both_ways() {
+async fn single_message_passes_both_ways() {
     let server_received_message = Rc::new(RefCell::new(false));
     let client_received_message = Rc::new(RefCell::new(false));
 
-    let mut server = NetworkManager::new(
-        SIGNALING_SERVER_URL,
-        SessionId::new(DUMMY_SESSION_ID),
-        ConnectionType::Local,
-    )
-    .unwrap();
+    let mut server =
+        NetworkManager::new("", SessionId::new(DUMMY_SESSION_ID), ConnectionType::InPage).unwrap();
 
     let server_clone = server.clone();
-    let server_on_open = move || server_clone.send_message("ping!").unwrap();
+    let server_on_open = move || server_clone.send_message(&"ping!").unwrap();
     let server_on_message = {
         let server_received_message = server_received_message.clone();
-        move |message| {
+        move |message: String| {
             console::log_1(&format!("server received message: {}", message).into());
             *server_received_message.borrow_mut() = true;
         }
     };
-    server.start(server_on_open, server_on_message).unwrap();
+    server
+        .start(ChannelConfig::default(), server_on_open, server_on_message)
+        .unwrap();
 
-    let mut client = NetworkManager::new(
-        SIGNALING_SERVER_URL,
-        SessionId::new(DUMMY_SESSION_ID),
-        ConnectionType::Local,
-    )
-    .unwrap();
+    let mut client =
+        NetworkManager::new("", SessionId::new(DUMMY_SESSION_ID), ConnectionType::InPage).unwrap();
     let client_on_open = || { /* do nothing */ };
     let client_clone = client.clone();
     let client_on_message = {
         let client_received_message = client_received_message.clone();
-        move |message| {
+        move |message: String| {
             console::log_1(&format!("client received message: {}", message).into());
-            client_clone.send_message("pong!").unwrap();
+            client_clone.send_message(&"pong!").unwrap();
             *client_received_message.borrow_mut() = true;
         }
     };
-    client.start(client_on_open, client_on_message).unwrap();
+    client
+        .start(ChannelConfig::default(), client_on_open, client_on_message)
+        .unwrap();
 
-    // assert!(*client_received_message.borrow());
-    // assert!(*server_received_message.borrow());
+    wait_for(|| *client_received_message.borrow() && *server_received_message.borrow()).await;
+    assert!(*client_received_message.borrow());
+    assert!(*server_received_message.borrow());
+    server.close();
+    client.close();
 }
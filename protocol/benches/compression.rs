@@ -0,0 +1,57 @@
+//! Measures how much a typical data channel SDP offer shrinks once compressed,
+//! and how long compressing and decompressing it takes.
+//!
+//! Run with `cargo bench -p wasm-peers-protocol --features compression`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use wasm_peers_protocol::one_to_one::SignalMessage;
+use wasm_peers_protocol::SessionId;
+
+/// Offer generated by Chromium for a single data channel, with host and srflx candidates.
+const SDP_OFFER: &str = "v=0\r
+o=- 4611731400430051336 2 IN IP4 127.0.0.1\r
+s=-\r
+t=0 0\r
+a=group:BUNDLE 0\r
+a=extmap-allow-mixed\r
+a=msid-semantic: WMS\r
+m=application 50712 UDP/DTLS/SCTP webrtc-datachannel\r
+c=IN IP4 203.0.113.17\r
+a=candidate:2999745851 1 udp 2122260223 192.168.56.1 50710 typ host generation 0 network-id 1\r
+a=candidate:1425006281 1 udp 2122194687 192.168.1.34 50711 typ host generation 0 network-id 2\r
+a=candidate:3524163919 1 udp 1686052607 203.0.113.17 50712 typ srflx raddr 192.168.1.34 rport 50711 generation 0 network-id 2\r
+a=candidate:4233069003 1 tcp 1518280447 192.168.56.1 9 typ host tcptype active generation 0 network-id 1\r
+a=candidate:394769209 1 tcp 1518214911 192.168.1.34 9 typ host tcptype active generation 0 network-id 2\r
+a=ice-ufrag:yqHk\r
+a=ice-pwd:wV2kdD0mPh3jRZ6ytI3nzL4G\r
+a=ice-options:trickle\r
+a=fingerprint:sha-256 7B:8B:F0:65:5F:78:E2:51:3B:AC:6F:F3:3F:46:1B:35:DC:B8:5F:64:1A:24:C2:43:F0:A1:58:D0:A1:2C:19:08\r
+a=setup:actpass\r
+a=mid:0\r
+a=sctp-port:5000\r
+a=max-message-size:262144\r
+";
+
+fn compression_benchmark(c: &mut Criterion) {
+    let offer = SignalMessage::SdpOffer(SessionId::new(u128::MAX), SDP_OFFER.to_string());
+    let uncompressed_size = rmp_serde::to_vec(&offer).unwrap().len();
+    let compressed = offer.compress();
+    let compressed_size = rmp_serde::to_vec(&compressed).unwrap().len();
+    println!(
+        "SDP offer: {uncompressed_size} bytes uncompressed, {compressed_size} bytes compressed ({:.0}%)",
+        100.0 * compressed_size as f64 / uncompressed_size as f64
+    );
+
+    c.bench_function("compress SDP offer", |b| {
+        b.iter(|| {
+            let offer = SignalMessage::SdpOffer(SessionId::new(u128::MAX), SDP_OFFER.to_string());
+            black_box(offer.compress())
+        })
+    });
+    c.bench_function("decompress SDP offer", |b| {
+        b.iter(|| black_box(compressed.clone().decompress().unwrap()))
+    });
+}
+
+criterion_group!(benches, compression_benchmark);
+criterion_main!(benches);
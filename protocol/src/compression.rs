@@ -0,0 +1,77 @@
+/*!
+Deflate compression of [SignalMessage]s.
+
+SDP offers and answers are several kilobytes of highly redundant text, so they shrink
+considerably when deflated. Small messages such as ICE candidates are not worth the
+overhead and are left as is, see [COMPRESSION_THRESHOLD].
+
+Compression is only enabled once both ends advertised it through [SignalMessage::Hello].
+ */
+
+use crate::one_to_one::SignalMessage;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::{self, Read, Write};
+
+/// Size in bytes of a serialized [SignalMessage] below which it is sent uncompressed.
+pub const COMPRESSION_THRESHOLD: usize = 256;
+
+impl SignalMessage {
+    /// Wrap the message into [SignalMessage::Compressed] if its serialized
+    /// form is larger than [COMPRESSION_THRESHOLD], otherwise return it unchanged.
+    pub fn compress(self) -> SignalMessage {
+        let serialized = match rmp_serde::to_vec(&self) {
+            Ok(serialized) if serialized.len() > COMPRESSION_THRESHOLD => serialized,
+            _ => return self,
+        };
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        match encoder
+            .write_all(&serialized)
+            .and_then(|_| encoder.finish())
+        {
+            Ok(compressed) if compressed.len() < serialized.len() => {
+                SignalMessage::Compressed(compressed)
+            }
+            _ => self,
+        }
+    }
+
+    /// Unwrap a [SignalMessage::Compressed], other messages are returned unchanged.
+    pub fn decompress(self) -> io::Result<SignalMessage> {
+        let compressed = match self {
+            SignalMessage::Compressed(compressed) => compressed,
+            message => return Ok(message),
+        };
+        let mut serialized = Vec::new();
+        DeflateDecoder::new(compressed.as_slice()).read_to_end(&mut serialized)?;
+        rmp_serde::from_slice(&serialized)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SessionId;
+
+    #[test]
+    fn small_messages_stay_uncompressed() {
+        let message = SignalMessage::SessionJoin(SessionId::new(1234)).compress();
+        assert!(matches!(message, SignalMessage::SessionJoin(_)));
+    }
+
+    #[test]
+    fn large_messages_round_trip() {
+        let sdp = "a=candidate:1 1 udp 2122260223 192.168.1.2 50000 typ host\r\n".repeat(20);
+        let message = SignalMessage::SdpOffer(SessionId::new(1234), sdp.clone()).compress();
+        assert!(matches!(message, SignalMessage::Compressed(_)));
+        match message.decompress().unwrap() {
+            SignalMessage::SdpOffer(session_id, offer) => {
+                assert_eq!(session_id, SessionId::new(1234));
+                assert_eq!(offer, sdp);
+            }
+            other => panic!("unexpected message after decompression: {other:?}"),
+        }
+    }
+}
@@ -3,13 +3,17 @@ Helper crate that declares common types and structures shared between [wasm-peer
 and [wasm-peers-signaling-server](https://docs.rs/wasm-peers-signaling-server/latest/wasm_peers_signaling_server/).
 */
 
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use std::{
     fmt::{Display, Formatter},
     str::FromStr,
 };
 
+#[cfg(feature = "compression")]
+pub mod compression;
 pub mod one_to_one;
+pub mod text;
 
 /// Port used for the websocket signaling channel of the WebRTC connection.
 ///
@@ -48,15 +52,20 @@ pub const STUN_PORT: u16 = 9004;
 /// It also defeats the main benefit of P2P for games: lower latency. Since this means all
 /// the data will have to bounce through the TURN server.
 ///
-/// TURN is a feature flag that can be removed through your Cargo.toml, if you do not wish
-/// to provide a TURN server for your clients. (TODO: currently not)
+/// TURN is the `turn` feature of this crate, enabled by default. Disable it through your
+/// Cargo.toml if you do not provide a TURN server for your clients, and this constant goes away.
+///
+/// It used to be 9004 like [STUN_PORT], but both servers cannot listen on the same port,
+/// so running them together requires this distinct one.
 ///
 /// See MDN https://developer.mozilla.org/en-US/docs/Web/API/WebRTC_API/Protocols
-pub const TURN_PORT: u16 = 9004;
+#[cfg(feature = "turn")]
+pub const TURN_PORT: u16 = 9005;
 
 /// Unique identifier of signaling session that each user provides
 /// when communicating with the signaling server.
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SessionId(u128);
 
 impl SessionId {
@@ -76,16 +85,19 @@ impl Display for SessionId {
         write!(f, "Seid-{}", self.0)
     }
 }
+/// Parses both the [Display] form `Seid-{n}` and a bare number.
 impl FromStr for SessionId {
     type Err = <u128 as FromStr>::Err;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix("Seid-").unwrap_or(s);
         Ok(SessionId(s.parse()?))
     }
 }
 
 /// Unique identifier of each peer connected to signaling server
 /// useful when communicating in one-to-many and many-to-many topologies.
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct UserId(pub u64);
 
 impl UserId {
@@ -111,3 +123,54 @@ impl Display for UserId {
         write!(f, "{}", self.0)
     }
 }
+impl FromStr for UserId {
+    type Err = <u64 as FromStr>::Err;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(UserId(s.parse()?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn session_id_display_round_trips() {
+        // xorshift, to cover ids of every magnitude without a property testing dependency
+        let mut state: u128 = 0x2545_f491_4f6c_dd1d;
+        let random_ids = std::iter::repeat_with(move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        });
+        let ids = [0, 1, 12348, u64::MAX as u128, u128::MAX]
+            .into_iter()
+            .chain(random_ids.take(1000));
+        for id in ids.map(SessionId::new) {
+            assert_eq!(id.to_string().parse(), Ok(id));
+        }
+    }
+
+    #[test]
+    fn session_id_parses_bare_number() {
+        assert_eq!("12348".parse(), Ok(SessionId::new(12348)));
+        assert!("Seid-".parse::<SessionId>().is_err());
+        assert!("Seid-Seid-1".parse::<SessionId>().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "turn")]
+    fn stun_and_turn_listen_on_distinct_ports() {
+        assert_ne!(STUN_PORT, TURN_PORT);
+        assert!(![STUN_PORT, TURN_PORT].contains(&WS_PORT));
+    }
+
+    #[test]
+    fn user_id_display_round_trips() {
+        for id in [0, 1, 4242, u64::MAX].map(UserId::new) {
+            assert_eq!(id.to_string().parse(), Ok(id));
+        }
+        assert!("-1".parse::<UserId>().is_err());
+    }
+}
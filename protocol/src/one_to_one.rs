@@ -3,25 +3,95 @@ Signaling messages exchanged between used by MiniServer, MiniClient and signalin
 to facilitate communication in client-server topology.
  */
 
-use crate::SessionId;
+use crate::text::{clamp_text, MAX_ERROR_LEN};
+use crate::{SessionId, UserId};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct IceCandidate {
     pub candidate: String,
     pub sdp_mid: Option<String>,
     pub sdp_m_line_index: Option<u16>,
+    /// Type parsed from the `candidate` line with [CandidateType::from_candidate],
+    /// `None` when sent by an older peer or when the line has no type.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub candidate_type: Option<CandidateType>,
+}
+
+/// How an ICE candidate reaches the peer that gathered it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CandidateType {
+    /// Address of a local network interface
+    Host,
+    /// Public address of the peer behind a NAT, as seen by a STUN server
+    ServerReflexive,
+    /// Public address of the peer behind a NAT, as seen by the other peer
+    PeerReflexive,
+    /// Address of a TURN server relaying the traffic
+    Relay,
+}
+
+impl CandidateType {
+    /// Parses the type following `typ` in a candidate line such as
+    /// `candidate:842163049 1 udp 1677729535 203.0.113.7 3478 typ srflx raddr 0.0.0.0 rport 0`.
+    pub fn from_candidate(candidate: &str) -> Option<Self> {
+        let mut fields = candidate.split_ascii_whitespace();
+        fields.find(|field| *field == "typ")?;
+        fields.next()?.parse().ok()
+    }
+}
+
+impl FromStr for CandidateType {
+    type Err = ();
+
+    /// Parses the type as written in candidate lines and in WebRTC statistics, `srflx` for example.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(CandidateType::Host),
+            "srflx" => Ok(CandidateType::ServerReflexive),
+            "prflx" => Ok(CandidateType::PeerReflexive),
+            "relay" => Ok(CandidateType::Relay),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Optional protocol features a peer or the signaling server supports,
+/// exchanged through [SignalMessage::Hello].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Capabilities {
+    /// Whether large messages may be sent as [SignalMessage::Compressed]
+    pub compression: bool,
 }
 
 /// Enum consisting of two main categories are messages used to setup signaling session
 /// and messages used to setup WebRTC connection afterwards.
-/// All of the messages include [SessionId] which is enough to identify the other peer in the connection.
-#[derive(Debug, Serialize, Deserialize)]
+/// Session messages include [SessionId] which is enough to identify the other peer in the connection.
+///
+/// New variants may be added without a major version bump, so matches on it need a catch-all arm.
+/// Peers should log and ignore messages they don't know how to handle.
+/// A message of a variant unknown to an older peer fails to decode, and is dropped there.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum SignalMessage {
     /// Either client or server connecting to signaling session
     SessionJoin(SessionId),
-    /// Report back to the users that both of them are in session
-    SessionReady(SessionId),
+    /// Report back to the users that both of them are in session,
+    /// whether the recipient owns it, having joined it first, and the id the server gave the recipient.
+    /// The owner is the one deciding how the session is configured, and offers the connection.
+    SessionReady(SessionId, bool, UserId),
+    /// Sent instead of [SignalMessage::SessionJoin] by a peer connecting again, with the id it had
+    /// in the session, so that the server gives it back its slot rather than taking it for a third peer.
+    SessionReconnect(SessionId, UserId),
+    /// Sent by a peer leaving the session for good, so that the server frees its slot right away
+    /// rather than once it notices the websocket closed.
+    SessionLeave(SessionId),
 
     /// SDP Offer that gets passed to the other user without modifications
     SdpOffer(SessionId, String),
@@ -32,4 +102,133 @@ pub enum SignalMessage {
 
     /// Generic error containing detailed information about the cause
     Error(SessionId, String),
+
+    /// Sent by a peer as soon as its websocket opens, and answered by the signaling server,
+    /// so that both ends only enable optional features the other understands.
+    Hello(Capabilities),
+    /// Deflated MessagePack encoding of another [SignalMessage],
+    /// only sent to a peer that announced [Capabilities::compression].
+    Compressed(#[cfg_attr(feature = "serde", serde(with = "serde_bytes"))] Vec<u8>),
+
+    /// Ask the signaling server to open a session under an id it picks, answered by
+    /// [SignalMessage::SessionCreated]. The sender is in the session right away, as after a [SignalMessage::SessionJoin].
+    SessionCreate,
+    /// Unguessable id minted by the signaling server for a [SignalMessage::SessionCreate],
+    /// for the sender to share with the peer that should join it.
+    SessionCreated(SessionId),
+
+    /// Token sent to the other peer of the session, which bounces it back in a [SignalMessage::Pong],
+    /// to measure the round trip time through the signaling server before the data channel opens.
+    Ping(SessionId, u64),
+    /// Answer to a [SignalMessage::Ping], with the same token.
+    Pong(SessionId, u64),
+
+    /// Payload of the application, passed to the other user in the session without modifications,
+    /// for example metadata of a game lobby exchanged before the data channel opens.
+    Custom(
+        SessionId,
+        #[cfg_attr(feature = "serde", serde(with = "serde_bytes"))] Vec<u8>,
+    ),
+}
+
+impl SignalMessage {
+    /// [SignalMessage::Error] explaining `error`, cut to [MAX_ERROR_LEN] characters.
+    pub fn error(session_id: SessionId, error: &str) -> Self {
+        SignalMessage::Error(session_id, clamp_text(error, MAX_ERROR_LEN).to_string())
+    }
+
+    /// Session the message is about, `None` for the ones about the connection to the
+    /// signaling server itself, and for [SignalMessage::Compressed] ones until decompressed.
+    pub fn session_id(&self) -> Option<SessionId> {
+        match self {
+            SignalMessage::SessionJoin(session_id)
+            | SignalMessage::SessionReady(session_id, ..)
+            | SignalMessage::SessionReconnect(session_id, _)
+            | SignalMessage::SessionLeave(session_id)
+            | SignalMessage::SdpOffer(session_id, _)
+            | SignalMessage::SdpAnswer(session_id, _)
+            | SignalMessage::IceCandidate(session_id, _)
+            | SignalMessage::Error(session_id, _)
+            | SignalMessage::SessionCreated(session_id)
+            | SignalMessage::Ping(session_id, _)
+            | SignalMessage::Pong(session_id, _)
+            | SignalMessage::Custom(session_id, _) => Some(*session_id),
+            SignalMessage::Hello(_)
+            | SignalMessage::Compressed(_)
+            | SignalMessage::SessionCreate => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn candidate_type_is_parsed_from_browser_candidates() {
+        let candidates = [
+            // Chrome, host address hidden behind an mDNS name
+            (
+                "candidate:3574136538 1 udp 2113937151 6a4b2f9c-1d1e-4e2b-9a3c-0f6d2c8a7b11.local 54400 typ host generation 0 ufrag EsAw network-cost 999",
+                Some(CandidateType::Host),
+            ),
+            (
+                "candidate:842163049 1 udp 1677729535 203.0.113.7 54400 typ srflx raddr 0.0.0.0 rport 0 generation 0 ufrag EsAw network-cost 999",
+                Some(CandidateType::ServerReflexive),
+            ),
+            (
+                "candidate:1532124476 1 udp 33562623 198.51.100.20 61234 typ relay raddr 203.0.113.7 rport 54400 generation 0 ufrag EsAw network-cost 999",
+                Some(CandidateType::Relay),
+            ),
+            // Firefox
+            (
+                "candidate:4 1 UDP 1686052863 203.0.113.7 49203 typ prflx raddr 192.168.1.2 rport 49203",
+                Some(CandidateType::PeerReflexive),
+            ),
+            (
+                "candidate:1 1 TCP 2105458943 192.168.1.2 9 typ host tcptype active",
+                Some(CandidateType::Host),
+            ),
+            // end of candidates, and malformed lines
+            ("", None),
+            ("candidate:1 1 udp 2122260223 192.168.1.2 50000 typ", None),
+            ("candidate:1 1 udp 2122260223 192.168.1.2 50000 typ unknown", None),
+        ];
+        for (candidate, candidate_type) in candidates {
+            assert_eq!(
+                CandidateType::from_candidate(candidate),
+                candidate_type,
+                "{candidate}"
+            );
+        }
+    }
+    #[test]
+    fn messages_tell_their_session() {
+        let session_id = SessionId::new(698);
+        assert_eq!(
+            SignalMessage::SdpAnswer(session_id, String::new()).session_id(),
+            Some(session_id)
+        );
+        assert_eq!(
+            SignalMessage::SessionReady(session_id, true, UserId::new(1)).session_id(),
+            Some(session_id)
+        );
+        assert_eq!(
+            SignalMessage::Hello(Capabilities::default()).session_id(),
+            None
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn candidate_without_type_from_older_peer_is_accepted() {
+        let older = rmp_serde::to_vec(&(
+            "candidate:1 1 udp 1 192.168.1.2 50000 typ host",
+            None::<String>,
+            Some(0u16),
+        ))
+        .unwrap();
+        let candidate: IceCandidate = rmp_serde::from_slice(&older).unwrap();
+        assert_eq!(candidate.candidate_type, None);
+    }
 }
@@ -0,0 +1,34 @@
+/*!
+Hygiene of user provided text before it is put into a message.
+
+Rust strings are always valid UTF-8, so text is only cut to a maximum length.
+Lengths are counted in characters, as a text input's `maxlength` would, and text is only ever
+cut between two characters.
+
+SDP offers and answers are not cut, as a truncated description can't be applied;
+they are passed as generated by the browser.
+ */
+
+/// Characters kept of the explanation of a [crate::one_to_one::SignalMessage::Error].
+pub const MAX_ERROR_LEN: usize = 256;
+
+/// `text` cut to its first `max_len` characters.
+pub fn clamp_text(text: &str, max_len: usize) -> &str {
+    match text.char_indices().nth(max_len) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn text_is_cut_between_characters() {
+        assert_eq!(clamp_text("gg", 5), "gg");
+        assert_eq!(clamp_text("goal!", 4), "goal");
+        assert_eq!(clamp_text("héé", 2), "hé");
+        assert_eq!(clamp_text("anything", 0), "");
+    }
+}
@@ -0,0 +1,98 @@
+//! Measures how long the server takes to forward a message in each of many sessions at once,
+//! as when many games set up their connections together.
+//!
+//! Run with `cargo bench -p wasm-peers-signaling-server`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use warp::ws::Message;
+use wasm_peers_protocol::one_to_one::SignalMessage;
+use wasm_peers_protocol::{SessionId, UserId};
+use wasm_peers_signaling_server::one_to_one::{
+    user_message, Connection, Connections, Namespace, SessionIds, Sessions,
+};
+
+fn signal(message: &SignalMessage) -> Message {
+    Message::binary(rmp_serde::to_vec(message).unwrap())
+}
+
+/// Sessions of two users each, the receivers of the second users of the sessions.
+async fn ready_sessions(
+    count: u64,
+    connections: &Connections,
+    sessions: &Sessions,
+) -> Vec<mpsc::UnboundedReceiver<Message>> {
+    let mut receivers = Vec::new();
+    for session in 0..count {
+        let session_id = SessionId::new(session as u128);
+        for user_id in [UserId::new(2 * session), UserId::new(2 * session + 1)] {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let connection = Connection {
+                sender,
+                compression: false,
+            };
+            connections.write().await.insert(user_id, connection);
+            user_message(
+                user_id,
+                &Namespace::default(),
+                SessionIds::default(),
+                signal(&SignalMessage::SessionJoin(session_id)),
+                connections,
+                sessions,
+            )
+            .await;
+            receivers.push(receiver);
+        }
+    }
+    for receiver in &mut receivers {
+        receiver.try_recv().expect("session was not ready");
+    }
+    receivers.into_iter().skip(1).step_by(2).collect()
+}
+
+fn forwarding_benchmark(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("forward a ping in every session");
+    for count in [10, 100, 1000] {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let mut receivers = runtime.block_on(ready_sessions(count, &connections, &sessions));
+        let (connections, sessions) = (Arc::new(connections), Arc::new(sessions));
+
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, &count| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    let tasks: Vec<_> = (0..count)
+                        .map(|session| {
+                            let (connections, sessions) = (connections.clone(), sessions.clone());
+                            tokio::spawn(async move {
+                                let ping = SignalMessage::Ping(SessionId::new(session as u128), 0);
+                                user_message(
+                                    UserId::new(2 * session),
+                                    &Namespace::default(),
+                                    SessionIds::default(),
+                                    signal(&ping),
+                                    &connections,
+                                    &sessions,
+                                )
+                                .await;
+                            })
+                        })
+                        .collect();
+                    for task in tasks {
+                        task.await.unwrap();
+                    }
+                });
+                for receiver in &mut receivers {
+                    receiver.try_recv().expect("ping was not forwarded");
+                }
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, forwarding_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,164 @@
+//! Load test of a running signaling server, without browsers: simulates many sessions of two peers,
+//! which join their session then exchange dummy SDP offers, answers and ICE candidates,
+//! and reports the throughput and the latency of each offer/answer round trip.
+//!
+//! Run with `cargo run --release -p wasm-peers-signaling-server --example load_test -- [URL] [SESSIONS] [ROUNDS]`,
+//! the URL defaulting to `ws://127.0.0.1:9003/one-to-one`, with 100 sessions of 10 rounds.
+
+use futures_util::{SinkExt, StreamExt};
+use std::env;
+use std::error::Error;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use wasm_peers_protocol::one_to_one::{IceCandidate, SignalMessage};
+use wasm_peers_protocol::SessionId;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Signaling messages sent by both peers during each round.
+const MESSAGES_PER_ROUND: usize = 3;
+
+/// Stands in for an SDP, about as long as the offer of a single data channel.
+const DUMMY_SDP: &str = concat!(
+    "v=0\r\no=- 4611731400430051336 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n",
+    "a=group:BUNDLE 0\r\nm=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n",
+    "c=IN IP4 0.0.0.0\r\na=ice-ufrag:yqHk\r\na=ice-pwd:wV2kdD0mPh3jRZ6ytI3nzL4G\r\n",
+    "a=fingerprint:sha-256 7B:8B:F0:65:5F:78:E2:51:3B:AC:6F:F3:3F:46:1B:35:DC:B8:5F:64:1A:24:C2:43:F0:A1:58:D0:A1:2C:19:08\r\n",
+    "a=setup:actpass\r\na=mid:0\r\na=sctp-port:5000\r\na=max-message-size:262144\r\n",
+);
+
+/// Websocket of a simulated peer.
+struct Peer(WebSocketStream<MaybeTlsStream<TcpStream>>);
+
+impl Peer {
+    async fn connect(url: &str) -> Result<Peer> {
+        let (websocket, _) = connect_async(url).await?;
+        Ok(Peer(websocket))
+    }
+
+    async fn send(&mut self, message: SignalMessage) -> Result<()> {
+        let message = rmp_serde::to_vec(&message)?;
+        self.0.send(Message::Binary(message)).await?;
+        Ok(())
+    }
+
+    /// Next signaling message, failing if it isn't the one `expected` accepts.
+    async fn receive(&mut self, expected: fn(&SignalMessage) -> bool) -> Result<SignalMessage> {
+        loop {
+            let message = match self.0.next().await {
+                Some(message) => message?,
+                None => return Err("the server closed the websocket".into()),
+            };
+            if let Message::Binary(message) = message {
+                let message = rmp_serde::from_slice(&message)?;
+                if !expected(&message) {
+                    return Err(format!("unexpected message: {message:?}").into());
+                }
+                return Ok(message);
+            }
+        }
+    }
+}
+
+/// Round trip time of each offer answered through the server.
+async fn run_session(url: &str, session_id: SessionId, rounds: usize) -> Result<Vec<Duration>> {
+    let mut first = Peer::connect(url).await?;
+    let mut second = Peer::connect(url).await?;
+    first.send(SignalMessage::SessionJoin(session_id)).await?;
+    second.send(SignalMessage::SessionJoin(session_id)).await?;
+    let ready = |message: &SignalMessage| matches!(message, SignalMessage::SessionReady(..));
+    first.receive(ready).await?;
+    second.receive(ready).await?;
+
+    let mut round_trips = Vec::with_capacity(rounds);
+    for round in 0..rounds {
+        let start = Instant::now();
+        let offer = SignalMessage::SdpOffer(session_id, DUMMY_SDP.to_string());
+        first.send(offer).await?;
+        second
+            .receive(|message| matches!(message, SignalMessage::SdpOffer(..)))
+            .await?;
+        // the server drops candidates it already forwarded, so each round gathers another one
+        let candidate = IceCandidate {
+            candidate: format!("candidate:{round} 1 udp 2122260223 192.168.1.2 50000 typ host"),
+            sdp_mid: Some("0".to_string()),
+            sdp_m_line_index: Some(0),
+            candidate_type: None,
+        };
+        second
+            .send(SignalMessage::IceCandidate(session_id, candidate))
+            .await?;
+        let answer = SignalMessage::SdpAnswer(session_id, DUMMY_SDP.to_string());
+        second.send(answer).await?;
+        first
+            .receive(|message| matches!(message, SignalMessage::IceCandidate(..)))
+            .await?;
+        first
+            .receive(|message| matches!(message, SignalMessage::SdpAnswer(..)))
+            .await?;
+        round_trips.push(start.elapsed());
+    }
+    Ok(round_trips)
+}
+
+/// Latency below which `fraction` of the sorted `latencies` are.
+fn percentile(latencies: &[Duration], fraction: f64) -> Duration {
+    let index = ((latencies.len() - 1) as f64 * fraction).round() as usize;
+    latencies[index]
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = env::args().skip(1);
+    let url = args
+        .next()
+        .unwrap_or_else(|| "ws://127.0.0.1:9003/one-to-one".to_string());
+    let sessions: usize = args
+        .next()
+        .map_or(100, |arg| arg.parse().expect("SESSIONS"));
+    let rounds: usize = args.next().map_or(10, |arg| arg.parse().expect("ROUNDS"));
+    println!("{sessions} sessions of {rounds} rounds against {url}");
+
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..sessions)
+        .map(|_| {
+            let url = url.clone();
+            let session_id = SessionId::new(rand::random());
+            tokio::spawn(async move { run_session(&url, session_id, rounds).await })
+        })
+        .collect();
+    let mut round_trips = Vec::new();
+    let mut failures = 0;
+    for task in tasks {
+        match task.await.expect("session task panicked") {
+            Ok(session_round_trips) => round_trips.extend(session_round_trips),
+            Err(error) => {
+                eprintln!("session failed: {error}");
+                failures += 1;
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+
+    let messages = round_trips.len() * MESSAGES_PER_ROUND;
+    println!(
+        "{} sessions succeeded, {failures} failed, in {elapsed:.2?}",
+        sessions - failures
+    );
+    println!(
+        "{messages} messages forwarded, {:.0} per second",
+        messages as f64 / elapsed.as_secs_f64()
+    );
+    if round_trips.is_empty() {
+        return;
+    }
+    round_trips.sort();
+    println!(
+        "offer/answer round trip: p50 {:.2?}, p99 {:.2?}, max {:.2?}",
+        percentile(&round_trips, 0.5),
+        percentile(&round_trips, 0.99),
+        round_trips[round_trips.len() - 1]
+    );
+}
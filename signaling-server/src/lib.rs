@@ -1 +1,115 @@
 pub mod one_to_one;
+#[cfg(feature = "stun-server")]
+pub mod stun_server;
+pub mod turn_credentials;
+
+use one_to_one::Namespace;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::reply::WithStatus;
+use warp::Filter;
+
+/// Filter matching the path under which the signaling endpoints are mounted,
+/// for example `/game/signaling` when the server sits behind a reverse proxy.
+/// An empty `base_path` mounts the endpoints at the root.
+pub fn base_path(base_path: &str) -> BoxedFilter<()> {
+    base_path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .fold(warp::any().boxed(), |filter, segment| {
+            filter.and(warp::path(segment.to_string())).boxed()
+        })
+}
+
+/// Filter extracting the optional application segment following an endpoint,
+/// `/one-to-one/football` joins sessions of the `football` namespace, `/one-to-one` the default one.
+pub fn namespace() -> BoxedFilter<(Namespace,)> {
+    let named = warp::path::param::<String>()
+        .and(warp::path::end())
+        .map(Namespace);
+    let unnamed = warp::path::end().map(Namespace::default);
+    named.or(unnamed).unify().boxed()
+}
+
+/// Probes for load balancers and container orchestration, served at the root:
+/// `GET /healthz` answers as long as the server runs, `GET /readyz` only while
+/// `relay_ready` is set, by the relay once the STUN/TURN sockets are bound and until it fails.
+///
+/// Neither touches the sessions, so they stay cheap under load.
+pub fn health(relay_ready: Arc<AtomicBool>) -> BoxedFilter<(WithStatus<&'static str>,)> {
+    let healthz = warp::path!("healthz")
+        .and(warp::get())
+        .map(|| warp::reply::with_status("ok", StatusCode::OK));
+    let readyz = warp::path!("readyz").and(warp::get()).map(move || {
+        if relay_ready.load(Ordering::Relaxed) {
+            warp::reply::with_status("ready", StatusCode::OK)
+        } else {
+            warp::reply::with_status("relay not bound", StatusCode::SERVICE_UNAVAILABLE)
+        }
+    });
+    healthz.or(readyz).unify().boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn base_path_prefixes_endpoints() {
+        let filter = base_path("/game/signaling/").and(warp::path("one-to-one"));
+        assert!(
+            warp::test::request()
+                .path("/game/signaling/one-to-one")
+                .matches(&filter)
+                .await
+        );
+        assert!(
+            !warp::test::request()
+                .path("/one-to-one")
+                .matches(&filter)
+                .await
+        );
+
+        let filter = base_path("").and(warp::path("one-to-one"));
+        assert!(
+            warp::test::request()
+                .path("/one-to-one")
+                .matches(&filter)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn readyz_waits_for_the_relay() {
+        let relay_ready = Arc::new(AtomicBool::new(false));
+        let filter = health(relay_ready.clone());
+
+        let reply = warp::test::request().path("/healthz").reply(&filter).await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        let reply = warp::test::request().path("/readyz").reply(&filter).await;
+        assert_eq!(reply.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        relay_ready.store(true, Ordering::Relaxed);
+        let reply = warp::test::request().path("/readyz").reply(&filter).await;
+        assert_eq!(reply.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn namespace_is_the_segment_after_the_endpoint() {
+        let filter = warp::path("one-to-one").and(namespace());
+        let namespace = warp::test::request()
+            .path("/one-to-one/football")
+            .filter(&filter)
+            .await
+            .unwrap();
+        assert_eq!(namespace, Namespace("football".to_string()));
+        let namespace = warp::test::request()
+            .path("/one-to-one")
+            .filter(&filter)
+            .await
+            .unwrap();
+        assert_eq!(namespace, Namespace::default());
+    }
+}
@@ -1,28 +1,48 @@
 use log::LevelFilter;
 use simplelog::{Config, TermLogger, TerminalMode};
-use std::{env, std::net::SocketAddr, std::str::FromStr, sync::Arc, time::Duration};
-use tokio::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{env, net::SocketAddr, str::FromStr, sync::Arc};
 use warp::Filter;
 
 use wasm_peers_protocol::{STUN_PORT, TURN_PORT, WS_PORT};
 use wasm_peers_signaling_server::one_to_one;
-
-use turn::{
-    auth::{self, AuthHandler},
-    relay::relay_static::{self, RelayAddressGeneratorStatic},
-    server::{self, config},
+#[cfg(feature = "stun-server")]
+use {log::error, tokio::net::UdpSocket, wasm_peers_signaling_server::stun_server};
+#[cfg(feature = "stun-turn")]
+use {
+    log::info,
+    std::{collections::HashMap, net::IpAddr, path::PathBuf, sync::RwLock, time::Duration},
+    tokio::signal::unix::{signal, SignalKind},
+    turn::{
+        auth::{self, AuthHandler},
+        relay::relay_static::RelayAddressGeneratorStatic,
+        server::{
+            config::{ConnConfig, ServerConfig},
+            Server,
+        },
+        Error,
+    },
+    wasm_peers_signaling_server::turn_credentials::TurnCredentials,
+    webrtc_util::vnet::net::Net,
 };
 
+/// Authentication keys of the TURN users, replaced when the credentials file is reloaded.
+#[cfg(feature = "stun-turn")]
+type CredMap = Arc<RwLock<HashMap<String, Vec<u8>>>>;
+
+#[cfg(feature = "stun-turn")]
 struct MyAuthHandler {
-    cred_map: HashMap<String, Vec<u8>>,
+    cred_map: CredMap,
 }
 
+#[cfg(feature = "stun-turn")]
 impl MyAuthHandler {
-    fn new(cred_map: HashMap<String, Vec<u8>>) -> Self {
+    fn new(cred_map: CredMap) -> Self {
         MyAuthHandler { cred_map }
     }
 }
 
+#[cfg(feature = "stun-turn")]
 impl AuthHandler for MyAuthHandler {
     fn auth_handle(
         &self,
@@ -30,7 +50,7 @@ impl AuthHandler for MyAuthHandler {
         _realm: &str,
         _src_addr: SocketAddr,
     ) -> Result<Vec<u8>, Error> {
-        if let Some(pw) = self.cred_map.get(username) {
+        if let Some(pw) = self.cred_map.read().unwrap().get(username) {
             //log::debug!("username={}, password={:?}", username, pw);
             Ok(pw.to_vec())
         } else {
@@ -38,6 +58,8 @@ impl AuthHandler for MyAuthHandler {
         }
     }
 }
+/// Whether `addr` would take the port of the websocket, or of the STUN or TURN sockets,
+/// which all listen on distinct ports.
 fn port_overlap(addr: &SocketAddr) -> bool {
     [STUN_PORT, TURN_PORT, WS_PORT].contains(&addr.port())
 }
@@ -51,14 +73,30 @@ async fn main() {
     let sessions = one_to_one::Sessions::default();
     let sessions = warp::any().map(move || sessions.clone());
 
-    let signaling_channel = warp::path("one-to-one")
+    // set SIGNALING_ASSIGN_SESSION_IDS so that sessions can only be opened with
+    // `SignalMessage::SessionCreate`, and joined by whoever was handed the random id
+    let session_ids = if env::var_os("SIGNALING_ASSIGN_SESSION_IDS").is_some() {
+        one_to_one::SessionIds::AssignedByServer
+    } else {
+        one_to_one::SessionIds::ChosenByClients
+    };
+
+    let base_path = env::var("SIGNALING_BASE_PATH").unwrap_or_default();
+    let signaling_channel = wasm_peers_signaling_server::base_path(&base_path)
+        .and(warp::path("one-to-one"))
+        .and(wasm_peers_signaling_server::namespace())
         .and(warp::ws())
         .and(connections)
         .and(sessions)
-        .map(|ws: warp::ws::Ws, connections, sessions| {
-            ws.on_upgrade(move |socket| one_to_one::user_connected(socket, connections, sessions))
+        .map(move |namespace, ws: warp::ws::Ws, connections, sessions| {
+            ws.on_upgrade(move |socket| {
+                one_to_one::user_connected(socket, namespace, session_ids, connections, sessions)
+            })
         });
 
+    let relay_ready = Arc::new(AtomicBool::new(false));
+    let health = wasm_peers_signaling_server::health(relay_ready.clone());
+
     let fallback = || "127.0.0.1:9000".to_string();
     let address = env::args().nth(1).unwrap_or_else(fallback);
     let address = SocketAddr::from_str(&address).expect("invalid IP address provided");
@@ -66,20 +104,161 @@ async fn main() {
         panic!("{address}'s port overlap with one of the protocol's predefined port, the port for the default server that serves the static wasm and html files should be distinct from the rest.");
     }
 
-    let conn = Arc::new(UdpSocket::bind(format!(":{TURN_PORT}")).await?);
-    println!("listening {}...", conn.local_addr()?);
-    tokio::spawn(Server::new(ServerConfig {
+    // set SIGNALING_DISABLE_RELAY when clients use external STUN/TURN servers,
+    // with `ConnectionType::External`, so that signaling and relaying scale separately
+    let relay_enabled =
+        cfg!(feature = "stun-server") && env::var_os("SIGNALING_DISABLE_RELAY").is_none();
+    if relay_enabled {
+        // read before serving, so that the server refuses to start with a malformed configuration
+        #[cfg(feature = "stun-turn")]
+        tokio::spawn(run_relay(relay_ready, TurnSettings::from_env()));
+        #[cfg(all(feature = "stun-server", not(feature = "stun-turn")))]
+        tokio::spawn(run_relay(relay_ready));
+    } else {
+        println!("running without the embedded STUN/TURN relay");
+        relay_ready.store(true, Ordering::Relaxed);
+    }
+    warp::serve(health.or(signaling_channel)).run(address).await;
+}
+
+/// Bind the STUN socket, and the TURN one with `stun-turn`, and serve STUN requests.
+/// `relay_ready` is only set while all of them are bound and serving.
+#[cfg(feature = "stun-server")]
+async fn run_relay(
+    relay_ready: Arc<AtomicBool>,
+    #[cfg(feature = "stun-turn")] turn_settings: TurnSettings,
+) {
+    let stun_socket = match UdpSocket::bind(format!("0.0.0.0:{STUN_PORT}")).await {
+        Ok(socket) => socket,
+        Err(error) => {
+            error!("failed to bind the STUN socket: {error}");
+            return;
+        }
+    };
+    if let Ok(address) = stun_socket.local_addr() {
+        println!("STUN listening {address}...");
+    }
+    #[cfg(feature = "stun-turn")]
+    let turn_server = match start_turn(turn_settings).await {
+        Ok(server) => server,
+        Err(error) => {
+            error!("failed to start the TURN server: {error}");
+            return;
+        }
+    };
+
+    relay_ready.store(true, Ordering::Relaxed);
+    if let Err(error) = stun_server::serve(stun_socket).await {
+        error!("the STUN server stopped: {error}");
+    }
+    relay_ready.store(false, Ordering::Relaxed);
+    #[cfg(feature = "stun-turn")]
+    if let Err(error) = turn_server.close().await {
+        error!("failed to close the TURN server: {error}");
+    }
+}
+
+#[cfg(feature = "stun-turn")]
+fn auth_keys(credentials: &TurnCredentials) -> HashMap<String, Vec<u8>> {
+    credentials
+        .users
+        .iter()
+        .map(|(username, password)| {
+            let key = auth::generate_auth_key(username, &credentials.realm, password);
+            (username.clone(), key)
+        })
+        .collect()
+}
+
+/// Load the credentials file again on SIGHUP, so that operators can rotate them without a restart.
+/// The previous credentials are kept if the file became invalid.
+#[cfg(feature = "stun-turn")]
+async fn reload_on_hangup(path: PathBuf, realm: String, cred_map: CredMap) {
+    let mut hangups = signal(SignalKind::hangup()).expect("failed to listen for SIGHUP");
+    while hangups.recv().await.is_some() {
+        match TurnCredentials::load(&path) {
+            Ok(credentials) if credentials.realm != realm => {
+                error!("the TURN realm can't change without a restart, keeping the previous credentials");
+            }
+            Ok(credentials) => {
+                *cred_map.write().unwrap() = auth_keys(&credentials);
+                info!("reloaded {} TURN users", credentials.users.len());
+            }
+            Err(error) => {
+                error!(
+                    "keeping the previous TURN credentials, {}: {error}",
+                    path.display()
+                );
+            }
+        }
+    }
+}
+
+/// Configuration of the TURN relay, read from the environment.
+#[cfg(feature = "stun-turn")]
+struct TurnSettings {
+    credentials_path: Option<PathBuf>,
+    credentials: TurnCredentials,
+    /// Address at which clients reach the relayed connections
+    relay_address: IpAddr,
+}
+
+#[cfg(feature = "stun-turn")]
+impl TurnSettings {
+    fn from_env() -> Self {
+        // set SIGNALING_TURN_CREDENTIALS to the JSON file listing the realm and users of the relay
+        let credentials_path = env::var_os("SIGNALING_TURN_CREDENTIALS").map(PathBuf::from);
+        let credentials = match &credentials_path {
+            Some(path) => TurnCredentials::load(path)
+                .unwrap_or_else(|error| panic!("TURN credentials in {}: {error}", path.display())),
+            None => TurnCredentials::default(),
+        };
+        // set SIGNALING_PUBLIC_IP to the public address of the server, the loopback one by default
+        let relay_address = match env::var("SIGNALING_PUBLIC_IP") {
+            Ok(public_ip) => IpAddr::from_str(&public_ip)
+                .unwrap_or_else(|error| panic!("SIGNALING_PUBLIC_IP {public_ip:?}: {error}")),
+            Err(_) => IpAddr::from([127, 0, 0, 1]),
+        };
+        TurnSettings {
+            credentials_path,
+            credentials,
+            relay_address,
+        }
+    }
+}
+
+/// Bind the TURN socket and relay the traffic of the allocations made on it.
+#[cfg(feature = "stun-turn")]
+async fn start_turn(settings: TurnSettings) -> Result<Server, Box<dyn std::error::Error>> {
+    let TurnSettings {
+        credentials_path,
+        credentials,
+        relay_address,
+    } = settings;
+    let cred_map = Arc::new(RwLock::new(auth_keys(&credentials)));
+    if let Some(path) = credentials_path {
+        tokio::spawn(reload_on_hangup(
+            path,
+            credentials.realm.clone(),
+            cred_map.clone(),
+        ));
+    }
+
+    let conn = Arc::new(UdpSocket::bind(format!("0.0.0.0:{TURN_PORT}")).await?);
+    println!("TURN listening {}...", conn.local_addr()?);
+    let server = Server::new(ServerConfig {
         conn_configs: vec![ConnConfig {
             conn,
             relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
-                relay_address: IpAddr::from_str(public_ip)?,
+                relay_address,
                 address: "0.0.0.0".to_owned(),
                 net: Arc::new(Net::new(None)),
             }),
         }],
-        realm: "".to_owned(),
+        realm: credentials.realm,
         auth_handler: Arc::new(MyAuthHandler::new(cred_map)),
         channel_bind_timeout: Duration::from_secs(0),
-    }));
-    warp::serve(signaling_channel).run(address).await;
+    })
+    .await?;
+    Ok(server)
 }
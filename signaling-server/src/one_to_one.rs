@@ -1,48 +1,249 @@
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use futures_util::{SinkExt, StreamExt, TryFutureExt};
-use log::{error, info, warn};
+use futures_util::{Sink, SinkExt, StreamExt};
+use log::{debug, error, info, warn};
 use tokio::sync::{mpsc, RwLock};
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use warp::ws::{Message, WebSocket};
 
-use wasm_peers_protocol::one_to_one::SignalMessage;
+use wasm_peers_protocol::one_to_one::{Capabilities, SignalMessage};
 use wasm_peers_protocol::{SessionId, UserId};
 
+/// Application a session belongs to, so that several apps can share one server
+/// without their session ids colliding. Empty for clients connecting without one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Namespace(pub String);
+
+/// Who picks the [SessionId] of new sessions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionIds {
+    /// Joining an unknown id creates the session, so anyone guessing an id can squat it.
+    #[default]
+    ChosenByClients,
+    /// Sessions are only created through [SignalMessage::SessionCreate], under a random id,
+    /// and joining an unknown id is refused.
+    AssignedByServer,
+}
+
+/// How many ICE candidates forwarded in a session are remembered to drop duplicates.
+const CANDIDATE_HISTORY: usize = 32;
+
+/// Error sent to a user joining a session both users are in, before closing its connection.
+const SESSION_FULL: &str = "session is full";
+
+/// Time a session opened with [SignalMessage::SessionCreate] is kept once both users disconnected,
+/// for them to reconnect into it, as its id can't be opened again by joining.
+const RELEASED_SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
 pub struct Session {
+    /// User which offers the connection: the one which created the session or joined it first,
+    /// until another user joins while it is gone, and the user still in the session takes over
+    pub owner: UserId,
     pub first: Option<UserId>,
     pub second: Option<UserId>,
+    /// Users which disconnected from the session, latest last, and can take their slot back
+    /// with [SignalMessage::SessionReconnect]
+    pub left: Vec<UserId>,
+    /// Whether the session was forgotten and opened again by a [SignalMessage::SessionReconnect],
+    /// so that the other user, whose previous id is unknown, can reconnect into the free slot
+    pub reopened: bool,
+    /// Whether the session was opened with [SignalMessage::SessionCreate], it is then kept
+    /// for [RELEASED_SESSION_TTL] once both users disconnected
+    pub assigned: bool,
+    /// When both users disconnected from an assigned session
+    pub released_at: Option<Instant>,
     pub offer_received: bool,
+    /// Latest ICE candidates forwarded, with their sender and media line index
+    pub forwarded_candidates: VecDeque<(UserId, String, Option<u16>)>,
 }
 
-pub type Connections = Arc<RwLock<HashMap<UserId, mpsc::UnboundedSender<Message>>>>;
-pub type Sessions = Arc<RwLock<HashMap<SessionId, Session>>>;
+impl Session {
+    fn new(first: UserId) -> Self {
+        Session {
+            owner: first,
+            first: Some(first),
+            second: None,
+            left: Vec::new(),
+            reopened: false,
+            assigned: false,
+            released_at: None,
+            offer_received: false,
+            forwarded_candidates: VecDeque::new(),
+        }
+    }
+
+    fn contains(&self, user_id: UserId) -> bool {
+        self.first == Some(user_id) || self.second == Some(user_id)
+    }
+
+    /// Put `user_id` in the free slot, returning the user already in the session.
+    fn join(&mut self, user_id: UserId) -> Result<Option<UserId>, &'static str> {
+        if self.contains(user_id) {
+            // sent again by a user still waiting for the other one
+            return Ok(None);
+        }
+        let other = match (self.first, self.second) {
+            (None, other) => {
+                self.first = Some(user_id);
+                other
+            }
+            (first, None) => {
+                self.second = Some(user_id);
+                first
+            }
+            (Some(_), Some(_)) => return Err(SESSION_FULL),
+        };
+        if !self.contains(self.owner) {
+            // nobody would offer the connection otherwise
+            self.owner = other.unwrap_or(user_id);
+        }
+        self.released_at = None;
+        if other.is_some() {
+            // no slot is left, the users which left can't come back
+            self.left.clear();
+            self.reopened = false;
+        }
+        Ok(other)
+    }
+
+    /// Free the slot of `user_id`, which disconnected and can reconnect into it.
+    fn release(&mut self, user_id: UserId) {
+        if self.first == Some(user_id) {
+            self.first = None;
+        } else if self.second == Some(user_id) {
+            self.second = None;
+        } else {
+            return;
+        }
+        if self.left.len() == 2 {
+            self.left.remove(0);
+        }
+        self.left.push(user_id);
+    }
+
+    /// Whether the session should still be kept at `now`, as a user is in it or may reconnect into it.
+    fn is_kept(&mut self, now: Instant) -> bool {
+        if self.first.is_some() || self.second.is_some() {
+            return true;
+        }
+        if !self.assigned {
+            // it is opened again by joining or reconnecting to its id
+            return false;
+        }
+        let released_at = *self.released_at.get_or_insert(now);
+        now.duration_since(released_at) < RELEASED_SESSION_TTL
+    }
+
+    /// Free the slot of `user_id`, leaving on purpose so it can't reconnect into it.
+    /// Returns whether the session is left empty.
+    fn leave(&mut self, user_id: UserId) -> bool {
+        if self.first == Some(user_id) {
+            self.first = None;
+        } else if self.second == Some(user_id) {
+            self.second = None;
+        }
+        self.first.is_none() && self.second.is_none()
+    }
+
+    /// The user of the session other than `user_id`, `None` if it did not join yet or left.
+    fn other_user(&self, user_id: UserId) -> Option<UserId> {
+        if self.first == Some(user_id) {
+            self.second
+        } else {
+            self.first
+        }
+    }
+
+    /// Returns `false` if `sender` already sent this candidate, which then shouldn't be forwarded again.
+    fn remember_candidate(
+        &mut self,
+        sender: UserId,
+        candidate: &str,
+        sdp_m_line_index: Option<u16>,
+    ) -> bool {
+        let already_forwarded = self.forwarded_candidates.iter().any(|(user, c, index)| {
+            *user == sender && c == candidate && *index == sdp_m_line_index
+        });
+        if already_forwarded {
+            return false;
+        }
+        if self.forwarded_candidates.len() == CANDIDATE_HISTORY {
+            self.forwarded_candidates.pop_front();
+        }
+        self.forwarded_candidates
+            .push_back((sender, candidate.to_string(), sdp_m_line_index));
+        true
+    }
+}
+
+pub struct Connection {
+    pub sender: mpsc::UnboundedSender<Message>,
+    /// Whether the user announced support for [SignalMessage::Compressed] in its [SignalMessage::Hello]
+    pub compression: bool,
+}
+
+impl Connection {
+    fn send(&self, message: SignalMessage) {
+        #[cfg(feature = "compression")]
+        let message = if self.compression {
+            message.compress()
+        } else {
+            message
+        };
+        let message = rmp_serde::to_vec(&message).unwrap();
+        if self.sender.send(Message::binary(message)).is_err() {
+            // the user is being disconnected, see [forward_messages]
+            warn!("dropping a message to a user whose websocket failed");
+        }
+    }
+
+    /// Close the websocket once the messages sent before are flushed.
+    fn close(&self) {
+        let _ = self.sender.send(Message::close());
+    }
+}
+
+pub type Connections = Arc<RwLock<HashMap<UserId, Connection>>>;
+pub type Sessions = Arc<RwLock<HashMap<(Namespace, SessionId), Session>>>;
 
 static NEXT_USER_ID: AtomicU64 = AtomicU64::new(1);
 
-pub async fn user_connected(ws: WebSocket, connections: Connections, sessions: Sessions) {
+pub async fn user_connected(
+    ws: WebSocket,
+    namespace: Namespace,
+    session_ids: SessionIds,
+    connections: Connections,
+    sessions: Sessions,
+) {
     let user_id = UserId::new(NEXT_USER_ID.fetch_add(1, Ordering::Relaxed));
-    info!("new user connected: {:?}", user_id);
+    info!("new user connected: {:?} in {:?}", user_id, namespace);
 
-    let (mut user_ws_tx, mut user_ws_rx) = ws.split();
+    let (user_ws_tx, mut user_ws_rx) = ws.split();
 
     let (tx, rx) = mpsc::unbounded_channel();
-    let mut rx = UnboundedReceiverStream::new(rx);
-
-    tokio::task::spawn(async move {
-        while let Some(message) = rx.next().await {
-            user_ws_tx
-                .send(message)
-                .unwrap_or_else(|e| eprintln!("websocket send error: {}", e))
-                .await;
-        }
-    });
-    connections.write().await.insert(user_id, tx);
+    let mut forwarding = tokio::task::spawn(forward_messages(rx, user_ws_tx));
+    connections.write().await.insert(
+        user_id,
+        Connection {
+            sender: tx,
+            compression: false,
+        },
+    );
 
-    while let Some(result) = user_ws_rx.next().await {
+    loop {
+        let result = tokio::select! {
+            result = user_ws_rx.next() => match result {
+                Some(result) => result,
+                None => break,
+            },
+            // the websocket can't be written to anymore, or was closed by the server
+            _ = &mut forwarding => break,
+        };
         let msg = match result {
             Ok(msg) => msg,
             Err(e) => {
@@ -50,112 +251,941 @@ pub async fn user_connected(ws: WebSocket, connections: Connections, sessions: S
                 break;
             }
         };
-        user_message(user_id, msg, &connections, &sessions).await;
+        user_message(
+            user_id,
+            &namespace,
+            session_ids,
+            msg,
+            &connections,
+            &sessions,
+        )
+        .await;
     }
     eprintln!("user disconnected: {:?}", user_id);
     user_disconnected(user_id, &connections, &sessions).await;
 }
 
-async fn user_message(
+/// Send the messages queued for a user on its websocket, until it is closed.
+/// Returns as soon as sending fails, for the user to be disconnected right away rather than
+/// once reading from the websocket fails too, which other users would keep sending to meanwhile.
+async fn forward_messages<S>(rx: mpsc::UnboundedReceiver<Message>, mut user_ws_tx: S)
+where
+    S: Sink<Message> + Unpin,
+    S::Error: Display,
+{
+    let mut rx = UnboundedReceiverStream::new(rx);
+    while let Some(message) = rx.next().await {
+        let close = message.is_close();
+        if let Err(error) = user_ws_tx.send(message).await {
+            eprintln!("websocket send error: {}", error);
+            break;
+        }
+        if close {
+            break;
+        }
+    }
+}
+
+/// Handle a message `msg` received from the websocket of `user_id`.
+///
+/// Locks are only held while looking up or updating the state, never while sending to other users.
+pub async fn user_message(
     user_id: UserId,
+    namespace: &Namespace,
+    session_ids: SessionIds,
     msg: Message,
     connections: &Connections,
     sessions: &Sessions,
 ) {
-    use SignalMessage::{IceCandidate, SdpAnswer, SdpOffer};
+    use SignalMessage::{Custom, IceCandidate, Ping, Pong, SdpAnswer, SdpOffer};
     let request = match rmp_serde::from_slice::<SignalMessage>(msg.as_bytes()) {
-        Ok(request) => {
-            info!("message received from user {:?}: {:?}", user_id, request);
-            request
-        }
+        Ok(request) => request,
         Err(error) => {
             error!("An error occurred: {:?}", error);
             return;
         }
     };
+    #[cfg(feature = "compression")]
+    let request = match request.decompress() {
+        Ok(request) => request,
+        Err(error) => {
+            error!(
+                "failed to decompress message from user {:?}: {:?}",
+                user_id, error
+            );
+            return;
+        }
+    };
+    info!("message received from user {:?}: {:?}", user_id, request);
     match &request {
+        SignalMessage::Hello(capabilities) => {
+            let supported = Capabilities {
+                compression: cfg!(feature = "compression"),
+            };
+            let mut connections_writer = connections.write().await;
+            if let Some(connection) = connections_writer.get_mut(&user_id) {
+                connection.compression = capabilities.compression && supported.compression;
+                connection.send(SignalMessage::Hello(supported));
+            }
+        }
+        SignalMessage::SessionCreate => {
+            let session_id = {
+                let mut sessions_writer = sessions.write().await;
+                // 128 random bits, colliding with an existing session is as unlikely as guessing one
+                let session_id = loop {
+                    let session_id = SessionId::new(rand::random());
+                    if !sessions_writer.contains_key(&(namespace.clone(), session_id)) {
+                        break session_id;
+                    }
+                };
+                let session = Session {
+                    assigned: true,
+                    ..Session::new(user_id)
+                };
+                sessions_writer.insert((namespace.clone(), session_id), session);
+                session_id
+            };
+            if let Some(connection) = connections.read().await.get(&user_id) {
+                connection.send(SignalMessage::SessionCreated(session_id));
+            }
+        }
         SignalMessage::SessionJoin(session_id) => {
-            match sessions.write().await.entry(*session_id) {
+            // the user already waiting in the session and the owner, `Err` if it can't be joined
+            let joined = match sessions
+                .write()
+                .await
+                .entry((namespace.clone(), *session_id))
+            {
+                Entry::Vacant(_) if session_ids == SessionIds::AssignedByServer => {
+                    Err("no such session")
+                }
                 // on first user in session - create session object and store connecting user id
                 Entry::Vacant(entry) => {
-                    entry.insert(Session {
-                        first: Some(user_id),
-                        second: None,
-                        offer_received: false,
-                    });
+                    entry.insert(Session::new(user_id));
+                    Ok(None)
                 }
                 // on second user - add him to existing session and notify users that session is ready
                 Entry::Occupied(mut entry) => {
-                    entry.get_mut().second = Some(user_id);
-                    let first_response = SignalMessage::SessionReady(*session_id);
-                    let second_response = SignalMessage::SessionReady(*session_id);
-                    let first_response = rmp_serde::to_vec(&first_response).unwrap();
-                    let second_response = rmp_serde::to_vec(&second_response).unwrap();
-
-                    let connections_reader = connections.read().await;
-                    if let Some(first_id) = &entry.get().first {
-                        let first_tx = connections_reader.get(first_id).unwrap();
-                        first_tx.send(Message::binary(first_response)).unwrap();
-                        let second_tx = connections_reader.get(&user_id).unwrap();
-                        second_tx.send(Message::binary(second_response)).unwrap();
+                    let session = entry.get_mut();
+                    // the owner may change when joining
+                    let joined = session.join(user_id);
+                    joined.map(|other| other.map(|other| (other, session.owner)))
+                }
+            };
+            session_joined(user_id, *session_id, joined, connections).await;
+        }
+        SignalMessage::SessionReconnect(session_id, previous_id) => {
+            let joined = match sessions
+                .write()
+                .await
+                .entry((namespace.clone(), *session_id))
+            {
+                Entry::Vacant(_) if session_ids == SessionIds::AssignedByServer => {
+                    Err("no such session")
+                }
+                // both users left, the first one back opens the session again for the other one
+                Entry::Vacant(entry) => {
+                    entry.insert(Session {
+                        reopened: true,
+                        ..Session::new(user_id)
+                    });
+                    Ok(None)
+                }
+                Entry::Occupied(entry) if entry.get().contains(user_id) => Ok(None),
+                Entry::Occupied(mut entry)
+                    if entry.get().left.contains(previous_id) || entry.get().reopened =>
+                {
+                    info!("user {previous_id:?} reconnected to {session_id:?} as {user_id:?}");
+                    let session = entry.get_mut();
+                    session.left.retain(|left| left != previous_id);
+                    if session.owner == *previous_id {
+                        session.owner = user_id;
                     }
+                    let joined = session.join(user_id);
+                    joined.map(|other| other.map(|other| (other, session.owner)))
+                }
+                Entry::Occupied(_) => Err("no slot to reconnect to in this session"),
+            };
+            session_joined(user_id, *session_id, joined, connections).await;
+        }
+        SignalMessage::SessionLeave(session_id) => {
+            let mut sessions_writer = sessions.write().await;
+            let key = (namespace.clone(), *session_id);
+            if let Some(session) = sessions_writer.get_mut(&key) {
+                if session.leave(user_id) {
+                    sessions_writer.remove(&key);
                 }
             }
         }
         // pass offer and answer to the other user in session without changing anything
-        message @ (SdpOffer(id, _) | SdpAnswer(id, _) | IceCandidate(id, _)) => {
-            let mut lock = sessions.write().await;
-            let session = match lock.get_mut(id) {
-                Some(session) => session,
-                None => {
-                    error!("No such session: {id:?}");
-                    return;
+        message @ (SdpOffer(id, _)
+        | SdpAnswer(id, _)
+        | IceCandidate(id, _)
+        | Ping(id, _)
+        | Pong(id, _)
+        | Custom(id, _)) => {
+            let recipient = if matches!(message, Ping(..) | Pong(..) | Custom(..)) {
+                // not part of the negotiation, the session is left as is
+                let sessions_reader = sessions.read().await;
+                match sessions_reader.get(&(namespace.clone(), *id)) {
+                    Some(session) => session.other_user(user_id),
+                    None => {
+                        error!("No such session: {id:?}");
+                        return;
+                    }
                 }
-            };
-            if session.offer_received {
-                warn!("offer already sent by the the peer, ignoring the 2nd offer: {id:?}");
             } else {
-                session.offer_received = true;
-            }
-
-            let recipient = if session.first.is_some() {
-                session.second
-            } else {
-                session.first
+                let mut sessions_writer = sessions.write().await;
+                let session = match sessions_writer.get_mut(&(namespace.clone(), *id)) {
+                    Some(session) => session,
+                    None => {
+                        error!("No such session: {id:?}");
+                        return;
+                    }
+                };
+                if let IceCandidate(_, candidate) = message {
+                    let candidate_line = &candidate.candidate;
+                    if !session.remember_candidate(
+                        user_id,
+                        candidate_line,
+                        candidate.sdp_m_line_index,
+                    ) {
+                        debug!(
+                            "dropping duplicate ICE candidate from user {user_id:?}: {candidate_line}"
+                        );
+                        return;
+                    }
+                }
+                if session.offer_received {
+                    warn!("offer already sent by the the peer, ignoring the 2nd offer: {id:?}");
+                } else {
+                    session.offer_received = true;
+                }
+                session.other_user(user_id)
             };
+            // the session lock is released, sending only holds the connections for reading
+            let connections_reader = connections.read().await;
             match recipient {
-                Some(recipient_id) => {
-                    let response = message;
-                    let response = rmp_serde::to_vec(&response).unwrap();
-                    let connections_reader = connections.read().await;
-                    let recipient_tx = connections_reader.get(&recipient_id).unwrap();
-
-                    recipient_tx.send(Message::binary(response)).unwrap();
-                }
+                Some(recipient_id) => match connections_reader.get(&recipient_id) {
+                    Some(recipient_connection) => recipient_connection.send(message.clone()),
+                    // disconnected since the session was looked up
+                    None => warn!(
+                        "user {recipient_id:?} of session {id:?} is gone, dropping {message:?}"
+                    ),
+                },
                 None => {
                     error!("Missing second user in session: {:?}", &id);
+                    // let the sender know its message went nowhere, so it can stop waiting
+                    if let Some(sender_connection) = connections_reader.get(&user_id) {
+                        let response = SignalMessage::error(*id, "peer not present");
+                        sender_connection.send(response);
+                    }
+                }
+            }
+        }
+        SignalMessage::SessionReady(..)
+        | SignalMessage::SessionCreated(_)
+        | SignalMessage::Error(..) => {}
+        SignalMessage::Compressed(_) => {
+            error!("compressed message from user {user_id:?} that did not negotiate compression");
+        }
+        unknown => {
+            error!("ignoring unknown message from user {user_id:?}: {unknown:?}");
+        }
+    }
+}
+
+/// Tell `user_id` why it could not join `session_id`, or tell both users the session is ready
+/// once `joined` with the user already there and the owner.
+async fn session_joined(
+    user_id: UserId,
+    session_id: SessionId,
+    joined: Result<Option<(UserId, UserId)>, &'static str>,
+    connections: &Connections,
+) {
+    let connections_reader = connections.read().await;
+    match joined {
+        Err(error) => {
+            warn!("user {user_id:?} could not join session {session_id:?}: {error}");
+            if let Some(connection) = connections_reader.get(&user_id) {
+                connection.send(SignalMessage::error(session_id, error));
+                // nothing left for it to do, a flood of rejected users would keep sockets open
+                if error == SESSION_FULL {
+                    connection.close();
+                }
+            }
+        }
+        Ok(None) => {}
+        Ok(Some((other_id, owner))) => {
+            for recipient in [other_id, user_id] {
+                match connections_reader.get(&recipient) {
+                    Some(connection) => connection.send(SignalMessage::SessionReady(
+                        session_id,
+                        recipient == owner,
+                        recipient,
+                    )),
+                    // disconnected since joining, its own disconnection frees its slot
+                    None => warn!("user {recipient:?} of session {session_id:?} is gone, not telling it the session is ready"),
                 }
             }
         }
-        SignalMessage::SessionReady(_) | SignalMessage::Error(..) => {}
     }
 }
 
+/// Forget `user_id`, along with the sessions it leaves empty and the released ones that expired.
 async fn user_disconnected(user_id: UserId, connections: &Connections, sessions: &Sessions) {
-    let mut session_to_delete = None;
-    for (session_id, session) in sessions.write().await.iter_mut() {
-        if session.first == Some(user_id) {
-            session.first = None;
-        } else if session.second == Some(user_id) {
-            session.second = None;
+    let now = Instant::now();
+    sessions.write().await.retain(|_, session| {
+        session.release(user_id);
+        session.is_kept(now)
+    });
+    connections.write().await.remove(&user_id);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn connect(
+        connections: &mut HashMap<UserId, Connection>,
+        user_id: UserId,
+    ) -> mpsc::UnboundedReceiver<Message> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        connections.insert(
+            user_id,
+            Connection {
+                sender,
+                compression: false,
+            },
+        );
+        receiver
+    }
+
+    fn signal(message: &SignalMessage) -> Message {
+        Message::binary(rmp_serde::to_vec(message).unwrap())
+    }
+
+    #[tokio::test]
+    async fn message_to_absent_peer_is_answered_with_error() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let session_id = SessionId::new(1234);
+        let user_id = UserId::new(1);
+        let mut receiver = connect(&mut *connections.write().await, user_id);
+
+        user_message(
+            user_id,
+            &Namespace::default(),
+            SessionIds::default(),
+            signal(&SignalMessage::SessionJoin(session_id)),
+            &connections,
+            &sessions,
+        )
+        .await;
+        user_message(
+            user_id,
+            &Namespace::default(),
+            SessionIds::default(),
+            signal(&SignalMessage::SdpOffer(session_id, "offer".to_string())),
+            &connections,
+            &sessions,
+        )
+        .await;
+
+        let response = receiver.try_recv().expect("sender got no response");
+        match rmp_serde::from_slice(response.as_bytes()).unwrap() {
+            SignalMessage::Error(id, _) => assert_eq!(id, session_id),
+            other => panic!("expected an error, got {other:?}"),
         }
-        if session.first == None && session.second == None {
-            session_to_delete = Some(*session_id);
+    }
+
+    #[tokio::test]
+    async fn sessions_are_scoped_by_namespace() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let session_id = SessionId::new(1234);
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_receiver = connect(&mut *connections.write().await, first);
+        let mut second_receiver = connect(&mut *connections.write().await, second);
+
+        let join = signal(&SignalMessage::SessionJoin(session_id));
+        let football = Namespace("football".to_string());
+        let chess = Namespace("chess".to_string());
+        user_message(
+            first,
+            &football,
+            SessionIds::default(),
+            join.clone(),
+            &connections,
+            &sessions,
+        )
+        .await;
+        user_message(
+            second,
+            &chess,
+            SessionIds::default(),
+            join,
+            &connections,
+            &sessions,
+        )
+        .await;
+
+        assert!(first_receiver.try_recv().is_err());
+        assert!(second_receiver.try_recv().is_err());
+        assert_eq!(sessions.read().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn duplicate_ice_candidates_are_forwarded_once() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let session_id = SessionId::new(1234);
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let _first_receiver = connect(&mut *connections.write().await, first);
+        let mut second_receiver = connect(&mut *connections.write().await, second);
+        let join = signal(&SignalMessage::SessionJoin(session_id));
+        user_message(
+            first,
+            &Namespace::default(),
+            SessionIds::default(),
+            join.clone(),
+            &connections,
+            &sessions,
+        )
+        .await;
+        user_message(
+            second,
+            &Namespace::default(),
+            SessionIds::default(),
+            join,
+            &connections,
+            &sessions,
+        )
+        .await;
+        second_receiver.try_recv().expect("session was not ready");
+
+        let candidate = signal(&SignalMessage::IceCandidate(
+            session_id,
+            wasm_peers_protocol::one_to_one::IceCandidate {
+                candidate: "candidate:1 1 udp 2122260223 192.168.1.2 50000 typ host".to_string(),
+                sdp_mid: Some("0".to_string()),
+                sdp_m_line_index: Some(0),
+                candidate_type: Some(wasm_peers_protocol::one_to_one::CandidateType::Host),
+            },
+        ));
+        for _ in 0..3 {
+            let message = candidate.clone();
+            user_message(
+                first,
+                &Namespace::default(),
+                SessionIds::default(),
+                message,
+                &connections,
+                &sessions,
+            )
+            .await;
         }
+
+        assert!(second_receiver.try_recv().is_ok());
+        assert!(second_receiver.try_recv().is_err());
     }
-    // remove session if it's empty
-    if let Some(session_id) = session_to_delete {
-        sessions.write().await.remove(&session_id);
+
+    #[tokio::test]
+    async fn ping_is_forwarded_to_the_other_peer() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let session_id = SessionId::new(1234);
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let _first_receiver = connect(&mut *connections.write().await, first);
+        let mut second_receiver = connect(&mut *connections.write().await, second);
+        let namespace = Namespace::default();
+        let send = |user_id, message| {
+            user_message(
+                user_id,
+                &namespace,
+                SessionIds::default(),
+                signal(&message),
+                &connections,
+                &sessions,
+            )
+        };
+        send(first, SignalMessage::SessionJoin(session_id)).await;
+        send(second, SignalMessage::SessionJoin(session_id)).await;
+        second_receiver.try_recv().expect("session was not ready");
+
+        send(first, SignalMessage::Ping(session_id, 42)).await;
+        let message = second_receiver.try_recv().expect("ping was not forwarded");
+        assert!(matches!(
+            rmp_serde::from_slice(message.as_bytes()).unwrap(),
+            SignalMessage::Ping(id, 42) if id == session_id
+        ));
+
+        send(first, SignalMessage::Custom(session_id, b"lobby".to_vec())).await;
+        let message = second_receiver
+            .try_recv()
+            .expect("payload was not forwarded");
+        assert!(matches!(
+            rmp_serde::from_slice(message.as_bytes()).unwrap(),
+            SignalMessage::Custom(id, payload) if id == session_id && payload == b"lobby"
+        ));
+    }
+
+    #[tokio::test]
+    async fn assigned_session_is_only_joined_with_its_id() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (host, guest) = (UserId::new(1), UserId::new(2));
+        let mut host_receiver = connect(&mut *connections.write().await, host);
+        let mut guest_receiver = connect(&mut *connections.write().await, guest);
+        let receive = |receiver: &mut mpsc::UnboundedReceiver<Message>| {
+            let message = receiver.try_recv().expect("no message received");
+            rmp_serde::from_slice::<SignalMessage>(message.as_bytes()).unwrap()
+        };
+        let namespace = Namespace::default();
+        let send = |user_id, message| {
+            user_message(
+                user_id,
+                &namespace,
+                SessionIds::AssignedByServer,
+                signal(&message),
+                &connections,
+                &sessions,
+            )
+        };
+
+        send(host, SignalMessage::SessionCreate).await;
+        let session_id = match receive(&mut host_receiver) {
+            SignalMessage::SessionCreated(session_id) => session_id,
+            other => panic!("expected a session id, got {other:?}"),
+        };
+        // the network manager of the host joins the session it created on the same websocket
+        send(host, SignalMessage::SessionJoin(session_id)).await;
+        assert!(host_receiver.try_recv().is_err());
+        let guessed_id = SessionId::new(session_id.get().wrapping_add(1));
+        send(guest, SignalMessage::SessionJoin(guessed_id)).await;
+        assert!(matches!(
+            receive(&mut guest_receiver),
+            SignalMessage::Error(id, _) if id == guessed_id
+        ));
+        assert_eq!(sessions.read().await.len(), 1);
+
+        send(guest, SignalMessage::SessionJoin(session_id)).await;
+        assert!(matches!(
+            receive(&mut host_receiver),
+            SignalMessage::SessionReady(id, true, user) if id == session_id && user == host
+        ));
+        assert!(matches!(
+            receive(&mut guest_receiver),
+            SignalMessage::SessionReady(id, false, user) if id == session_id && user == guest
+        ));
+    }
+
+    #[tokio::test]
+    async fn repeated_join_keeps_waiting_for_the_other_user() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let session_id = SessionId::new(1234);
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let mut first_receiver = connect(&mut *connections.write().await, first);
+        let _second_receiver = connect(&mut *connections.write().await, second);
+        let namespace = Namespace::default();
+        let join = |user_id| {
+            user_message(
+                user_id,
+                &namespace,
+                SessionIds::default(),
+                signal(&SignalMessage::SessionJoin(session_id)),
+                &connections,
+                &sessions,
+            )
+        };
+        join(first).await;
+        join(first).await;
+        assert!(first_receiver.try_recv().is_err());
+
+        join(second).await;
+        let message = first_receiver.try_recv().expect("session was not ready");
+        assert!(matches!(
+            rmp_serde::from_slice(message.as_bytes()).unwrap(),
+            SignalMessage::SessionReady(id, true, user) if id == session_id && user == first
+        ));
+    }
+
+    #[tokio::test]
+    async fn user_reconnects_into_the_slot_it_left() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let session_id = SessionId::new(1234);
+        let (host, guest, rejoined, intruder) = (
+            UserId::new(1),
+            UserId::new(2),
+            UserId::new(3),
+            UserId::new(4),
+        );
+        let mut host_receiver = connect(&mut *connections.write().await, host);
+        let _guest_receiver = connect(&mut *connections.write().await, guest);
+        let receive = |receiver: &mut mpsc::UnboundedReceiver<Message>| {
+            let message = receiver.try_recv().expect("no message received");
+            rmp_serde::from_slice::<SignalMessage>(message.as_bytes()).unwrap()
+        };
+        let namespace = Namespace::default();
+        let send = |user_id, message| {
+            user_message(
+                user_id,
+                &namespace,
+                SessionIds::default(),
+                signal(&message),
+                &connections,
+                &sessions,
+            )
+        };
+        send(host, SignalMessage::SessionJoin(session_id)).await;
+        send(guest, SignalMessage::SessionJoin(session_id)).await;
+        receive(&mut host_receiver);
+
+        user_disconnected(host, &connections, &sessions).await;
+        let mut intruder_receiver = connect(&mut *connections.write().await, intruder);
+        send(intruder, SignalMessage::SessionReconnect(session_id, guest)).await;
+        assert!(matches!(
+            receive(&mut intruder_receiver),
+            SignalMessage::Error(id, _) if id == session_id
+        ));
+
+        let mut rejoined_receiver = connect(&mut *connections.write().await, rejoined);
+        send(rejoined, SignalMessage::SessionReconnect(session_id, host)).await;
+        assert!(matches!(
+            receive(&mut rejoined_receiver),
+            SignalMessage::SessionReady(id, true, user) if id == session_id && user == rejoined
+        ));
+
+        send(intruder, SignalMessage::SessionJoin(session_id)).await;
+        assert!(matches!(
+            receive(&mut intruder_receiver),
+            SignalMessage::Error(id, _) if id == session_id
+        ));
+    }
+
+    #[tokio::test]
+    async fn message_to_a_peer_disconnecting_meanwhile_is_dropped() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let session_id = SessionId::new(1234);
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let _first_receiver = connect(&mut *connections.write().await, first);
+        let _second_receiver = connect(&mut *connections.write().await, second);
+        let namespace = Namespace::default();
+        let send = |user_id, message| {
+            user_message(
+                user_id,
+                &namespace,
+                SessionIds::default(),
+                signal(&message),
+                &connections,
+                &sessions,
+            )
+        };
+        send(first, SignalMessage::SessionJoin(session_id)).await;
+        send(second, SignalMessage::SessionJoin(session_id)).await;
+
+        // its connection is removed before its sessions are updated
+        connections.write().await.remove(&second);
+        send(
+            first,
+            SignalMessage::SdpOffer(session_id, "offer".to_string()),
+        )
+        .await;
+        send(first, SignalMessage::Ping(session_id, 1)).await;
+    }
+
+    #[tokio::test]
+    async fn remaining_user_takes_over_a_session_its_owner_left() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let session_id = SessionId::new(1234);
+        let (host, guest, newcomer) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        let mut host_receiver = connect(&mut *connections.write().await, host);
+        let mut guest_receiver = connect(&mut *connections.write().await, guest);
+        let mut newcomer_receiver = connect(&mut *connections.write().await, newcomer);
+        let namespace = Namespace::default();
+        let join = |user_id| {
+            user_message(
+                user_id,
+                &namespace,
+                SessionIds::default(),
+                signal(&SignalMessage::SessionJoin(session_id)),
+                &connections,
+                &sessions,
+            )
+        };
+        join(host).await;
+        join(guest).await;
+        host_receiver.try_recv().unwrap();
+        guest_receiver.try_recv().unwrap();
+
+        user_disconnected(host, &connections, &sessions).await;
+        join(newcomer).await;
+        let owners: Vec<_> = [
+            (guest, &mut guest_receiver),
+            (newcomer, &mut newcomer_receiver),
+        ]
+        .into_iter()
+        .map(|(user_id, receiver)| {
+            let message = receiver.try_recv().expect("session was not ready");
+            match rmp_serde::from_slice(message.as_bytes()).unwrap() {
+                SignalMessage::SessionReady(id, owner, user) => {
+                    assert_eq!((id, user), (session_id, user_id));
+                    owner
+                }
+                other => panic!("unexpected {other:?}"),
+            }
+        })
+        .collect();
+        assert_eq!(owners, [true, false]);
+    }
+
+    #[tokio::test]
+    async fn both_users_reconnect_after_disconnecting() {
+        for session_ids in [SessionIds::ChosenByClients, SessionIds::AssignedByServer] {
+            let connections = Connections::default();
+            let sessions = Sessions::default();
+            let (host, guest, host_back, guest_back) = (
+                UserId::new(1),
+                UserId::new(2),
+                UserId::new(3),
+                UserId::new(4),
+            );
+            let mut host_receiver = connect(&mut *connections.write().await, host);
+            let _guest_receiver = connect(&mut *connections.write().await, guest);
+            let receive = |receiver: &mut mpsc::UnboundedReceiver<Message>| {
+                let message = receiver.try_recv().expect("no message received");
+                rmp_serde::from_slice::<SignalMessage>(message.as_bytes()).unwrap()
+            };
+            let namespace = Namespace::default();
+            let send = |user_id, message| {
+                user_message(
+                    user_id,
+                    &namespace,
+                    session_ids,
+                    signal(&message),
+                    &connections,
+                    &sessions,
+                )
+            };
+            send(host, SignalMessage::SessionCreate).await;
+            let session_id = match receive(&mut host_receiver) {
+                SignalMessage::SessionCreated(session_id) => session_id,
+                other => panic!("expected a session id, got {other:?}"),
+            };
+            send(guest, SignalMessage::SessionJoin(session_id)).await;
+            user_disconnected(host, &connections, &sessions).await;
+            user_disconnected(guest, &connections, &sessions).await;
+            if session_ids == SessionIds::ChosenByClients {
+                // forgotten, and opened again by the first user reconnecting
+                sessions.write().await.clear();
+            }
+
+            let mut host_back_receiver = connect(&mut *connections.write().await, host_back);
+            let mut guest_back_receiver = connect(&mut *connections.write().await, guest_back);
+            send(host_back, SignalMessage::SessionReconnect(session_id, host)).await;
+            send(
+                guest_back,
+                SignalMessage::SessionReconnect(session_id, guest),
+            )
+            .await;
+            assert!(matches!(
+                receive(&mut host_back_receiver),
+                SignalMessage::SessionReady(id, true, user) if id == session_id && user == host_back
+            ));
+            assert!(matches!(
+                receive(&mut guest_back_receiver),
+                SignalMessage::SessionReady(id, false, user) if id == session_id && user == guest_back
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn released_assigned_sessions_expire() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let (host, other) = (UserId::new(1), UserId::new(2));
+        let mut host_receiver = connect(&mut *connections.write().await, host);
+        let _other_receiver = connect(&mut *connections.write().await, other);
+        user_message(
+            host,
+            &Namespace::default(),
+            SessionIds::AssignedByServer,
+            signal(&SignalMessage::SessionCreate),
+            &connections,
+            &sessions,
+        )
+        .await;
+        host_receiver.try_recv().unwrap();
+        user_disconnected(host, &connections, &sessions).await;
+        assert_eq!(sessions.read().await.len(), 1);
+
+        for session in sessions.write().await.values_mut() {
+            session.released_at = Instant::now().checked_sub(RELEASED_SESSION_TTL);
+        }
+        user_disconnected(other, &connections, &sessions).await;
+        assert!(sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn third_user_is_rejected_and_disconnected() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let session_id = SessionId::new(1234);
+        let (first, second, third) = (UserId::new(1), UserId::new(2), UserId::new(3));
+        let mut first_receiver = connect(&mut *connections.write().await, first);
+        let mut second_receiver = connect(&mut *connections.write().await, second);
+        let mut third_receiver = connect(&mut *connections.write().await, third);
+        let namespace = Namespace::default();
+        let join = |user_id| {
+            user_message(
+                user_id,
+                &namespace,
+                SessionIds::default(),
+                signal(&SignalMessage::SessionJoin(session_id)),
+                &connections,
+                &sessions,
+            )
+        };
+        join(first).await;
+        join(second).await;
+        join(third).await;
+
+        let message = third_receiver
+            .try_recv()
+            .expect("third user got no response");
+        assert!(matches!(
+            rmp_serde::from_slice(message.as_bytes()).unwrap(),
+            SignalMessage::Error(id, error) if id == session_id && error == SESSION_FULL
+        ));
+        assert!(third_receiver.try_recv().unwrap().is_close());
+        for receiver in [&mut first_receiver, &mut second_receiver] {
+            assert!(!receiver.try_recv().unwrap().is_close());
+            assert!(receiver.try_recv().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn waiting_user_leaves_its_session() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let session_id = SessionId::new(1234);
+        let (host, guest) = (UserId::new(1), UserId::new(2));
+        let _host_receiver = connect(&mut *connections.write().await, host);
+        let mut guest_receiver = connect(&mut *connections.write().await, guest);
+        let namespace = Namespace::default();
+        let send = |user_id, message| {
+            user_message(
+                user_id,
+                &namespace,
+                SessionIds::default(),
+                signal(&message),
+                &connections,
+                &sessions,
+            )
+        };
+        send(host, SignalMessage::SessionJoin(session_id)).await;
+        send(host, SignalMessage::SessionLeave(session_id)).await;
+        assert!(sessions.read().await.is_empty());
+
+        send(guest, SignalMessage::SessionJoin(session_id)).await;
+        assert!(guest_receiver.try_recv().is_err());
+        assert_eq!(sessions.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn forwarding_stops_once_the_websocket_fails() {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let broken = Box::pin(futures_util::sink::unfold((), |(), _: Message| async {
+            Err("broken pipe")
+        }));
+        sender.send(Message::binary(vec![1])).unwrap();
+        sender.send(Message::binary(vec![2])).unwrap();
+
+        // returns instead of waiting for more messages
+        forward_messages(receiver, broken).await;
+
+        let connection = Connection {
+            sender,
+            compression: false,
+        };
+        connection.send(SignalMessage::SessionCreate);
+        connection.close();
+    }
+
+    #[tokio::test]
+    async fn sessions_are_removed_once_both_peers_disconnect() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let session_id = SessionId::new(1234);
+        let (first, second) = (UserId::new(1), UserId::new(2));
+        let _first_receiver = connect(&mut *connections.write().await, first);
+        let _second_receiver = connect(&mut *connections.write().await, second);
+        let namespace = Namespace::default();
+        let join = |user_id| {
+            user_message(
+                user_id,
+                &namespace,
+                SessionIds::default(),
+                signal(&SignalMessage::SessionJoin(session_id)),
+                &connections,
+                &sessions,
+            )
+        };
+        join(first).await;
+        join(second).await;
+
+        user_disconnected(first, &connections, &sessions).await;
+        assert_eq!(sessions.read().await.len(), 1);
+        user_disconnected(second, &connections, &sessions).await;
+        assert!(sessions.read().await.is_empty());
+        assert!(connections.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn maps_do_not_grow_with_churn() {
+        let connections = Connections::default();
+        let sessions = Sessions::default();
+        let namespace = Namespace::default();
+        // a session that stays open the whole time, alongside the churning ones
+        let (host, guest) = (UserId::new(0), UserId::new(1));
+        let _host_receiver = connect(&mut *connections.write().await, host);
+        let _guest_receiver = connect(&mut *connections.write().await, guest);
+        let join = |user_id, session_id| {
+            user_message(
+                user_id,
+                &namespace,
+                SessionIds::default(),
+                signal(&SignalMessage::SessionJoin(session_id)),
+                &connections,
+                &sessions,
+            )
+        };
+        join(host, SessionId::new(0)).await;
+        join(guest, SessionId::new(0)).await;
+
+        for cycle in 1..=500 {
+            let session_id = SessionId::new(cycle % 7 + 1);
+            let first = UserId::new(2 * cycle as u64);
+            let second = UserId::new(2 * cycle as u64 + 1);
+            let _first_receiver = connect(&mut *connections.write().await, first);
+            let _second_receiver = connect(&mut *connections.write().await, second);
+            join(first, session_id).await;
+            join(second, session_id).await;
+            // peers leave in either order
+            let (leaving_first, leaving_second) = if cycle % 2 == 0 {
+                (first, second)
+            } else {
+                (second, first)
+            };
+            user_disconnected(leaving_first, &connections, &sessions).await;
+            user_disconnected(leaving_second, &connections, &sessions).await;
+
+            assert_eq!(sessions.read().await.len(), 1);
+            assert_eq!(connections.read().await.len(), 2);
+        }
     }
-    connections.write().await.remove(&user_id);
 }
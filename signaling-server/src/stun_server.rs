@@ -0,0 +1,85 @@
+/*!
+Minimal STUN server, answering binding requests with the public address they came from,
+so that clients can use your own server instead of a third party one for `stun:` urls.
+ */
+
+use std::io;
+use std::net::SocketAddr;
+
+use log::debug;
+use stun::message::{is_message, Message, Setter, BINDING_REQUEST, BINDING_SUCCESS};
+use stun::xoraddr::XorMappedAddress;
+use tokio::net::UdpSocket;
+
+/// Answer the STUN binding requests received on `socket`, until receiving from it fails.
+pub async fn serve(socket: UdpSocket) -> io::Result<()> {
+    let mut buffer = [0; 1500];
+    loop {
+        let (length, source) = socket.recv_from(&mut buffer).await?;
+        match binding_response(&buffer[..length], source) {
+            Some(response) => {
+                if let Err(error) = socket.send_to(&response, source).await {
+                    debug!("failed to answer STUN binding request from {source}: {error}");
+                }
+            }
+            None => debug!("ignoring datagram from {source} that is not a STUN binding request"),
+        }
+    }
+}
+
+/// Binding success response for `request`, telling `source` its address as seen by the server.
+fn binding_response(request: &[u8], source: SocketAddr) -> Option<Vec<u8>> {
+    if !is_message(request) {
+        return None;
+    }
+    let mut request_message = Message::new();
+    request_message.unmarshal_binary(request).ok()?;
+    if request_message.typ != BINDING_REQUEST {
+        return None;
+    }
+    let setters: [Box<dyn Setter>; 3] = [
+        // copies the transaction id of the request
+        Box::new(request_message),
+        Box::new(BINDING_SUCCESS),
+        Box::new(XorMappedAddress {
+            ip: source.ip(),
+            port: source.port(),
+        }),
+    ];
+    let mut response = Message::new();
+    response.build(&setters).ok()?;
+    Some(response.raw)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stun::agent::TransactionId;
+    use stun::message::Getter;
+
+    #[tokio::test]
+    async fn binding_request_is_answered_with_the_client_address() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_address = server.local_addr().unwrap();
+        tokio::spawn(serve(server));
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let mut request = Message::new();
+        request
+            .build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])
+            .unwrap();
+        client.send_to(&request.raw, server_address).await.unwrap();
+
+        let mut buffer = [0; 1500];
+        let (length, _) = client.recv_from(&mut buffer).await.unwrap();
+        let mut response = Message::new();
+        response.unmarshal_binary(&buffer[..length]).unwrap();
+        assert_eq!(response.typ, BINDING_SUCCESS);
+        assert_eq!(response.transaction_id, request.transaction_id);
+        let mut mapped_address = XorMappedAddress::default();
+        mapped_address.get_from(&response).unwrap();
+        let client_address = client.local_addr().unwrap();
+        assert_eq!(mapped_address.ip, client_address.ip());
+        assert_eq!(mapped_address.port, client_address.port());
+    }
+}
@@ -0,0 +1,101 @@
+/*!
+Users allowed on the embedded TURN relay, loaded from a JSON file:
+
+```json
+{
+    "realm": "example.org",
+    "users": { "alice": "correct horse battery staple" }
+}
+```
+ */
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use serde::Deserialize;
+
+/// Realm of the relay and password of each of its users.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TurnCredentials {
+    pub realm: String,
+    pub users: HashMap<String, String>,
+}
+
+/// Why a credentials file was refused.
+#[derive(Debug)]
+pub enum CredentialsError {
+    Io(std::io::Error),
+    Format(serde_json::Error),
+    /// The file is valid JSON, but an entry can't be used
+    Invalid(String),
+}
+
+impl Display for CredentialsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CredentialsError::Io(error) => write!(f, "failed to read the file: {error}"),
+            CredentialsError::Format(error) => write!(f, "malformed file: {error}"),
+            CredentialsError::Invalid(reason) => write!(f, "invalid entry: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for CredentialsError {}
+
+impl TurnCredentials {
+    pub fn load(path: &Path) -> Result<Self, CredentialsError> {
+        let json = std::fs::read_to_string(path).map_err(CredentialsError::Io)?;
+        TurnCredentials::parse(&json)
+    }
+
+    pub fn parse(json: &str) -> Result<Self, CredentialsError> {
+        let credentials: TurnCredentials =
+            serde_json::from_str(json).map_err(CredentialsError::Format)?;
+        credentials.validate()?;
+        Ok(credentials)
+    }
+
+    fn validate(&self) -> Result<(), CredentialsError> {
+        let invalid = |reason: String| Err(CredentialsError::Invalid(reason));
+        if self.realm.is_empty() {
+            return invalid("the realm is empty".to_string());
+        }
+        for (username, password) in &self.users {
+            // the long-term credential key is derived from `username:realm:password`
+            if username.is_empty() || username.contains(':') {
+                return invalid(format!("username {username:?} is empty or contains ':'"));
+            }
+            if password.is_empty() {
+                return invalid(format!("user {username:?} has an empty password"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn credentials_file_is_validated() {
+        let credentials =
+            TurnCredentials::parse(r#"{ "realm": "example.org", "users": { "alice": "secret" } }"#)
+                .unwrap();
+        assert_eq!(credentials.realm, "example.org");
+        assert_eq!(credentials.users["alice"], "secret");
+
+        let malformed = [
+            r#"{ "realm": "example.org", "users": { "alice": "" } }"#,
+            r#"{ "realm": "example.org", "users": { "al:ice": "secret" } }"#,
+            r#"{ "realm": "", "users": {} }"#,
+            r#"{ "realm": "example.org", "users": {}, "port": 3478 }"#,
+            r#"{ "realm": "example.org", "users": ["alice"] }"#,
+        ];
+        for json in malformed {
+            assert!(TurnCredentials::parse(json).is_err(), "accepted {json}");
+        }
+    }
+}